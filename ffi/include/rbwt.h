@@ -0,0 +1,39 @@
+/* C interface to rust-bwt. Link against librbwt, built from the rust-bwt-ffi crate. */
+
+#ifndef RBWT_H
+#define RBWT_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Return codes, the same as the rust-bwt command's exit statuses */
+#define RBWT_OK 0
+#define RBWT_ERR_INTERNAL 1     /* something failed inside the library; a bug */
+#define RBWT_ERR_NULL_POINTER 2 /* a pointer that's required was null */
+#define RBWT_ERR_CORRUPT 3      /* the compressed input is truncated or corrupt */
+#define RBWT_ERR_CHECKSUM 4     /* a block failed its CRC-32 check */
+#define RBWT_ERR_NOT_A_STREAM 5 /* not a rust-bwt stream, or written by a newer version */
+#define RBWT_ERR_TOO_LARGE 6    /* the output would be too large */
+
+/*
+ * Compresses input_len bytes at input (which may be NULL if input_len is 0). On success, stores a
+ * newly allocated buffer in *out and its length in *out_len; release it with rbwt_free. On
+ * failure, *out is NULL.
+ */
+int rbwt_compress(const uint8_t *input, size_t input_len, uint8_t **out, size_t *out_len);
+
+/* Decompresses a stream written by rbwt_compress or the rust-bwt command, like rbwt_compress */
+int rbwt_decompress(const uint8_t *input, size_t input_len, uint8_t **out, size_t *out_len);
+
+/* Releases a buffer from rbwt_compress or rbwt_decompress, given the length stored with it */
+void rbwt_free(uint8_t *buf, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* RBWT_H */
@@ -0,0 +1,241 @@
+//! C bindings for [`rust_bwt`]: one-shot compression and decompression of a buffer in memory.
+//! Declared in `include/rbwt.h`.
+//!
+//! Every function returns [`RBWT_OK`] or an `RBWT_ERR_*` code. The codes match the exit statuses of
+//! the `rust-bwt` command. A buffer handed back through `out` belongs to the caller, who releases
+//! it with [`rbwt_free`].
+
+use std::ffi::c_int;
+use std::panic;
+use std::ptr;
+use std::slice;
+
+use rust_bwt::BwtError;
+
+pub const RBWT_OK: c_int = 0;
+/// Something failed inside the library; a bug
+pub const RBWT_ERR_INTERNAL: c_int = 1;
+/// A pointer that's required was null
+pub const RBWT_ERR_NULL_POINTER: c_int = 2;
+/// The compressed input is truncated or corrupt
+pub const RBWT_ERR_CORRUPT: c_int = 3;
+/// A block failed its CRC-32 check
+pub const RBWT_ERR_CHECKSUM: c_int = 4;
+/// The input isn't a rust-bwt stream, or was written by a newer version
+pub const RBWT_ERR_NOT_A_STREAM: c_int = 5;
+/// The output would be too large
+pub const RBWT_ERR_TOO_LARGE: c_int = 6;
+
+/// Compresses `input_len` bytes at `input` like [`rust_bwt::compress_bytes`]. On success, the
+/// output is stored in `*out` and its length in `*out_len`.
+///
+/// # Safety
+///
+/// `input` must point to `input_len` readable bytes, or may be null if `input_len` is 0. `out`
+/// and `out_len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn rbwt_compress(
+    input: *const u8,
+    input_len: usize,
+    out: *mut *mut u8,
+    out_len: *mut usize,
+) -> c_int {
+    // SAFETY: passed on from the caller
+    unsafe {
+        call(input, input_len, out, out_len, |input| {
+            Ok(rust_bwt::compress_bytes(input))
+        })
+    }
+}
+
+/// Decompresses the stream of `input_len` bytes at `input` like [`rust_bwt::decompress_bytes`].
+/// On success, the output is stored in `*out` and its length in `*out_len`.
+///
+/// # Safety
+///
+/// As for [`rbwt_compress`].
+#[no_mangle]
+pub unsafe extern "C" fn rbwt_decompress(
+    input: *const u8,
+    input_len: usize,
+    out: *mut *mut u8,
+    out_len: *mut usize,
+) -> c_int {
+    // SAFETY: passed on from the caller
+    unsafe { call(input, input_len, out, out_len, rust_bwt::decompress_bytes) }
+}
+
+/// Releases a buffer returned by [`rbwt_compress`] or [`rbwt_decompress`]. Null is ignored.
+///
+/// # Safety
+///
+/// `buf` and `len` must be exactly what one of those functions stored in `*out` and `*out_len`,
+/// and the buffer mustn't be used or released again afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn rbwt_free(buf: *mut u8, len: usize) {
+    if !buf.is_null() {
+        // SAFETY: the buffer came from `Box::into_raw` in `call`, with this length
+        drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(buf, len)) });
+    }
+}
+
+/// Checks the pointers, runs `f` on the input, and hands its output to the caller. Panics are
+/// caught, since they mustn't unwind into C.
+unsafe fn call(
+    input: *const u8,
+    input_len: usize,
+    out: *mut *mut u8,
+    out_len: *mut usize,
+    f: impl FnOnce(&[u8]) -> Result<Vec<u8>, BwtError> + panic::UnwindSafe,
+) -> c_int {
+    if out.is_null() || out_len.is_null() || (input.is_null() && input_len > 0) {
+        return RBWT_ERR_NULL_POINTER;
+    }
+    // SAFETY: checked non-null above; the caller guarantees the rest
+    unsafe {
+        *out = ptr::null_mut();
+        *out_len = 0;
+    }
+    let input = match input_len {
+        0 => &[],
+        // SAFETY: the caller guarantees `input` points to `input_len` bytes
+        _ => unsafe { slice::from_raw_parts(input, input_len) },
+    };
+
+    match panic::catch_unwind(|| f(input)) {
+        Ok(Ok(output)) => {
+            let output = Box::into_raw(output.into_boxed_slice());
+            // SAFETY: as above
+            unsafe {
+                *out_len = output.len();
+                *out = output.cast();
+            }
+            RBWT_OK
+        }
+        Ok(Err(e)) => error_code(&e),
+        Err(_) => RBWT_ERR_INTERNAL,
+    }
+}
+
+fn error_code(e: &BwtError) -> c_int {
+    use BwtError::*;
+
+    match e {
+        // Only reading a range, which these bindings don't offer, can fail with the last two
+        Io(_) | NoIndex | RangeOutOfBounds { .. } => RBWT_ERR_INTERNAL,
+        TruncatedStream
+        | UnexpectedEof { .. }
+        | InvalidSentinelIndex
+        | CorruptStream(_)
+        | LengthMismatch { .. }
+        | InconsistentTransform { .. } => RBWT_ERR_CORRUPT,
+        ChecksumMismatch { .. } => RBWT_ERR_CHECKSUM,
+        BadMagic | UnsupportedVersion(_) | UnsupportedFlags(_) => RBWT_ERR_NOT_A_STREAM,
+        OutputTooLarge { .. } => RBWT_ERR_TOO_LARGE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Calls `f` like a C caller would, returning its code and a copy of the output
+    fn call_c(
+        f: unsafe extern "C" fn(*const u8, usize, *mut *mut u8, *mut usize) -> c_int,
+        input: &[u8],
+    ) -> (c_int, Vec<u8>) {
+        let mut out = ptr::null_mut();
+        let mut out_len = 0;
+        // SAFETY: `input` is a live slice and the out pointers are locals
+        let code = unsafe { f(input.as_ptr(), input.len(), &mut out, &mut out_len) };
+        if code != RBWT_OK {
+            assert!(out.is_null());
+            assert_eq!(out_len, 0);
+            return (code, Vec::new());
+        }
+
+        // SAFETY: on success, `out` holds `out_len` bytes, released here exactly once
+        let output = unsafe { slice::from_raw_parts(out, out_len) }.to_vec();
+        unsafe { rbwt_free(out, out_len) };
+        (code, output)
+    }
+
+    #[test]
+    fn round_trips() {
+        for input in [&b""[..], b"a", b"banana", &b"mississippi ".repeat(1000)] {
+            let (code, compressed) = call_c(rbwt_compress, input);
+            assert_eq!(code, RBWT_OK);
+            assert_eq!(
+                call_c(rbwt_decompress, &compressed),
+                (RBWT_OK, input.to_vec())
+            );
+        }
+    }
+
+    #[test]
+    fn reports_null_pointers() {
+        let mut out = ptr::null_mut();
+        let mut out_len = 0;
+        let input = b"banana";
+        // SAFETY: every pointer is either null or valid
+        unsafe {
+            for f in [rbwt_compress, rbwt_decompress] {
+                assert_eq!(
+                    f(ptr::null(), 1, &mut out, &mut out_len),
+                    RBWT_ERR_NULL_POINTER
+                );
+                assert_eq!(
+                    f(input.as_ptr(), input.len(), ptr::null_mut(), &mut out_len),
+                    RBWT_ERR_NULL_POINTER
+                );
+                assert_eq!(
+                    f(input.as_ptr(), input.len(), &mut out, ptr::null_mut()),
+                    RBWT_ERR_NULL_POINTER
+                );
+            }
+
+            // Null is fine for an empty input, and for rbwt_free
+            assert_eq!(
+                rbwt_compress(ptr::null(), 0, &mut out, &mut out_len),
+                RBWT_OK
+            );
+            rbwt_free(out, out_len);
+            rbwt_free(ptr::null_mut(), 0);
+        }
+    }
+
+    #[test]
+    fn reports_bad_input() {
+        let (_, compressed) = call_c(rbwt_compress, &b"banana bandana ".repeat(100));
+
+        // Cut short anywhere, the stream is truncated
+        for len in [0, 1, 8, compressed.len() / 2, compressed.len() - 1] {
+            let (code, _) = call_c(rbwt_decompress, &compressed[..len]);
+            assert!(
+                [RBWT_ERR_CORRUPT, RBWT_ERR_NOT_A_STREAM].contains(&code),
+                "{} bytes: {}",
+                len,
+                code
+            );
+        }
+        assert_eq!(
+            call_c(rbwt_decompress, &compressed[..compressed.len() - 1]).0,
+            RBWT_ERR_CORRUPT
+        );
+
+        assert_eq!(
+            call_c(rbwt_decompress, b"not a stream at all").0,
+            RBWT_ERR_NOT_A_STREAM
+        );
+
+        let mut flipped = compressed.clone();
+        let middle = flipped.len() / 2;
+        flipped[middle] ^= 1;
+        let (code, _) = call_c(rbwt_decompress, &flipped);
+        assert!(
+            [RBWT_ERR_CORRUPT, RBWT_ERR_CHECKSUM].contains(&code),
+            "{}",
+            code
+        );
+    }
+}
@@ -0,0 +1,456 @@
+//! `compress --archive`: several files and directories in one file, keeping their names. Each file
+//! is its own complete rust-bwt stream, so members can be listed without decompressing anything,
+//! and one can be extracted without decompressing the rest.
+//!
+//! An archive is [`MAGIC`] and a version byte, then one header per member, ending with a
+//! `MEMBER_END` kind byte. A member header is a kind byte, then its path as a little-endian `u32`
+//! length and that many bytes, then for files the original and compressed lengths as
+//! little-endian `u64`s, followed by the compressed stream itself.
+//!
+//! Paths are relative, `/`-separated and UTF-8. A name that isn't UTF-8 has each of its invalid
+//! bytes written `\xHH`, and a backslash is written `\\`, so every name survives the round trip.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::ffi::{OsStr, OsString};
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+
+use rust_bwt::{BwtError, DecompressOptions, Options};
+
+use crate::interrupt;
+use crate::{CountingReader, CountingWriter};
+
+/// What every archive starts with. Different from a stream's, so neither is mistaken for the
+/// other.
+pub(crate) const MAGIC: &[u8; 4] = b"RBWA";
+
+const VERSION: u8 = 1;
+
+const MEMBER_END: u8 = 0;
+const MEMBER_FILE: u8 = 1;
+const MEMBER_DIRECTORY: u8 = 2;
+
+/// Longer paths are taken as corruption rather than allocated
+const MAX_PATH_LEN: u32 = 1 << 16;
+
+/// One entry of an archive
+pub(crate) struct Member {
+    /// Relative and `/`-separated, escaped as described in the module docs
+    pub(crate) path: String,
+    pub(crate) is_directory: bool,
+    /// Zero for directories
+    pub(crate) original_len: u64,
+    /// Length of the member's compressed stream; zero for directories
+    pub(crate) compressed_len: u64,
+    /// Where the compressed stream starts in the archive
+    offset: u64,
+}
+
+/// Writes an archive of `inputs` to `path`. Each input is stored under its own file name, and
+/// with `recursive`, a directory's contents are stored under its name. Unless `overwrite`, `path`
+/// mustn't exist already. If anything fails, the partial archive is removed.
+///
+/// Returns the entries found inside directories that are neither files nor directories, such as
+/// symbolic links, which weren't archived.
+pub(crate) fn create(
+    path: &Path,
+    inputs: &[PathBuf],
+    recursive: bool,
+    options: &Options,
+    overwrite: bool,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut writer = Writer {
+        output: BufWriter::new(crate::create_output(path, overwrite)?),
+        options,
+        recursive,
+        paths: HashSet::new(),
+        skipped: Vec::new(),
+    };
+
+    match writer.write_all(inputs) {
+        Ok(()) => Ok(writer.skipped),
+        Err(e) => {
+            drop(writer);
+            fs::remove_file(path)?;
+            Err(e)
+        }
+    }
+}
+
+struct Writer<'a> {
+    output: BufWriter<File>,
+    options: &'a Options,
+    recursive: bool,
+    /// Every member path so far, so that two inputs with the same name are caught
+    paths: HashSet<String>,
+    skipped: Vec<PathBuf>,
+}
+
+impl Writer<'_> {
+    fn write_all(&mut self, inputs: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+        self.output.write_all(MAGIC)?;
+        self.output.write_all(&[VERSION])?;
+
+        for input in inputs {
+            // `.` and `..` have no file name of their own, but the directory they point to does
+            let name = match input.file_name() {
+                Some(name) => name.to_owned(),
+                None => fs::canonicalize(input)?
+                    .file_name()
+                    .ok_or_else(|| format!("{} has no name to store it under", input.display()))?
+                    .to_owned(),
+            };
+            self.add(input, escape(&name)?)?;
+        }
+
+        self.output.write_all(&[MEMBER_END])?;
+        self.output.flush()?;
+        Ok(())
+    }
+
+    /// Adds the file or directory at `source` as `path`
+    fn add(&mut self, source: &Path, path: String) -> Result<(), Box<dyn Error>> {
+        if !self.paths.insert(path.clone()) {
+            return Err(format!("more than one input would be stored as {}", path).into());
+        }
+
+        if !fs::metadata(source)?.is_dir() {
+            return self.add_file(source, &path);
+        }
+        if !self.recursive {
+            return Err(format!(
+                "{} is a directory; pass --recursive to archive it",
+                source.display()
+            )
+            .into());
+        }
+
+        self.write_header(MEMBER_DIRECTORY, &path)?;
+
+        // Sorted, so the same tree always makes the same archive
+        let mut entries = fs::read_dir(source)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let file_type = entry.file_type()?;
+            if !file_type.is_file() && !file_type.is_dir() {
+                self.skipped.push(entry.path());
+                continue;
+            }
+            self.add(
+                &entry.path(),
+                format!("{}/{}", path, escape(&entry.file_name())?),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn add_file(&mut self, source: &Path, path: &str) -> Result<(), Box<dyn Error>> {
+        let mut input = CountingReader::new(interrupt::Interruptible(File::open(source)?));
+
+        self.write_header(MEMBER_FILE, path)?;
+        // The lengths are only known afterwards
+        let lengths_offset = self.output.stream_position()?;
+        self.output.write_all(&[0; 16])?;
+        let offset = self.output.stream_position()?;
+
+        rust_bwt::compress_stream_with(&mut input, self.options, &mut self.output)?;
+        let end = self.output.stream_position()?;
+
+        self.output.seek(SeekFrom::Start(lengths_offset))?;
+        self.output.write_all(&input.count.to_le_bytes())?;
+        self.output.write_all(&(end - offset).to_le_bytes())?;
+        self.output.seek(SeekFrom::Start(end))?;
+        Ok(())
+    }
+
+    fn write_header(&mut self, kind: u8, path: &str) -> io::Result<()> {
+        self.output.write_all(&[kind])?;
+        self.output.write_all(&(path.len() as u32).to_le_bytes())?;
+        self.output.write_all(path.as_bytes())
+    }
+}
+
+/// Reads the headers of every member of the archive in `reader`, skipping over the compressed
+/// streams
+pub(crate) fn list<R: Read + Seek>(mut reader: R) -> Result<Vec<Member>, BwtError> {
+    read_archive_header(&mut reader)?;
+
+    let mut members = Vec::new();
+    while let Some(member) = read_member(&mut reader)? {
+        reader.seek(SeekFrom::Start(member.offset + member.compressed_len))?;
+        members.push(member);
+    }
+    Ok(members)
+}
+
+/// Extracts the archive at `path` under `destination`, or with `only`, just the member with that
+/// path and, for a directory, everything in it. Unless `overwrite`, existing files are an error.
+/// Members whose paths would land outside `destination` are refused. With `flat`, every file is
+/// extracted straight into `destination` under its own name, and directories aren't created.
+pub(crate) fn extract(
+    path: &Path,
+    destination: &Path,
+    only: Option<&str>,
+    options: &DecompressOptions,
+    flat: bool,
+    overwrite: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    read_archive_header(&mut reader)?;
+    let only = only.map(|only| only.trim_end_matches('/'));
+
+    let mut extracted = 0;
+    while let Some(member) = read_member(&mut reader)? {
+        let next = member.offset + member.compressed_len;
+        let wanted = only.is_none_or(|only| {
+            member.path == only
+                || member
+                    .path
+                    .strip_prefix(only)
+                    .is_some_and(|rest| rest.starts_with('/'))
+        });
+        if !wanted {
+            reader.seek(SeekFrom::Start(next))?;
+            continue;
+        }
+
+        let relative = if flat {
+            member.path.rsplit('/').next().and_then(relative_path)
+        } else {
+            relative_path(&member.path)
+        };
+        let target = destination.join(relative.ok_or_else(|| {
+            format!(
+                "refusing to extract {}: it's absolute or leads outside the destination",
+                member.path
+            )
+        })?);
+        if member.is_directory {
+            if !flat {
+                fs::create_dir_all(&target)?;
+            }
+        } else {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut output =
+                CountingWriter::new(BufWriter::new(crate::create_output(&target, overwrite)?));
+            let mut decompressed = rust_bwt::decompress_stream_with(
+                interrupt::Interruptible((&mut reader).take(member.compressed_len)),
+                options,
+                &mut output,
+            )
+            .and_then(|()| Ok(output.flush()?));
+            if decompressed.is_ok() && output.count != member.original_len {
+                decompressed = Err(BwtError::LengthMismatch {
+                    expected: member.original_len,
+                    actual: output.count,
+                });
+            }
+            if let Err(e) = decompressed {
+                // Whatever was written is truncated or garbage
+                drop(output);
+                fs::remove_file(&target)?;
+                return Err(e.into());
+            }
+            reader.seek(SeekFrom::Start(next))?;
+        }
+        extracted += 1;
+    }
+
+    match only {
+        Some(only) if extracted == 0 => {
+            Err(format!("{} has no member {}", path.display(), only).into())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn read_archive_header<R: Read>(reader: &mut R) -> Result<(), BwtError> {
+    let [magic @ .., version] = read_array::<_, 5>(reader)?;
+    if &magic != MAGIC {
+        return Err(BwtError::BadMagic);
+    }
+    if version != VERSION {
+        return Err(BwtError::UnsupportedVersion(version));
+    }
+    Ok(())
+}
+
+/// Reads the next member's header, leaving `reader` at the start of its compressed stream. `None`
+/// at the end of the archive.
+fn read_member<R: Read + Seek>(reader: &mut R) -> Result<Option<Member>, BwtError> {
+    let [kind] = read_array(reader)?;
+    let is_directory = match kind {
+        MEMBER_END => return Ok(None),
+        MEMBER_FILE => false,
+        MEMBER_DIRECTORY => true,
+        _ => return Err(BwtError::CorruptStream("unknown archive member kind")),
+    };
+
+    let path_len = u32::from_le_bytes(read_array(reader)?);
+    if path_len > MAX_PATH_LEN {
+        return Err(BwtError::CorruptStream("archive member path is too long"));
+    }
+    let mut path = vec![0; path_len as usize];
+    reader.read_exact(&mut path).map_err(eof_is_truncation)?;
+    let path = String::from_utf8(path)
+        .map_err(|_| BwtError::CorruptStream("archive member path isn't UTF-8"))?;
+
+    let (original_len, compressed_len) = if is_directory {
+        (0, 0)
+    } else {
+        (
+            u64::from_le_bytes(read_array(reader)?),
+            u64::from_le_bytes(read_array(reader)?),
+        )
+    };
+
+    Ok(Some(Member {
+        path,
+        is_directory,
+        original_len,
+        compressed_len,
+        offset: reader.stream_position()?,
+    }))
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> Result<[u8; N], BwtError> {
+    let mut array = [0; N];
+    reader.read_exact(&mut array).map_err(eof_is_truncation)?;
+    Ok(array)
+}
+
+fn eof_is_truncation(e: io::Error) -> BwtError {
+    match e.kind() {
+        io::ErrorKind::UnexpectedEof => BwtError::TruncatedStream,
+        _ => BwtError::Io(e),
+    }
+}
+
+/// `name` as stored in a member path
+fn escape(name: &OsStr) -> Result<String, Box<dyn Error>> {
+    #[cfg(unix)]
+    let bytes = std::os::unix::ffi::OsStrExt::as_bytes(name);
+    #[cfg(not(unix))]
+    let bytes = name
+        .to_str()
+        .ok_or_else(|| format!("{} isn't valid Unicode", name.to_string_lossy()))?
+        .as_bytes();
+
+    let mut escaped = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                c => escaped.push(c),
+            }
+        }
+        for b in chunk.invalid() {
+            let _ = write!(escaped, "\\x{:02x}", b);
+        }
+    }
+    Ok(escaped)
+}
+
+/// Reverses [`escape`], or `None` for a malformed escape
+fn unescape(escaped: &str) -> Option<OsString> {
+    let mut bytes = Vec::with_capacity(escaped.len());
+    let mut rest = escaped.as_bytes();
+    while let Some((&b, after)) = rest.split_first() {
+        rest = after;
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+        match rest {
+            [b'\\', after @ ..] => {
+                bytes.push(b'\\');
+                rest = after;
+            }
+            [b'x', high, low, after @ ..] => {
+                let hex = std::str::from_utf8(&[*high, *low]).ok()?.to_owned();
+                bytes.push(u8::from_str_radix(&hex, 16).ok()?);
+                rest = after;
+            }
+            _ => return None,
+        }
+    }
+
+    #[cfg(unix)]
+    return Some(std::os::unix::ffi::OsStringExt::from_vec(bytes));
+    #[cfg(not(unix))]
+    return String::from_utf8(bytes).ok().map(OsString::from);
+}
+
+/// The relative path a member path stands for, or `None` if it's absolute, climbs out with `..`,
+/// or otherwise isn't a plain list of names
+fn relative_path(path: &str) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in path.split('/') {
+        let name = unescape(component)?;
+        // Exactly one normal component: no `.`, `..`, drive prefixes, or separators smuggled in
+        // through escapes
+        let mut components = Path::new(&name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(normal)), None) if normal == name => relative.push(normal),
+            _ => return None,
+        }
+    }
+    Some(relative)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_only_plain_relative_paths() {
+        assert_eq!(relative_path("a"), Some(PathBuf::from("a")));
+        assert_eq!(relative_path("a/b c/d"), Some(PathBuf::from("a/b c/d")));
+        assert_eq!(relative_path("..a/b.."), Some(PathBuf::from("..a/b..")));
+        assert_eq!(
+            relative_path("back\\\\slash"),
+            Some(PathBuf::from("back\\slash"))
+        );
+
+        for path in [
+            "",
+            "/etc/passwd",
+            "..",
+            "../evil",
+            "a/../../evil",
+            "a/./b",
+            "a//b",
+            "a/",
+            // A slash or `..` smuggled in through an escape
+            "a\\x2fb",
+            "\\x2e\\x2e/evil",
+            // A malformed escape
+            "a\\x4",
+        ] {
+            assert_eq!(relative_path(path), None, "{}", path);
+        }
+    }
+
+    #[test]
+    fn escapes_names_that_arent_utf_8() {
+        for name in ["plain", "back\\slash", "\\x41", "ünïcode"] {
+            let escaped = escape(OsStr::new(name)).unwrap();
+            assert_eq!(unescape(&escaped).unwrap(), name);
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+
+            let name = OsStr::from_bytes(b"caf\xe9\\\xff");
+            let escaped = escape(name).unwrap();
+            assert_eq!(escaped, "caf\\xe9\\\\\\xff");
+            assert_eq!(unescape(&escaped).unwrap(), name);
+        }
+    }
+}
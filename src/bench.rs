@@ -0,0 +1,61 @@
+//! `bench`: times compression and decompression of a file or of generated text.
+
+use std::time::{Duration, Instant};
+
+/// Words the generated text is made of, so it compresses roughly like natural language
+const WORDS: [&str; 16] = [
+    "the",
+    "of",
+    "and",
+    "banana",
+    "bandana",
+    "transform",
+    "wheeler",
+    "burrows",
+    "rotation",
+    "sorted",
+    "column",
+    "block",
+    "stream",
+    "run",
+    "length",
+    "sentinel",
+];
+
+/// `len` bytes of pseudo-random text, the same on every run
+pub(crate) fn synthetic_text(len: usize) -> Vec<u8> {
+    let mut text = Vec::with_capacity(len + 16);
+    // xorshift64, seeded with anything nonzero
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    while text.len() < len {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+
+        text.extend_from_slice(WORDS[(state % WORDS.len() as u64) as usize].as_bytes());
+        text.push(if state >> 60 == 0 { b'\n' } else { b' ' });
+    }
+    text.truncate(len);
+    text
+}
+
+/// Runs `f` once to warm up, then `iterations` more times, returning the fastest of those runs
+/// and the last run's result
+pub(crate) fn fastest<T, E>(
+    iterations: usize,
+    mut f: impl FnMut() -> Result<T, E>,
+) -> Result<(Duration, T), E> {
+    let mut result = f()?;
+    let mut fastest = Duration::MAX;
+    for _ in 0..iterations {
+        let started = Instant::now();
+        result = f()?;
+        fastest = fastest.min(started.elapsed());
+    }
+    Ok((fastest, result))
+}
+
+/// Throughput of processing `len` bytes in `elapsed`, in MB/s
+pub(crate) fn megabytes_per_second(len: usize, elapsed: Duration) -> f64 {
+    len as f64 / 1e6 / elapsed.as_secs_f64()
+}
@@ -0,0 +1,152 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::ops::Range;
+
+/// Bijective Burrows-Wheeler transform (Gil and Scott), which needs no sentinel: the output is a
+/// permutation of `input`, and every byte string is the transform of exactly one other.
+///
+/// The input is split into its Lyndon factorization, a non-increasing sequence of words each
+/// strictly smaller than all of its rotations. Every rotation of every factor is then sorted by
+/// comparing the rotations repeated infinitely, and the output is the last byte of each.
+pub fn bijective_forward(input: &[u8]) -> Vec<u8> {
+    let factors = lyndon_factors(input);
+
+    // Each rotation as (factor, offset into it)
+    let mut rotations = factors
+        .iter()
+        .flat_map(|factor| (0..factor.len()).map(move |offset| (factor.clone(), offset)))
+        .collect::<Vec<_>>();
+    rotations.sort_by(|(a, a_offset), (b, b_offset)| {
+        omega_cmp(&input[a.clone()], *a_offset, &input[b.clone()], *b_offset)
+    });
+
+    rotations
+        .into_iter()
+        .map(|(factor, offset)| {
+            let word = &input[factor];
+            word[(offset + word.len() - 1) % word.len()]
+        })
+        .collect()
+}
+
+/// Reverses [`bijective_forward`]
+pub fn bijective_reverse(input: &[u8]) -> Vec<u8> {
+    // Stably sorting the last column gives the first column. Row r's rotation starts with its
+    // first-column byte, held at `sorted[r]` in the last column, and the row whose last byte that
+    // is holds the same rotation shifted left by one.
+    let mut starts = [0_usize; 256];
+    for b in input {
+        starts[*b as usize] += 1;
+    }
+    let mut total = 0;
+    for start in starts.iter_mut() {
+        (*start, total) = (total, total + *start);
+    }
+    let mut sorted = vec![0; input.len()];
+    for (i, b) in input.iter().enumerate() {
+        sorted[starts[*b as usize]] = i;
+        starts[*b as usize] += 1;
+    }
+
+    // Every cycle of rows spells one factor (or a power of one), starting from the row of its
+    // smallest rotation, which is the factor itself. Cycles are found in increasing order of their
+    // factors, the reverse of the order they appear in the factorization.
+    let mut visited = vec![false; input.len()];
+    let mut factors = Vec::new();
+    for first_row in 0..input.len() {
+        if visited[first_row] {
+            continue;
+        }
+        let mut factor = Vec::new();
+        let mut row = first_row;
+        while !visited[row] {
+            visited[row] = true;
+            row = sorted[row];
+            factor.push(input[row]);
+        }
+        factors.push(factor);
+    }
+
+    factors.into_iter().rev().flatten().collect()
+}
+
+/// Duval's algorithm: the ranges of `input`'s Lyndon factors, in order
+fn lyndon_factors(input: &[u8]) -> Vec<Range<usize>> {
+    let mut factors = Vec::new();
+
+    let mut i = 0;
+    while i < input.len() {
+        let (mut j, mut k) = (i + 1, i);
+        while j < input.len() && input[k] <= input[j] {
+            k = if input[k] < input[j] { i } else { k + 1 };
+            j += 1;
+        }
+        while i <= k {
+            factors.push(i..i + j - k);
+            i += j - k;
+        }
+    }
+
+    factors
+}
+
+/// Compares the rotations of `a` and `b` starting at the given offsets, each repeated forever.
+/// Two periodic strings that agree on their first `a.len() + b.len()` bytes agree everywhere.
+fn omega_cmp(a: &[u8], a_offset: usize, b: &[u8], b_offset: usize) -> Ordering {
+    (0..a.len() + b.len())
+        .map(|i| a[(a_offset + i) % a.len()].cmp(&b[(b_offset + i) % b.len()]))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::collections::BTreeSet;
+
+    use super::*;
+
+    #[test]
+    fn transforms_known_strings() {
+        // Lyndon factors b | an | an | a
+        assert_eq!(bijective_forward(b"banana"), b"annbaa");
+        assert_eq!(bijective_forward(b"a"), b"a");
+        assert_eq!(bijective_forward(b""), b"");
+    }
+
+    #[test]
+    fn factors_into_lyndon_words() {
+        assert_eq!(lyndon_factors(b"banana"), [0..1, 1..3, 3..5, 5..6]);
+        assert_eq!(lyndon_factors(b"aabab").len(), 1);
+        assert_eq!(lyndon_factors(b"cba"), [0..1, 1..2, 2..3]);
+    }
+
+    #[test]
+    fn is_a_bijection() {
+        // Every string over {a, b} of each length up to 10
+        for len in 0..=10 {
+            let mut outputs = BTreeSet::new();
+            for bits in 0..1_u32 << len {
+                let input = (0..len)
+                    .map(|i| if bits >> i & 1 == 0 { b'a' } else { b'b' })
+                    .collect::<Vec<_>>();
+                let output = bijective_forward(&input);
+                assert_eq!(bijective_reverse(&output), input);
+                assert!(outputs.insert(output));
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let every_byte = (0..=255).rev().collect::<Vec<u8>>();
+        for input in [
+            &b"mississippi"[..],
+            &[7; 100],
+            &b"abab".repeat(50),
+            &every_byte,
+        ] {
+            assert_eq!(bijective_reverse(&bijective_forward(input)), input);
+        }
+    }
+}
@@ -0,0 +1,1945 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, SeekFrom};
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use crate::bijective::{bijective_forward, bijective_reverse};
+use crate::bwtstring::{rle_read_bytes, rle_write_bytes, RunCounts};
+use crate::crc32::crc32;
+use crate::fmindex::FmIndex;
+use crate::huffman::{huffman_decode, huffman_encode};
+use crate::hybrid::{hybrid_rle_decode_limited, hybrid_rle_encode};
+use crate::index::{self, index_len, IndexEntry, IndexWriter, FOOTER_LEN};
+use crate::log::{debug, trace};
+use crate::mtf::{mtf_decode, mtf_encode};
+use crate::parallel::for_each_ordered;
+use crate::range::{range_decode, range_encode};
+use crate::rle0::{rle0_decode_limited, rle0_encode};
+use crate::{
+    BWTStr, BlockInfo, BwtError, DecompressOptions, Entropy, Options, Progress, StageTimes,
+    StreamHeader, StreamInfo, SuffixSort,
+};
+
+/// Marks a versioned block-format stream: magic, [`FORMAT_VERSION`], a flags byte naming the stages
+/// applied to every block, then the blocks themselves
+pub(crate) const MAGIC: [u8; 4] = *b"RBWT";
+
+/// Bumped whenever the layout of a block changes. Version 2 switched `(byte, count)` runs from
+/// `u16` to varint counts. Version 3 added the block index after the end-of-stream marker.
+pub(crate) const FORMAT_VERSION: u8 = 3;
+
+/// The first format version whose streams with [`FLAG_CRC32`] end with a block index (see
+/// [`index`](crate::index)) after the end-of-stream marker
+const INDEX_FORMAT_VERSION: u8 = 3;
+
+/// The oldest versioned format still read, with `u16` run counts
+const FIRST_FORMAT_VERSION: u8 = 1;
+
+/// Set when each block's transformed bytes went through move-to-front before RLE
+pub(crate) const FLAG_MTF: u8 = 1 << 0;
+
+/// Set when each block's body is zero-run (RLE0) encoded instead of `(byte, u16 count)` runs. The
+/// block is then the u64 sentinel index followed by the RLE0 output.
+pub(crate) const FLAG_RLE0: u8 = 1 << 1;
+
+/// Set when each block, as described by the other flags, was then canonical Huffman coded
+pub(crate) const FLAG_HUFFMAN: u8 = 1 << 2;
+
+/// Set when each block, as described by the other flags, was then adaptive range coded. Never set
+/// together with [`FLAG_HUFFMAN`].
+pub(crate) const FLAG_RANGE: u8 = 1 << 3;
+
+/// Set when each block starts with a little-endian CRC-32 of its original bytes, ahead of the
+/// stages above, and the stream ends with an empty block as an end-of-stream marker
+pub(crate) const FLAG_CRC32: u8 = 1 << 4;
+
+/// Set when each block's body is literal/run hybrid encoded instead of `(byte, count)` runs. Like
+/// with [`FLAG_RLE0`], the block is then the u64 sentinel index followed by the encoded bytes. Never
+/// set together with [`FLAG_RLE0`].
+pub(crate) const FLAG_HYBRID_RLE: u8 = 1 << 5;
+
+/// Set when the flags byte is followed by the total length of the original data, as a
+/// little-endian `u64`. Left out when it isn't known up front, as with
+/// [`compress_chunks`](crate::compress_chunks).
+pub(crate) const FLAG_ORIGINAL_LEN: u8 = 1 << 6;
+
+/// Set when each block was transformed with the bijective BWT instead of the sentinel one. Such
+/// blocks store no sentinel index, and their runs are written without one.
+///
+/// This is the last free bit of the flags byte: a further stage needs a new format version.
+pub(crate) const FLAG_BIJECTIVE: u8 = 1 << 7;
+
+/// Marks the original, unversioned block format: length-prefixed blocks of plain BWT + RLE. Older
+/// single-block streams start directly with the sentinel index instead.
+pub(crate) const LEGACY_BLOCK_MAGIC: [u8; 4] = *b"BWTB";
+
+/// Size of the little-endian length prefix in front of every block
+const BLOCK_LEN_BYTE_CNT: usize = (u64::BITS / 8) as usize;
+
+/// Size of the little-endian sentinel index at the start of every block
+const SENTINEL_INDEX_BYTE_CNT: usize = (u64::BITS / 8) as usize;
+
+/// The empty block that ends a stream with [`FLAG_CRC32`], length prefix included
+const END_MARKER: [u8; BLOCK_LEN_BYTE_CNT] = [0; BLOCK_LEN_BYTE_CNT];
+
+/// Size of the little-endian original length in headers with [`FLAG_ORIGINAL_LEN`]
+const ORIGINAL_LEN_BYTE_CNT: usize = (u64::BITS / 8) as usize;
+
+/// Decompressing into memory reserves room for the stored original length up front, but no more
+/// than this, so a corrupted length can't cause a huge allocation on its own
+const MAX_PREALLOCATION: u64 = 1 << 28; // 256 MiB
+
+/// Size of the little-endian CRC-32 at the start of every block with [`FLAG_CRC32`]
+const CRC_BYTE_CNT: usize = (u32::BITS / 8) as usize;
+
+/// Writes the stream header followed by one length-prefixed block per `options.block_size` chunk.
+/// Each block is a complete RLE stream, including its own sentinel index.
+///
+/// Blocks are compressed on up to `options.threads` worker threads but always written in input
+/// order, so the output doesn't depend on the thread count.
+pub(crate) fn write_blocks<W: io::Write>(
+    input: &[u8],
+    options: &Options,
+    writer: &mut W,
+) -> io::Result<()> {
+    assert!(options.block_size > 0, "block size must be nonzero");
+
+    let flags = stage_flags(options) | FLAG_ORIGINAL_LEN;
+    let header = header(flags, Some(input.len() as u64));
+    writer.write_all(&header)?;
+
+    let mut index = IndexWriter::default();
+    let mut done = Progress {
+        compressed_len: header.len() as u64,
+        ..Default::default()
+    };
+    for_each_ordered(
+        input.chunks(options.block_size),
+        options.threads,
+        |chunk| {
+            (
+                chunk.len(),
+                compress_block(chunk, flags, options.suffix_sort),
+            )
+        },
+        |(original_len, block)| {
+            index.push(index_entry(&done));
+            done.original_len += original_len as u64;
+            done.compressed_len += (BLOCK_LEN_BYTE_CNT + block.len()) as u64;
+            write_block(&block, writer)
+        },
+    )?;
+
+    if flags & FLAG_CRC32 != 0 {
+        writer.write_all(&END_MARKER)?;
+        done.compressed_len += END_MARKER.len() as u64;
+        writer.write_all(&index.finish(done.compressed_len, done.original_len))?;
+    }
+    Ok(())
+}
+
+/// Like [`write_blocks`], but pulls the input from `reader` one block at a time, so at most a few
+/// blocks per thread are held in memory. The header can't store the original length, which isn't
+/// known until the end, so only the index footer has it; the output is what [`CompressChunks`]
+/// yields for the same input.
+///
+/// `progress` is called after each block is written. Unless `resumed` is empty, the header and the
+/// blocks it reports were written by an earlier call, and this carries on after the last of them:
+/// see [`resume_stream`].
+pub(crate) fn write_stream<R: io::Read + Send, W: io::Write>(
+    reader: R,
+    options: &Options,
+    writer: &mut W,
+    resumed: &[Progress],
+    progress: &mut dyn FnMut(Progress),
+) -> Result<StageTimes, BwtError> {
+    assert!(options.block_size > 0, "block size must be nonzero");
+
+    let mut times = StageTimes::default();
+    let mut read_time = Duration::ZERO;
+    let mut reader = TimedIo {
+        inner: reader,
+        elapsed: &mut read_time,
+    };
+
+    let flags = stage_flags(options);
+    debug!("compressing a stream with {:?}", options);
+    let header = header(flags, None);
+    let mut done = Progress {
+        compressed_len: header.len() as u64,
+        ..Default::default()
+    };
+    let mut index = IndexWriter::default();
+    match resumed.split_last() {
+        None => timed(&mut times.io, || writer.write_all(&header))?,
+        Some((last, before)) => {
+            // Each block starts where the one before it ended, and the first after the header
+            index.push(index_entry(&done));
+            for block in before {
+                index.push(index_entry(block));
+            }
+            done = *last;
+        }
+    }
+
+    let block_size = options.block_size as u64;
+    let mut finished = false;
+    let chunks = std::iter::from_fn(|| {
+        if finished {
+            return None;
+        }
+        let mut chunk = Vec::new();
+        match (&mut reader).take(block_size).read_to_end(&mut chunk) {
+            Ok(0) => None,
+            Ok(_) => Some(Ok(chunk)),
+            Err(e) => {
+                finished = true;
+                Some(Err(e))
+            }
+        }
+    });
+    for_each_ordered(
+        chunks,
+        options.threads,
+        |chunk| {
+            let mut times = StageTimes::default();
+            chunk.map(|chunk| {
+                let block = compress_block_timed(&chunk, flags, options.suffix_sort, &mut times);
+                (chunk.len(), block, times)
+            })
+        },
+        |block| {
+            let (original_len, block, block_times) = block?;
+            trace!(
+                "block {}: {} -> {} bytes",
+                done.blocks,
+                original_len,
+                block.len()
+            );
+            times += block_times;
+            timed(&mut times.io, || write_block(&block, writer))?;
+
+            index.push(index_entry(&done));
+            done.blocks += 1;
+            done.original_len += original_len as u64;
+            done.compressed_len += (BLOCK_LEN_BYTE_CNT + block.len()) as u64;
+            progress(done);
+            Ok::<_, io::Error>(())
+        },
+    )?;
+
+    if flags & FLAG_CRC32 != 0 {
+        timed(&mut times.io, || writer.write_all(&END_MARKER))?;
+        done.compressed_len += END_MARKER.len() as u64;
+        let index = index.finish(done.compressed_len, done.original_len);
+        timed(&mut times.io, || writer.write_all(&index))?;
+        done.compressed_len += index.len() as u64;
+        progress(done);
+    }
+    times.io += read_time;
+    debug!("compressed {} blocks; {:?}", done.blocks, times);
+    Ok(times)
+}
+
+/// Carries on writing a stream that [`write_stream`] started in `stream` and was stopped, after
+/// the last block `resumed` reports, which must be the progress reported after each block so far.
+/// `reader` must continue from where that block's input ended. The header already in `stream` must
+/// be the one `options` gives. With no blocks to resume after, the stream is written from the
+/// start.
+pub(crate) fn resume_stream<R: io::Read + Send, W: io::Read + io::Write + io::Seek>(
+    reader: R,
+    options: &Options,
+    stream: &mut W,
+    resumed: &[Progress],
+    progress: &mut dyn FnMut(Progress),
+) -> Result<StageTimes, BwtError> {
+    let invalid = |message| BwtError::Io(io::Error::new(io::ErrorKind::InvalidInput, message));
+    if !resumed
+        .iter()
+        .map(|block| block.blocks)
+        .eq(1..=resumed.len() as u64)
+    {
+        return Err(invalid(
+            "the blocks to resume after aren't the progress reported after each block",
+        ));
+    }
+
+    stream.seek(SeekFrom::Start(0))?;
+    if let Some(last) = resumed.last() {
+        let expected = header(stage_flags(options), None);
+        let mut found = vec![0; expected.len()];
+        stream.read_exact(&mut found)?;
+        if found != expected {
+            return Err(invalid(
+                "the stream being resumed was started with other settings",
+            ));
+        }
+        stream.seek(SeekFrom::Start(last.compressed_len))?;
+    }
+    write_stream(reader, options, stream, resumed, progress)
+}
+
+/// The header flags naming the stages `options` selects
+fn stage_flags(options: &Options) -> u8 {
+    let mut flags = 0;
+    if options.mtf {
+        flags |= FLAG_MTF;
+    }
+    if options.rle0 {
+        flags |= FLAG_RLE0;
+    } else if options.hybrid_rle {
+        flags |= FLAG_HYBRID_RLE;
+    }
+    match options.entropy {
+        Entropy::None => {}
+        Entropy::Huffman => flags |= FLAG_HUFFMAN,
+        Entropy::Range => flags |= FLAG_RANGE,
+    }
+    if options.checksum {
+        flags |= FLAG_CRC32;
+    }
+    if options.bijective {
+        flags |= FLAG_BIJECTIVE;
+    }
+    flags
+}
+
+/// The stream header. `original_len` must be given exactly when `flags` has [`FLAG_ORIGINAL_LEN`].
+fn header(flags: u8, original_len: Option<u64>) -> Vec<u8> {
+    let mut header = MAGIC.to_vec();
+    header.extend_from_slice(&[FORMAT_VERSION, flags]);
+    if let Some(original_len) = original_len {
+        header.extend_from_slice(original_len.to_le_bytes().as_slice());
+    }
+    header
+}
+
+/// Collects [`write_blocks`] output into a `Vec`
+pub(crate) fn compress_blocks(input: &[u8], options: &Options) -> Vec<u8> {
+    let mut output = Vec::new();
+    write_blocks(input, options, &mut output).expect("writing to a Vec cannot fail");
+    output
+}
+
+fn write_block<W: io::Write>(block: &[u8], writer: &mut W) -> io::Result<()> {
+    writer.write_all((block.len() as u64).to_le_bytes().as_slice())?;
+    writer.write_all(block)
+}
+
+/// Lazily compresses a stream of arbitrarily sized chunks, yielding the stream header and then each
+/// length-prefixed block as soon as enough input has arrived to fill it. The yielded pieces
+/// concatenate to exactly what [`compress_blocks`] returns for the concatenated chunks.
+pub(crate) struct CompressChunks<I> {
+    chunks: I,
+    flags: u8,
+    suffix_sort: SuffixSort,
+    block_size: usize,
+    /// Input received but not yet compressed, always less than a block once a block is yielded
+    pending: Vec<u8>,
+    /// How much has been yielded, for the index
+    done: Progress,
+    index: IndexWriter,
+    header_sent: bool,
+    finished: bool,
+}
+
+impl<I> CompressChunks<I> {
+    pub(crate) fn new(chunks: I, options: &Options) -> Self {
+        assert!(options.block_size > 0, "block size must be nonzero");
+
+        Self {
+            chunks,
+            flags: stage_flags(options),
+            suffix_sort: options.suffix_sort,
+            block_size: options.block_size,
+            pending: Vec::new(),
+            done: Progress::default(),
+            index: IndexWriter::default(),
+            header_sent: false,
+            finished: false,
+        }
+    }
+
+    fn framed_block(&mut self, block: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        write_block(
+            &compress_block(block, self.flags, self.suffix_sort),
+            &mut output,
+        )
+        .expect("writing to a Vec cannot fail");
+        self.index.push(index_entry(&self.done));
+        self.done.original_len += block.len() as u64;
+        self.done.compressed_len += output.len() as u64;
+        output
+    }
+}
+
+impl<I: Iterator<Item = Vec<u8>>> Iterator for CompressChunks<I> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.header_sent {
+            self.header_sent = true;
+            let header = header(self.flags, None);
+            self.done.compressed_len = header.len() as u64;
+            return Some(header);
+        }
+
+        loop {
+            if self.pending.len() >= self.block_size {
+                let rest = self.pending.split_off(self.block_size);
+                let block = std::mem::replace(&mut self.pending, rest);
+                return Some(self.framed_block(&block));
+            }
+
+            if self.finished {
+                return None;
+            }
+            match self.chunks.next() {
+                Some(chunk) => self.pending.extend_from_slice(&chunk),
+                None if !self.pending.is_empty() => {
+                    let block = std::mem::take(&mut self.pending);
+                    return Some(self.framed_block(&block));
+                }
+                None => {
+                    self.finished = true;
+                    if self.flags & FLAG_CRC32 != 0 {
+                        let mut end = END_MARKER.to_vec();
+                        let stream_len = self.done.compressed_len + end.len() as u64;
+                        let index = std::mem::take(&mut self.index);
+                        end.extend(index.finish(stream_len, self.done.original_len));
+                        return Some(end);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Reverses [`compress_blocks`], writing each block's output to `writer` in order. Blocks are
+/// decompressed on up to `options.threads` worker threads.
+///
+/// Streams in the versioned format must start with [`MAGIC`]; unversioned block streams (starting
+/// with [`LEGACY_BLOCK_MAGIC`]) and bare single-block streams are accepted too. Input that is
+/// none of these fails with [`BwtError::BadMagic`]. With `options.concatenated`, a stream ending
+/// in [`END_MARKER`] may be followed by more streams with headers, which are decompressed in turn.
+///
+/// No block may decode to more than the stream's stored original length or
+/// `options.max_output_size`, whichever is smaller, and the output as a whole may not exceed the
+/// latter, so a crafted stream can't make decompression allocate more than that.
+pub(crate) fn read_blocks<W: io::Write>(
+    mut input: &[u8],
+    options: &DecompressOptions,
+    writer: &mut W,
+) -> Result<(), BwtError> {
+    let mut done = Progress::default();
+    loop {
+        let Some((header, blocks)) = parse_header(input)? else {
+            if done.compressed_len > 0 {
+                return Err(BwtError::CorruptStream("data after end of stream"));
+            }
+            return decompress_bare(input, options, writer).map(|_| ());
+        };
+
+        let mut blocks = Blocks {
+            rest: blocks,
+            offset: done.compressed_len as usize + header.len,
+            end_marker: header.flags & FLAG_CRC32 != 0,
+            index: header.has_index(),
+            blocks: 0,
+            next_stream: options.concatenated,
+            following: &[],
+        };
+        write_decompressed(
+            &header,
+            &mut blocks,
+            options,
+            writer,
+            &mut done,
+            &mut |_| {},
+        )?;
+        if blocks.following.is_empty() {
+            return Ok(());
+        }
+        input = blocks.following;
+    }
+}
+
+/// Decompresses `input` as a bare stream from before block mode, returning how long each stage
+/// took and the size of the output
+fn decompress_bare<W: io::Write>(
+    input: &[u8],
+    options: &DecompressOptions,
+    writer: &mut W,
+) -> Result<(StageTimes, u64), BwtError> {
+    let max_output_size = options.max_output_size.unwrap_or(u64::MAX);
+    let mut times = StageTimes::default();
+
+    // Without any magic this is either a bare stream from before block mode or not ours at all;
+    // only a clean decode makes it the former
+    let output = decompress_block(
+        input,
+        0,
+        RunCounts::U16,
+        block_limit(max_output_size),
+        options,
+        &mut times,
+    )
+    .map_err(bare_error)?;
+    timed(&mut times.io, || writer.write_all(&output))?;
+    Ok((times, output.len() as u64))
+}
+
+/// Like [`read_blocks`], but pulls the stream from `reader` one block at a time, so at most a few
+/// blocks per thread are held in memory. Bare streams are a single block, so they're read whole.
+///
+/// `progress` is called after each block is written.
+pub(crate) fn read_stream<R: io::Read + Send, W: io::Write>(
+    reader: R,
+    options: &DecompressOptions,
+    writer: &mut W,
+    progress: &mut dyn FnMut(Progress),
+) -> Result<StageTimes, BwtError> {
+    let mut read_time = Duration::ZERO;
+    let mut reader = TimedIo {
+        inner: reader,
+        elapsed: &mut read_time,
+    };
+
+    let mut times = StageTimes::default();
+    let mut done = Progress::default();
+    loop {
+        let prefix = read_prefix(&mut reader)?;
+        let next_stream = done.compressed_len > 0;
+        if next_stream && prefix.is_empty() {
+            break;
+        }
+
+        let Some((header, _)) = parse_header(&prefix)? else {
+            if next_stream {
+                return Err(BwtError::CorruptStream("data after end of stream"));
+            }
+            let mut input = prefix;
+            reader.read_to_end(&mut input)?;
+            let (bare_times, output_len) = decompress_bare(&input, options, writer)?;
+            times += bare_times;
+            progress(Progress {
+                original_len: output_len,
+                compressed_len: input.len() as u64,
+                blocks: 1,
+                checked_blocks: 0,
+            });
+            break;
+        };
+
+        let end_marker = header.flags & FLAG_CRC32 != 0;
+        let blocks = ReadBlocks {
+            reader: &mut reader,
+            offset: done.compressed_len + header.len as u64,
+            end_marker,
+            index: header.has_index(),
+            blocks: 0,
+            next_stream: options.concatenated,
+            finished: false,
+        };
+        times += write_decompressed(&header, blocks, options, writer, &mut done, progress)?;
+        // Without an end marker, the stream ran to the end of the input
+        if !(end_marker && options.concatenated) {
+            break;
+        }
+    }
+
+    drop(reader);
+    times.io += read_time;
+    Ok(times)
+}
+
+/// Decompresses the bytes of the original data in `range`, reading only the blocks that hold them,
+/// from a single stream that ends in a block index. See [`decompress_range`](crate::decompress_range).
+pub(crate) fn read_range<R: io::Read + io::Seek + Send, W: io::Write>(
+    mut reader: R,
+    range: Range<u64>,
+    options: &DecompressOptions,
+    writer: &mut W,
+) -> Result<(), BwtError> {
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    let Some(footer_start) = file_len.checked_sub(FOOTER_LEN) else {
+        return Err(BwtError::NoIndex);
+    };
+    reader.seek(SeekFrom::Start(footer_start))?;
+    let mut footer = [0; FOOTER_LEN as usize];
+    reader.read_exact(&mut footer)?;
+    let footer = index::parse_footer(&footer).ok_or(BwtError::NoIndex)?;
+    // A shorter stream is the last of several, and its index covers only its own blocks
+    if footer.stream_len < file_len {
+        return Err(BwtError::NoIndex);
+    }
+
+    reader.seek(SeekFrom::Start(0))?;
+    let header = match parse_header(&read_prefix(&mut reader)?)? {
+        Some((header, _)) if header.has_index() => header,
+        _ => return Err(BwtError::NoIndex),
+    };
+    let index_start = file_len
+        .checked_sub(index_len(footer.blocks))
+        .filter(|start| *start >= header.len as u64)
+        .filter(|_| footer.stream_len == file_len)
+        .filter(|_| {
+            header
+                .original_len
+                .is_none_or(|len| len == footer.original_len)
+        })
+        .ok_or(BwtError::CorruptStream("malformed block index"))?;
+
+    if range.end > footer.original_len {
+        return Err(BwtError::RangeOutOfBounds {
+            end: range.end,
+            len: footer.original_len,
+        });
+    }
+    let max_output_size = options.max_output_size.unwrap_or(u64::MAX);
+    if range.end.saturating_sub(range.start) > max_output_size {
+        return Err(BwtError::OutputTooLarge {
+            limit: max_output_size,
+        });
+    }
+    if range.is_empty() {
+        return Ok(());
+    }
+
+    reader.seek(SeekFrom::Start(index_start))?;
+    let mut entries = vec![0; (index_len(footer.blocks) - FOOTER_LEN) as usize];
+    reader.read_exact(&mut entries)?;
+    let entries = index::parse_entries(&entries);
+
+    // The blocks starting at or before the range's start, less all but the last of them, up to
+    // the first starting at or after its end
+    let first = entries
+        .partition_point(|entry| entry.original_offset <= range.start)
+        .checked_sub(1)
+        .ok_or(BwtError::CorruptStream("malformed block index"))?;
+    let end = entries.partition_point(|entry| entry.original_offset < range.end);
+    debug!(
+        "decompressing bytes {:?} from blocks {}..{} of {}",
+        range, first, end, footer.blocks
+    );
+
+    let block_starts = entries[first..]
+        .iter()
+        .map(|entry| entry.original_offset)
+        .chain([footer.original_len])
+        .collect::<Vec<_>>();
+    reader.seek(SeekFrom::Start(entries[first].compressed_offset))?;
+    let blocks = ReadBlocks {
+        reader: &mut reader,
+        offset: entries[first].compressed_offset,
+        end_marker: false,
+        index: false,
+        blocks: 0,
+        next_stream: false,
+        finished: false,
+    };
+
+    let (flags, counts) = (header.flags, header.counts);
+    let limit = block_limit(footer.original_len);
+    let mut block_starts = block_starts.windows(2);
+    for_each_ordered(
+        blocks.take(end - first),
+        options.threads,
+        |block| {
+            block.and_then(|block| {
+                decompress_block(
+                    &block,
+                    flags,
+                    counts,
+                    limit,
+                    options,
+                    &mut StageTimes::default(),
+                )
+            })
+        },
+        |output| {
+            let output = output?;
+            let starts = block_starts.next().expect("a pair of starts per block");
+            let (start, next_start) = (starts[0], starts[1]);
+            if output.len() as u64 != next_start.saturating_sub(start) {
+                return Err(BwtError::CorruptStream(
+                    "block index doesn't match the blocks",
+                ));
+            }
+            let from = range.start.saturating_sub(start) as usize;
+            let to = (range.end - start).min(output.len() as u64) as usize;
+            writer.write_all(&output[from..to])?;
+            Ok(())
+        },
+    )
+}
+
+/// Reads just enough of a stream for [`parse_header`]: the magic, then version, flags and original
+/// length if it's ours. Shorter at the end of the input.
+fn read_prefix<R: io::Read>(reader: &mut R) -> Result<Vec<u8>, BwtError> {
+    let mut prefix = vec![0; MAGIC.len()];
+    let mut prefix_len = read_full(reader, &mut prefix)?;
+    if prefix[..prefix_len] == MAGIC {
+        prefix.resize(MAGIC.len() + 2, 0);
+        prefix_len += read_full(reader, &mut prefix[MAGIC.len()..])?;
+        if prefix_len == prefix.len() && prefix[MAGIC.len() + 1] & FLAG_ORIGINAL_LEN != 0 {
+            prefix.resize(prefix_len + ORIGINAL_LEN_BYTE_CNT, 0);
+            prefix_len += read_full(reader, &mut prefix[MAGIC.len() + 2..])?;
+        }
+    }
+    prefix.truncate(prefix_len);
+    Ok(prefix)
+}
+
+/// Decompresses `blocks` on up to `options.threads` worker threads, writing each one's output to
+/// `writer` in order, and calling `progress` after each one. `done` is how far decompression had
+/// got before this stream, and is advanced past it. Checks the output against
+/// `options.max_output_size` and the header's original length. Returns how long each stage took.
+fn write_decompressed<B, W>(
+    header: &Header,
+    blocks: impl Iterator<Item = Result<B, BwtError>> + Send,
+    options: &DecompressOptions,
+    writer: &mut W,
+    done: &mut Progress,
+    progress: &mut dyn FnMut(Progress),
+) -> Result<StageTimes, BwtError>
+where
+    B: AsRef<[u8]> + Send,
+    W: io::Write,
+{
+    let max_output_size = options.max_output_size.unwrap_or(u64::MAX);
+
+    // A stream that says up front it's too large can be refused without decoding anything
+    let stream_start = done.original_len;
+    let blocks_start = done.blocks;
+    if header
+        .original_len
+        .is_some_and(|len| stream_start.saturating_add(len) > max_output_size)
+    {
+        return Err(BwtError::OutputTooLarge {
+            limit: max_output_size,
+        });
+    }
+    let limit = block_limit(header.original_len.unwrap_or(u64::MAX).min(max_output_size));
+    debug!(
+        "decompressing a stream with {:?} and {:?}",
+        header.describe(),
+        options
+    );
+
+    let (flags, counts) = (header.flags, header.counts);
+    let checked = flags & FLAG_CRC32 != 0 && options.verify_checksum;
+    let mut times = StageTimes::default();
+    done.compressed_len += header.len as u64;
+    for_each_ordered(
+        blocks,
+        options.threads,
+        |block| {
+            let mut times = StageTimes::default();
+            block.and_then(|block| {
+                let block = block.as_ref();
+                decompress_block(block, flags, counts, limit, options, &mut times)
+                    .map(|output| (block.len(), output, times))
+            })
+        },
+        |output| {
+            let (compressed_len, output, block_times) = output?;
+            trace!(
+                "block {}: {} -> {} bytes",
+                done.blocks,
+                compressed_len,
+                output.len()
+            );
+            times += block_times;
+            if done.original_len + output.len() as u64 > max_output_size {
+                return Err(BwtError::OutputTooLarge {
+                    limit: max_output_size,
+                });
+            }
+            timed(&mut times.io, || writer.write_all(&output))?;
+
+            done.blocks += 1;
+            done.checked_blocks += checked as u64;
+            done.original_len += output.len() as u64;
+            done.compressed_len += (BLOCK_LEN_BYTE_CNT + compressed_len) as u64;
+            progress(*done);
+            Ok(())
+        },
+    )?;
+
+    match header.original_len {
+        Some(expected) if expected != done.original_len - stream_start => {
+            Err(BwtError::LengthMismatch {
+                expected,
+                actual: done.original_len - stream_start,
+            })
+        }
+        _ => {
+            if header.flags & FLAG_CRC32 != 0 {
+                done.compressed_len += END_MARKER.len() as u64;
+                if header.has_index() {
+                    done.compressed_len += index_len(done.blocks - blocks_start);
+                }
+                progress(*done);
+            }
+            debug!("decompressed {} blocks; {:?}", done.blocks, times);
+            Ok(times)
+        }
+    }
+}
+
+/// What a stream's header says about how to read its blocks
+struct Header {
+    /// `None` for the unversioned block format
+    version: Option<u8>,
+    flags: u8,
+    counts: RunCounts,
+    original_len: Option<u64>,
+    /// Size of the header itself, which is where the first block starts
+    len: usize,
+}
+
+impl Header {
+    fn blocks<'a>(&self, blocks: &'a [u8]) -> Blocks<'a> {
+        Blocks {
+            rest: blocks,
+            offset: self.len,
+            end_marker: self.flags & FLAG_CRC32 != 0,
+            index: self.has_index(),
+            blocks: 0,
+            next_stream: false,
+            following: &[],
+        }
+    }
+
+    /// Whether the stream ends with a block index after its end marker
+    fn has_index(&self) -> bool {
+        self.version >= Some(INDEX_FORMAT_VERSION) && self.flags & FLAG_CRC32 != 0
+    }
+
+    fn describe(&self) -> StreamHeader {
+        let flags = self.flags;
+        let entropy = if flags & FLAG_HUFFMAN != 0 {
+            Entropy::Huffman
+        } else if flags & FLAG_RANGE != 0 {
+            Entropy::Range
+        } else {
+            Entropy::None
+        };
+
+        StreamHeader {
+            version: self.version,
+            mtf: flags & FLAG_MTF != 0,
+            rle0: flags & FLAG_RLE0 != 0,
+            hybrid_rle: flags & FLAG_HYBRID_RLE != 0,
+            entropy,
+            checksum: flags & FLAG_CRC32 != 0,
+            original_len: self.original_len,
+            bijective: flags & FLAG_BIJECTIVE != 0,
+            index: self.has_index(),
+        }
+    }
+}
+
+/// Splits a block-format stream into its header and the blocks after it. Returns `None` if `input`
+/// doesn't start with either magic.
+fn parse_header(input: &[u8]) -> Result<Option<(Header, &[u8])>, BwtError> {
+    if let Some(rest) = input.strip_prefix(MAGIC.as_slice()) {
+        let [version, flags, rest @ ..] = rest else {
+            return Err(BwtError::UnexpectedEof {
+                field: "stream header",
+                offset: 0,
+            });
+        };
+        if !(FIRST_FORMAT_VERSION..=FORMAT_VERSION).contains(version) {
+            return Err(BwtError::UnsupportedVersion(*version));
+        }
+        if flags & (FLAG_HUFFMAN | FLAG_RANGE) == FLAG_HUFFMAN | FLAG_RANGE
+            || flags & (FLAG_RLE0 | FLAG_HYBRID_RLE) == FLAG_RLE0 | FLAG_HYBRID_RLE
+        {
+            return Err(BwtError::UnsupportedFlags(*flags));
+        }
+        let counts = if *version == FIRST_FORMAT_VERSION {
+            RunCounts::U16
+        } else {
+            RunCounts::Varint
+        };
+        let (original_len, rest) = if flags & FLAG_ORIGINAL_LEN != 0 {
+            let (len_bytes, rest) =
+                rest.split_at_checked(ORIGINAL_LEN_BYTE_CNT)
+                    .ok_or(BwtError::UnexpectedEof {
+                        field: "original length",
+                        offset: (input.len() - rest.len()) as u64,
+                    })?;
+            (
+                Some(u64::from_le_bytes(len_bytes.try_into().unwrap())),
+                rest,
+            )
+        } else {
+            (None, rest)
+        };
+        let mut header = Header {
+            version: Some(*version),
+            flags: *flags,
+            counts,
+            original_len,
+            len: input.len() - rest.len(),
+        };
+        // A stream written a block at a time only knows its length at the end, so it's in the
+        // index footer instead, which is at hand if `input` is the whole stream
+        if header.original_len.is_none() && header.has_index() {
+            header.original_len = index::stored_original_len(input);
+        }
+        Ok(Some((header, rest)))
+    } else if let Some(rest) = input.strip_prefix(LEGACY_BLOCK_MAGIC.as_slice()) {
+        let header = Header {
+            version: None,
+            flags: 0,
+            counts: RunCounts::U16,
+            original_len: None,
+            len: LEGACY_BLOCK_MAGIC.len(),
+        };
+        Ok(Some((header, rest)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Reads just the header of a stream. Bare streams, which have none, are decoded to make sure
+/// they're streams at all.
+pub(crate) fn stream_header(input: &[u8]) -> Result<StreamHeader, BwtError> {
+    match parse_header(input)? {
+        Some((header, _)) => Ok(header.describe()),
+        None => {
+            decode_block(input, 0, RunCounts::U16, usize::MAX).map_err(bare_error)?;
+            Ok(bare_header())
+        }
+    }
+}
+
+/// Failing to decode a stream without any magic means it isn't one of ours, unless it failed only
+/// for being too large
+fn bare_error(e: BwtError) -> BwtError {
+    match e {
+        BwtError::OutputTooLarge { .. } => e,
+        _ => BwtError::BadMagic,
+    }
+}
+
+/// A byte count as a per-block decoding limit
+fn block_limit(limit: u64) -> usize {
+    usize::try_from(limit).unwrap_or(usize::MAX)
+}
+
+/// Describes a stream without reversing any transforms: each block is only entropy and run-length
+/// decoded, which is enough to learn its original size, sentinel index and runs. A stream that
+/// ends partway through is described up to the last whole block and marked truncated.
+pub(crate) fn stream_info(input: &[u8]) -> Result<StreamInfo, BwtError> {
+    let Some((header, blocks)) = parse_header(input)? else {
+        let (transformed, _) =
+            decode_block(input, 0, RunCounts::U16, usize::MAX).map_err(bare_error)?;
+        return Ok(StreamInfo {
+            header: bare_header(),
+            blocks: vec![block_info(input.len(), &transformed, None)],
+            truncated: false,
+        });
+    };
+
+    let limit = block_limit(header.original_len.unwrap_or(u64::MAX));
+    let mut infos = Vec::new();
+    let mut truncated = false;
+    for block in header.blocks(blocks) {
+        let block = match block {
+            Ok(block) => block,
+            Err(BwtError::UnexpectedEof { .. }) => {
+                truncated = true;
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        let (transformed, crc32) = decode_block(block, header.flags, header.counts, limit)?;
+        infos.push(block_info(block.len(), &transformed, crc32));
+    }
+
+    // Without an end-of-stream marker, a stream cut at a block boundary is only noticed by
+    // coming up short of the stored length
+    let original_len = infos
+        .iter()
+        .map(|info| info.original_len as u64)
+        .sum::<u64>();
+    truncated |= header
+        .original_len
+        .is_some_and(|expected| original_len < expected);
+
+    Ok(StreamInfo {
+        header: header.describe(),
+        blocks: infos,
+        truncated,
+    })
+}
+
+/// Counts the occurrences of `pattern` in a stream by backward search over each block's
+/// transform, and those spanning a block boundary with [`Straddling`].
+pub(crate) fn count_matches(input: &[u8], pattern: &[u8]) -> Result<u64, BwtError> {
+    let mut count = 0;
+    let mut straddling = Straddling::new(pattern);
+    decode_blocks(input, |_, transformed, _| {
+        let (block_count, straddling_count) = match &transformed {
+            Transformed::Sentinel(transformed) => {
+                let index = FmIndex::new(transformed);
+                let edge_len = straddling.edge_len();
+                let straddling_positions = straddling.next_block(
+                    transformed.len() - 1,
+                    &index.text_start(edge_len),
+                    &index.text_end(edge_len),
+                );
+                (index.count(pattern), straddling_positions.len())
+            }
+            Transformed::Bijective(transformed) => {
+                let original = bijective_reverse(transformed);
+                (
+                    scan(&original, pattern).count(),
+                    straddling.next_original(&original).len(),
+                )
+            }
+        };
+        count += (block_count + straddling_count) as u64;
+    })?;
+    Ok(count)
+}
+
+/// Locates the occurrences of `pattern` in a stream by backward search over each block's
+/// transform, and those spanning a block boundary with [`Straddling`], returning their positions
+/// in the original data in increasing order.
+pub(crate) fn locate_matches(
+    input: &[u8],
+    pattern: &[u8],
+    sample_rate: usize,
+) -> Result<Vec<u64>, BwtError> {
+    let mut positions = Vec::new();
+    let mut straddling = Straddling::new(pattern);
+    decode_blocks(input, |_, transformed, _| {
+        // Occurrences starting in earlier blocks come before any starting in this one
+        let block_start = straddling.len;
+        let block_positions = match &transformed {
+            Transformed::Sentinel(transformed) => {
+                let index = FmIndex::with_sample_rate(transformed, sample_rate);
+                let edge_len = straddling.edge_len();
+                positions.extend(straddling.next_block(
+                    transformed.len() - 1,
+                    &index.text_start(edge_len),
+                    &index.text_end(edge_len),
+                ));
+                index.locate(pattern)
+            }
+            Transformed::Bijective(transformed) => {
+                let original = bijective_reverse(transformed);
+                positions.extend(straddling.next_original(&original));
+                scan(&original, pattern).collect()
+            }
+        };
+        positions.extend(
+            block_positions
+                .into_iter()
+                .map(|position| block_start + position as u64),
+        );
+    })?;
+    Ok(positions)
+}
+
+/// Positions of `pattern` in a block's original data, found the slow way. Bijective transforms
+/// have no FM-index to search.
+fn scan<'a>(original: &'a [u8], pattern: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+    (0..(original.len() + 1).saturating_sub(pattern.len()))
+        .filter(move |i| original[*i..].starts_with(pattern))
+}
+
+/// Finds the occurrences of a pattern that span a block boundary, which searching each block on
+/// its own misses. Keeps the end of the data seen so far, as far back as such an occurrence can
+/// start: one byte short of the pattern.
+struct Straddling<'a> {
+    pattern: &'a [u8],
+    /// The last [`edge_len`](Self::edge_len) bytes of the data seen so far, or all of it
+    tail: Vec<u8>,
+    /// Length of the data seen so far
+    len: u64,
+}
+
+impl<'a> Straddling<'a> {
+    fn new(pattern: &'a [u8]) -> Self {
+        Self {
+            pattern,
+            tail: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// How much of the start and end of each block [`next_block`](Self::next_block) needs
+    fn edge_len(&self) -> usize {
+        self.pattern.len().saturating_sub(1)
+    }
+
+    /// Moves past the next block, `len` bytes long, given its first and last
+    /// [`edge_len`](Self::edge_len) bytes, or all of it if it's shorter. Returns the positions of
+    /// the occurrences that end in it but start before it, in increasing order.
+    fn next_block(&mut self, len: usize, start: &[u8], end: &[u8]) -> Vec<u64> {
+        let window = [self.tail.as_slice(), start].concat();
+        let tail_start = self.len - self.tail.len() as u64;
+        let positions = (0..self.tail.len())
+            .filter(|i| window[*i..].starts_with(self.pattern))
+            .map(|i| tail_start + i as u64)
+            .collect();
+
+        // A block shorter than the tail only adds to it
+        if len > end.len() {
+            self.tail.clear();
+        }
+        self.tail.extend_from_slice(end);
+        let excess = self.tail.len().saturating_sub(self.edge_len());
+        self.tail.drain(..excess);
+        self.len += len as u64;
+        positions
+    }
+
+    /// Like [`next_block`](Self::next_block), given the block's whole original data
+    fn next_original(&mut self, original: &[u8]) -> Vec<u64> {
+        let edge_len = self.edge_len().min(original.len());
+        self.next_block(
+            original.len(),
+            &original[..edge_len],
+            &original[original.len() - edge_len..],
+        )
+    }
+}
+
+/// Reads into `buf` until it's full or `reader` runs out, returning how much was read
+fn read_full<R: io::Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(read) => len += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+/// Entropy and run-length decodes every block of a stream in order, without reversing its
+/// transform, passing `f` each block's compressed length, transform, and stored CRC-32. Returns
+/// the stream's header.
+fn decode_blocks(
+    input: &[u8],
+    mut f: impl FnMut(usize, Transformed, Option<u32>),
+) -> Result<StreamHeader, BwtError> {
+    let Some((header, blocks)) = parse_header(input)? else {
+        let (transformed, _) =
+            decode_block(input, 0, RunCounts::U16, usize::MAX).map_err(bare_error)?;
+        f(input.len(), transformed, None);
+        return Ok(bare_header());
+    };
+
+    let limit = block_limit(header.original_len.unwrap_or(u64::MAX));
+    for block in header.blocks(blocks) {
+        let block = block?;
+        let (transformed, crc32) = decode_block(block, header.flags, header.counts, limit)?;
+        f(block.len(), transformed, crc32);
+    }
+
+    Ok(header.describe())
+}
+
+/// A bare stream from before block mode: plain BWT + RLE with `u16` counts
+fn bare_header() -> StreamHeader {
+    StreamHeader {
+        version: None,
+        mtf: false,
+        rle0: false,
+        hybrid_rle: false,
+        entropy: Entropy::None,
+        checksum: false,
+        original_len: None,
+        bijective: false,
+        index: false,
+    }
+}
+
+fn block_info(compressed_len: usize, transformed: &Transformed, crc32: Option<u32>) -> BlockInfo {
+    let sentinel_index = match transformed {
+        Transformed::Sentinel(transformed) => Some(transformed.sentinel_index()),
+        Transformed::Bijective(_) => None,
+    };
+
+    BlockInfo {
+        compressed_len,
+        original_len: transformed.original_len(),
+        sentinel_index,
+        run_lengths: transformed.run_length_histogram(),
+        crc32,
+    }
+}
+
+/// Collects [`read_blocks`] output into a `Vec`
+pub(crate) fn decompress_blocks(
+    input: &[u8],
+    options: &DecompressOptions,
+) -> Result<Vec<u8>, BwtError> {
+    let capacity = match parse_header(input) {
+        Ok(Some((header, _))) => header.original_len.unwrap_or(0).min(MAX_PREALLOCATION),
+        _ => 0,
+    };
+    let mut output = Vec::with_capacity(capacity as usize);
+    read_blocks(input, options, &mut output)?;
+    Ok(output)
+}
+
+/// Splits a sequence of length-prefixed blocks, stopping after the first malformed one
+struct Blocks<'a> {
+    rest: &'a [u8],
+    /// Where `rest` starts in the whole stream, for error messages
+    offset: usize,
+    /// Whether the blocks must be followed by [`END_MARKER`]
+    end_marker: bool,
+    /// Whether the end marker is followed by a block index
+    index: bool,
+    /// How many blocks have been split off, which is how many entries the index has
+    blocks: u64,
+    /// Whether another stream may follow the end marker
+    next_stream: bool,
+    /// What followed the end marker, with `next_stream`
+    following: &'a [u8],
+}
+
+impl<'a> Iterator for Blocks<'a> {
+    type Item = Result<&'a [u8], BwtError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.end_marker {
+            if self.rest.is_empty() {
+                return Some(Err(self.truncated("end-of-stream marker")));
+            }
+            if let Some(rest) = self.rest.strip_prefix(END_MARKER.as_slice()) {
+                self.end_marker = false;
+                self.offset += END_MARKER.len();
+                let rest = if self.index {
+                    let Some((index, rest)) = usize::try_from(index_len(self.blocks))
+                        .ok()
+                        .and_then(|index_len| rest.split_at_checked(index_len))
+                    else {
+                        return Some(Err(self.truncated("block index")));
+                    };
+                    if let Err(e) = index::check(index, self.blocks) {
+                        self.rest = &[];
+                        return Some(Err(e));
+                    }
+                    rest
+                } else {
+                    rest
+                };
+                self.rest = &[];
+                if self.next_stream {
+                    self.following = rest;
+                    return None;
+                }
+                return (!rest.is_empty())
+                    .then_some(Err(BwtError::CorruptStream("data after end of stream")));
+            }
+        }
+        if self.rest.is_empty() {
+            return None;
+        }
+
+        let Some((len_bytes, rest)) = self.rest.split_at_checked(BLOCK_LEN_BYTE_CNT) else {
+            return Some(Err(self.truncated("block length")));
+        };
+        let block_len = u64::from_le_bytes(len_bytes.try_into().unwrap());
+        let Some((block, rest)) = usize::try_from(block_len)
+            .ok()
+            .and_then(|block_len| rest.split_at_checked(block_len))
+        else {
+            self.offset += BLOCK_LEN_BYTE_CNT;
+            return Some(Err(self.truncated("block")));
+        };
+
+        self.offset += self.rest.len() - rest.len();
+        self.rest = rest;
+        self.blocks += 1;
+        Some(Ok(block))
+    }
+}
+
+impl Blocks<'_> {
+    /// Reports that the stream ended in the middle of `field`, which starts at the current offset,
+    /// and stops the iteration
+    fn truncated(&mut self, field: &'static str) -> BwtError {
+        self.rest = &[];
+        self.end_marker = false;
+        BwtError::UnexpectedEof {
+            field,
+            offset: self.offset as u64,
+        }
+    }
+}
+
+/// Like [`Blocks`], but reads each block from a [`Read`](io::Read) as it's needed
+struct ReadBlocks<R> {
+    reader: R,
+    /// How far into the stream `reader` is, for error messages
+    offset: u64,
+    /// Whether the blocks must be followed by [`END_MARKER`]
+    end_marker: bool,
+    /// Whether the end marker is followed by a block index
+    index: bool,
+    /// How many blocks have been read, which is how many entries the index has
+    blocks: u64,
+    /// Whether another stream may follow the end marker. If so, it's left unread in `reader`.
+    next_stream: bool,
+    finished: bool,
+}
+
+impl<R: io::Read> Iterator for ReadBlocks<R> {
+    type Item = Result<Vec<u8>, BwtError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        let mut len_bytes = [0; BLOCK_LEN_BYTE_CNT];
+        match read_full(&mut self.reader, &mut len_bytes) {
+            Ok(0) if self.end_marker => return Some(Err(self.truncated("end-of-stream marker"))),
+            Ok(0) => {
+                self.finished = true;
+                return None;
+            }
+            Ok(BLOCK_LEN_BYTE_CNT) => {}
+            Ok(_) => return Some(Err(self.truncated("block length"))),
+            Err(e) => return Some(Err(self.failed(e))),
+        }
+        if self.end_marker && len_bytes == END_MARKER {
+            self.offset += END_MARKER.len() as u64;
+            if self.index {
+                if let Err(e) = self.read_index() {
+                    return Some(Err(e));
+                }
+            }
+            self.finished = true;
+            if self.next_stream {
+                return None;
+            }
+            return match read_full(&mut self.reader, &mut [0]) {
+                Ok(0) => None,
+                Ok(_) => Some(Err(BwtError::CorruptStream("data after end of stream"))),
+                Err(e) => Some(Err(e.into())),
+            };
+        }
+
+        let block_len = u64::from_le_bytes(len_bytes);
+        self.offset += BLOCK_LEN_BYTE_CNT as u64;
+        // Grows as the data actually arrives, so a corrupted length can't cause a huge allocation
+        let mut block = Vec::new();
+        match (&mut self.reader).take(block_len).read_to_end(&mut block) {
+            Ok(read) if read as u64 == block_len => {}
+            Ok(_) => return Some(Err(self.truncated("block"))),
+            Err(e) => return Some(Err(self.failed(e))),
+        }
+
+        self.offset += block_len;
+        self.blocks += 1;
+        Some(Ok(block))
+    }
+}
+
+impl<R> ReadBlocks<R> {
+    /// Reports that the stream ended in the middle of `field`, which starts at the current offset,
+    /// and stops the iteration
+    fn truncated(&mut self, field: &'static str) -> BwtError {
+        self.finished = true;
+        BwtError::UnexpectedEof {
+            field,
+            offset: self.offset,
+        }
+    }
+
+    /// Stops the iteration on a read error
+    fn failed(&mut self, e: io::Error) -> BwtError {
+        self.finished = true;
+        e.into()
+    }
+}
+
+impl<R: io::Read> ReadBlocks<R> {
+    /// Reads and checks the block index after the end marker
+    fn read_index(&mut self) -> Result<(), BwtError> {
+        let index_len = index_len(self.blocks);
+        let mut index = Vec::new();
+        match (&mut self.reader).take(index_len).read_to_end(&mut index) {
+            Ok(read) if read as u64 == index_len => {}
+            Ok(_) => return Err(self.truncated("block index")),
+            Err(e) => return Err(self.failed(e)),
+        }
+        self.offset += index_len;
+        index::check(&index, self.blocks).inspect_err(|_| self.finished = true)
+    }
+}
+
+/// BWT, then the stages selected by `flags`, then RLE, of a single block, preceded by its CRC-32
+/// with [`FLAG_CRC32`]. The sentinel BWT sorts suffixes with `suffix_sort`.
+pub(crate) fn compress_block(input: &[u8], flags: u8, suffix_sort: SuffixSort) -> Vec<u8> {
+    compress_block_timed(input, flags, suffix_sort, &mut StageTimes::default())
+}
+
+/// Like [`compress_block`], adding the time each stage takes to `times`
+fn compress_block_timed(
+    input: &[u8],
+    flags: u8,
+    suffix_sort: SuffixSort,
+    times: &mut StageTimes,
+) -> Vec<u8> {
+    let mut output = Vec::new();
+    if flags & FLAG_BIJECTIVE != 0 {
+        let mut transformed = timed(&mut times.transform, || bijective_forward(input));
+        if flags & FLAG_MTF != 0 {
+            transformed = timed(&mut times.mtf, || mtf_encode(&transformed));
+        }
+        timed(&mut times.runs, || {
+            encode_runs(&transformed, flags, &mut output)
+        });
+    } else {
+        let mut transformed = timed(&mut times.transform, || {
+            BWTStr::new(input.to_vec()).forward_transform_with(suffix_sort)
+        });
+        if flags & FLAG_MTF != 0 {
+            transformed = timed(&mut times.mtf, || transformed.mtf_encode());
+        }
+
+        timed(&mut times.runs, || {
+            if flags & (FLAG_RLE0 | FLAG_HYBRID_RLE) != 0 {
+                output.extend_from_slice(
+                    (transformed.sentinel_index() as u64)
+                        .to_le_bytes()
+                        .as_slice(),
+                );
+                encode_runs(&transformed.to_bytes(), flags, &mut output);
+            } else {
+                transformed
+                    .rle_write(&mut output)
+                    .expect("writing to a Vec cannot fail");
+            }
+        });
+    }
+
+    if flags & FLAG_HUFFMAN != 0 {
+        output = timed(&mut times.entropy, || huffman_encode(&output));
+    } else if flags & FLAG_RANGE != 0 {
+        output = timed(&mut times.entropy, || range_encode(&output));
+    }
+
+    if flags & FLAG_CRC32 != 0 {
+        let mut checked = timed(&mut times.checksum, || crc32(input))
+            .to_le_bytes()
+            .to_vec();
+        checked.extend(output);
+        output = checked;
+    }
+    output
+}
+
+/// Runs `f`, adding the time it takes to `elapsed`. `wasm32-unknown-unknown` has no clock, and
+/// `Instant::now` panics there, so nothing is added on it.
+fn timed<T>(elapsed: &mut Duration, f: impl FnOnce() -> T) -> T {
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+        return f();
+    }
+    let start = Instant::now();
+    let result = f();
+    *elapsed += start.elapsed();
+    result
+}
+
+/// Passes reads or writes through to `inner`, adding the time they take to `elapsed`
+struct TimedIo<'a, T> {
+    inner: T,
+    elapsed: &'a mut Duration,
+}
+
+impl<T: io::Read> io::Read for TimedIo<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        timed(self.elapsed, || self.inner.read(buf))
+    }
+}
+
+/// Appends `bytes`, run-length encoded as selected by `flags`
+fn encode_runs(bytes: &[u8], flags: u8, output: &mut Vec<u8>) {
+    if flags & FLAG_RLE0 != 0 {
+        output.extend(rle0_encode(bytes));
+    } else if flags & FLAG_HYBRID_RLE != 0 {
+        output.extend(hybrid_rle_encode(bytes));
+    } else {
+        rle_write_bytes(output, bytes).expect("writing to a Vec cannot fail");
+    }
+}
+
+/// Reverses [`encode_runs`]
+fn decode_runs(
+    input: &[u8],
+    flags: u8,
+    counts: RunCounts,
+    limit: usize,
+) -> Result<Vec<u8>, BwtError> {
+    if flags & FLAG_RLE0 != 0 {
+        rle0_decode_limited(input, limit)
+    } else if flags & FLAG_HYBRID_RLE != 0 {
+        hybrid_rle_decode_limited(input, limit)
+    } else {
+        rle_read_bytes(input, counts, limit)
+    }
+}
+
+/// A block with every stage but the transform itself undone
+pub(crate) enum Transformed {
+    /// From [`BWTStr::forward_transform`]
+    Sentinel(BWTStr),
+    /// From [`bijective_forward`]
+    Bijective(Vec<u8>),
+}
+
+impl Transformed {
+    /// Size of the block's original data
+    pub(crate) fn original_len(&self) -> usize {
+        match self {
+            Transformed::Sentinel(transformed) => transformed.len() - 1,
+            Transformed::Bijective(transformed) => transformed.len(),
+        }
+    }
+
+    /// See [`BWTStr::run_length_histogram`]
+    pub(crate) fn run_length_histogram(&self) -> BTreeMap<usize, u64> {
+        match self {
+            Transformed::Sentinel(transformed) => transformed.run_length_histogram(),
+            Transformed::Bijective(transformed) => {
+                let mut histogram = BTreeMap::new();
+                for run in transformed.chunk_by(|a, b| a == b) {
+                    *histogram.entry(run.len()).or_insert(0) += 1;
+                }
+                histogram
+            }
+        }
+    }
+
+    /// Reverses the transform. A bijective transform can't be inconsistent, so `checked` only
+    /// matters for the sentinel one.
+    pub(crate) fn reverse(&self, checked: bool) -> Result<Vec<u8>, BwtError> {
+        match self {
+            Transformed::Sentinel(transformed) if checked => {
+                Ok(transformed.reverse_transform_checked()?.bytes().collect())
+            }
+            Transformed::Sentinel(transformed) => {
+                Ok(transformed.reverse_transform().bytes().collect())
+            }
+            Transformed::Bijective(transformed) => Ok(bijective_reverse(transformed)),
+        }
+    }
+}
+
+/// Reverses [`compress_block`], adding the time each stage takes to `times`. `counts` says how
+/// `(byte, count)` runs are stored, which depends on the format version.
+pub(crate) fn decompress_block(
+    input: &[u8],
+    flags: u8,
+    counts: RunCounts,
+    limit: usize,
+    options: &DecompressOptions,
+    times: &mut StageTimes,
+) -> Result<Vec<u8>, BwtError> {
+    let (transformed, expected_crc) = decode_block_timed(input, flags, counts, limit, times)?;
+    let original = timed(&mut times.transform, || {
+        transformed.reverse(options.checked_reverse)
+    })?;
+
+    if let Some(expected) = expected_crc.filter(|_| options.verify_checksum) {
+        let actual = timed(&mut times.checksum, || crc32(&original));
+        if actual != expected {
+            return Err(BwtError::ChecksumMismatch { expected, actual });
+        }
+    }
+    Ok(original)
+}
+
+/// Undoes every stage of [`compress_block`] but the BWT itself, returning the transformed string
+/// and the block's stored CRC-32, if any. Fails with [`BwtError::OutputTooLarge`] rather than
+/// decoding more than `limit` bytes.
+fn decode_block(
+    input: &[u8],
+    flags: u8,
+    counts: RunCounts,
+    limit: usize,
+) -> Result<(Transformed, Option<u32>), BwtError> {
+    decode_block_timed(input, flags, counts, limit, &mut StageTimes::default())
+}
+
+/// Like [`decode_block`], adding the time each stage takes to `times`
+fn decode_block_timed(
+    input: &[u8],
+    flags: u8,
+    counts: RunCounts,
+    limit: usize,
+    times: &mut StageTimes,
+) -> Result<(Transformed, Option<u32>), BwtError> {
+    let (expected_crc, input) = if flags & FLAG_CRC32 != 0 {
+        let (crc_bytes, rest) = input
+            .split_at_checked(CRC_BYTE_CNT)
+            .ok_or(BwtError::TruncatedStream)?;
+        (
+            Some(u32::from_le_bytes(crc_bytes.try_into().unwrap())),
+            rest,
+        )
+    } else {
+        (None, input)
+    };
+
+    let decoded;
+    let mut input = if flags & FLAG_HUFFMAN != 0 {
+        decoded = timed(&mut times.entropy, || huffman_decode(input))?;
+        &decoded[..]
+    } else if flags & FLAG_RANGE != 0 {
+        decoded = timed(&mut times.entropy, || range_decode(input))?;
+        &decoded[..]
+    } else {
+        input
+    };
+
+    if flags & FLAG_BIJECTIVE != 0 {
+        let mut transformed = timed(&mut times.runs, || decode_runs(input, flags, counts, limit))?;
+        if flags & FLAG_MTF != 0 {
+            transformed = timed(&mut times.mtf, || mtf_decode(&transformed));
+        }
+        return Ok((Transformed::Bijective(transformed), expected_crc));
+    }
+
+    let mut transformed = if flags & (FLAG_RLE0 | FLAG_HYBRID_RLE) != 0 {
+        let (sentinel_index_bytes, body) =
+            input
+                .split_at_checked(SENTINEL_INDEX_BYTE_CNT)
+                .ok_or(BwtError::UnexpectedEof {
+                    field: "sentinel index",
+                    offset: 0,
+                })?;
+        let bytes = timed(&mut times.runs, || decode_runs(body, flags, counts, limit))?;
+        let sentinel_index =
+            usize::try_from(u64::from_le_bytes(sentinel_index_bytes.try_into().unwrap()))
+                .ok()
+                .filter(|sentinel_index| *sentinel_index <= bytes.len())
+                .ok_or(BwtError::InvalidSentinelIndex)?;
+        BWTStr::new_with_sentinel(bytes, sentinel_index)
+    } else {
+        timed(&mut times.runs, || {
+            BWTStr::rle_read_counts(&mut input, counts, limit)
+        })?
+    };
+
+    if flags & FLAG_MTF != 0 {
+        transformed = timed(&mut times.mtf, || transformed.mtf_decode());
+    }
+
+    Ok((Transformed::Sentinel(transformed), expected_crc))
+}
+
+/// Where `done` has got to, as the index entry of the next block
+fn index_entry(done: &Progress) -> IndexEntry {
+    IndexEntry {
+        compressed_offset: done.compressed_len,
+        original_offset: done.original_len,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes from a small alphabet, so patterns recur, from a fixed seed
+    fn text(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                b"abc"[(state >> 16) as usize % 3]
+            })
+            .collect()
+    }
+
+    fn naive_positions(text: &[u8], pattern: &[u8]) -> Vec<u64> {
+        scan(text, pattern).map(|i| i as u64).collect()
+    }
+
+    fn compressed(input: &[u8], block_size: usize, bijective: bool) -> Vec<u8> {
+        compress_blocks(
+            input,
+            &Options {
+                block_size,
+                bijective,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn counts_matches_across_block_boundaries() {
+        let input = b"abracadabra abracadabra";
+        for block_size in [1, 2, 3, 5, 10, 11, 12, 100] {
+            let compressed = compressed(input, block_size, false);
+            assert_eq!(count_matches(&compressed, b"abracadabra").unwrap(), 2);
+            assert_eq!(count_matches(&compressed, b"abra").unwrap(), 4);
+            assert_eq!(count_matches(&compressed, b"a a").unwrap(), 1);
+            assert_eq!(count_matches(&compressed, b"zebra").unwrap(), 0);
+            assert_eq!(count_matches(&compressed, b"").unwrap(), 23);
+        }
+    }
+
+    #[test]
+    fn locates_matches_across_block_boundaries() {
+        let input = b"abracadabra abracadabra";
+        for block_size in [1, 2, 3, 5, 10, 11, 12, 100] {
+            let compressed = compressed(input, block_size, false);
+            let locate = |pattern| locate_matches(&compressed, pattern, 4).unwrap();
+            assert_eq!(locate(b"abra"), [0, 7, 12, 19]);
+            assert_eq!(locate(b"abracadabra"), [0, 12]);
+            assert_eq!(locate(b"a abr"), [10]);
+            assert_eq!(locate(b"zebra"), []);
+        }
+    }
+
+    #[test]
+    fn locates_like_a_scan_of_the_original() {
+        let input = text(2000, 11);
+        for block_size in [1, 7, 64, 500, 4096] {
+            for bijective in [false, true] {
+                let compressed = compressed(&input, block_size, bijective);
+                for pattern in [&b"a"[..], b"aa", b"abcab", b"cccc", b"abcabcabca"] {
+                    assert_eq!(
+                        locate_matches(&compressed, pattern, 3).unwrap(),
+                        naive_positions(&input, pattern),
+                        "block size {}, pattern {:?}",
+                        block_size,
+                        pattern
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn counts_like_a_scan_of_the_original() {
+        let input = text(2000, 7);
+        for block_size in [1, 7, 64, 500, 4096] {
+            for bijective in [false, true] {
+                let compressed = compressed(&input, block_size, bijective);
+                for pattern in [&b"a"[..], b"ab", b"abcab", b"cccc", b"abcabcabca"] {
+                    assert_eq!(
+                        count_matches(&compressed, pattern).unwrap(),
+                        naive_positions(&input, pattern).len() as u64,
+                        "block size {}, pattern {:?}",
+                        block_size,
+                        pattern
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_combination_of_stages_round_trips() {
+        let block_size = 64;
+        let inputs = [
+            Vec::new(),
+            vec![0],
+            text(block_size, 1),
+            text(block_size + 1, 2),
+            [
+                vec![0; 100],
+                vec![255; 100],
+                text(100, 3),
+                (0..=255).collect(),
+            ]
+            .concat(),
+        ];
+
+        for bits in 0..1 << 5 {
+            for entropy in Entropy::ALL {
+                let options = Options {
+                    block_size,
+                    mtf: bits & 1 != 0,
+                    rle0: bits & 2 != 0,
+                    hybrid_rle: bits & 4 != 0,
+                    checksum: bits & 8 != 0,
+                    bijective: bits & 16 != 0,
+                    entropy,
+                    ..Default::default()
+                };
+                for input in &inputs {
+                    let compressed = compress_blocks(input, &options);
+                    let decompressed =
+                        decompress_blocks(&compressed, &DecompressOptions::default());
+                    assert_eq!(decompressed.unwrap(), *input, "{:?}", options);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn resumed_streams_match_uninterrupted_ones() {
+        let input = text(5000, 5);
+        let options = Options {
+            block_size: 700,
+            threads: 1,
+            ..Default::default()
+        };
+        let mut whole = Vec::new();
+        let mut written = Vec::new();
+        write_stream(&input[..], &options, &mut whole, &[], &mut |done| {
+            written.push(done)
+        })
+        .unwrap();
+        // The last report is for the end marker and the index, after the last block
+        written.pop();
+        assert_eq!(written.len(), 8);
+
+        for stopped_after in 0..=written.len() {
+            let resumed = &written[..stopped_after];
+            let start = resumed.last().copied().unwrap_or_default();
+            // Part of the next block made it out before the stream was stopped
+            let mut stream = io::Cursor::new(whole[..start.compressed_len as usize + 10].to_vec());
+            let threads = Options {
+                threads: 3,
+                ..options.clone()
+            };
+            resume_stream(
+                &input[start.original_len as usize..],
+                &threads,
+                &mut stream,
+                resumed,
+                &mut |_| {},
+            )
+            .unwrap();
+            assert_eq!(stream.into_inner(), whole, "after {} blocks", stopped_after);
+        }
+
+        let mut other = io::Cursor::new(whole.clone());
+        let mtf = Options {
+            mtf: false,
+            ..options.clone()
+        };
+        assert!(matches!(
+            resume_stream(&input[700..], &mtf, &mut other, &written[..1], &mut |_| {}),
+            Err(BwtError::Io(_))
+        ));
+        assert!(matches!(
+            resume_stream(
+                &input[1400..],
+                &options,
+                &mut other,
+                &written[1..2],
+                &mut |_| {}
+            ),
+            Err(BwtError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn streamed_output_round_trips() {
+        let input = text(1000, 4);
+        let options = Options {
+            block_size: 100,
+            ..Default::default()
+        };
+        let chunks = input.chunks(33).map(<[u8]>::to_vec);
+        let streamed = CompressChunks::new(chunks, &options)
+            .flatten()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            decompress_blocks(&streamed, &DecompressOptions::default()).unwrap(),
+            input
+        );
+    }
+
+    #[test]
+    fn decompresses_ranges_at_block_boundaries() {
+        let input = text(1000, 5);
+        let compressed = compressed(&input, 100, false);
+        let range = |range: Range<u64>| {
+            let mut output = Vec::new();
+            read_range(
+                io::Cursor::new(&compressed),
+                range,
+                &DecompressOptions::default(),
+                &mut output,
+            )
+            .map(|()| output)
+        };
+
+        for (start, end) in [
+            (0, 0),
+            (0, 1),
+            (99, 101),
+            (100, 200),
+            (250, 750),
+            (999, 1000),
+            (1000, 1000),
+            (0, 1000),
+        ] {
+            assert_eq!(
+                range(start..end).unwrap(),
+                input[start as usize..end as usize]
+            );
+        }
+        assert!(matches!(
+            range(0..1001),
+            Err(BwtError::RangeOutOfBounds {
+                end: 1001,
+                len: 1000
+            })
+        ));
+
+        let unchecked = compress_blocks(
+            &input,
+            &Options {
+                checksum: false,
+                ..Default::default()
+            },
+        );
+        assert!(matches!(
+            read_range(
+                io::Cursor::new(&unchecked),
+                0..1,
+                &DecompressOptions::default(),
+                &mut Vec::new()
+            ),
+            Err(BwtError::NoIndex)
+        ));
+    }
+
+    #[test]
+    fn decompresses_concatenated_streams() {
+        let (first, second) = (text(300, 6), text(50, 7));
+        let appended = [
+            compressed(&first, 100, false),
+            compressed(&second, 100, true),
+        ]
+        .concat();
+
+        assert_eq!(
+            decompress_blocks(&appended, &DecompressOptions::default()).unwrap(),
+            [first, second].concat()
+        );
+        let single = DecompressOptions {
+            concatenated: false,
+            ..Default::default()
+        };
+        assert!(matches!(
+            decompress_blocks(&appended, &single),
+            Err(BwtError::CorruptStream("data after end of stream"))
+        ));
+    }
+}
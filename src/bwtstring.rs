@@ -1,258 +1,296 @@
-use std::cmp;
-use std::collections::VecDeque;
-use std::fmt;
+use alloc::collections::BTreeMap;
+use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp;
+use core::fmt;
+#[cfg(feature = "std")]
 use std::io;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub(crate) enum BWTByte {
+use crate::log::trace;
+use crate::suffix::sorted_suffixes;
+use crate::{BwtError, SuffixSort};
+
+/// A single symbol of a [`BWTStr`]. The sentinel sorts before every byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BWTByte {
     Byte(u8),
     Sentinel,
 }
 
+/// A string of bytes containing exactly one [`BWTByte::Sentinel`], along with its position.
+///
+/// Only the bytes are stored; the sentinel is implied by `sentinel_index`, so a string takes one
+/// byte per symbol.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub(crate) struct BWTStr {
-    pub(crate) inner: VecDeque<BWTByte>,
+pub struct BWTStr {
+    /// Every symbol but the sentinel, in order
+    bytes: Vec<u8>,
     sentinel_index: usize,
 }
 
 impl BWTStr {
-    pub fn new(inner: impl Into<VecDeque<u8>>) -> Self {
-        let mut inner = inner
-            .into()
-            .iter()
-            .map(|b| BWTByte::Byte(*b))
-            .collect::<VecDeque<_>>();
-        inner.push_back(BWTByte::Sentinel);
-        let len = inner.len();
-
-        Self {
-            inner,
-            sentinel_index: len,
-        }
+    /// Wraps `inner`, appending the sentinel to the end.
+    pub fn new(inner: impl Into<Vec<u8>>) -> Self {
+        let inner = inner.into();
+        let sentinel_index = inner.len();
+        Self::new_with_sentinel(inner, sentinel_index)
     }
 
-    pub fn new_with_sentinel(inner: impl Into<VecDeque<u8>>, sentinel_index: usize) -> Self {
-        let mut inner = inner
-            .into()
-            .iter()
-            .map(|b| BWTByte::Byte(*b))
-            .collect::<VecDeque<_>>();
-        inner.insert(sentinel_index, BWTByte::Sentinel);
+    /// Wraps `inner`, inserting the sentinel at `sentinel_index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sentinel_index` is greater than `inner`'s length.
+    pub fn new_with_sentinel(inner: impl Into<Vec<u8>>, sentinel_index: usize) -> Self {
+        let bytes = inner.into();
+        assert!(
+            sentinel_index <= bytes.len(),
+            "sentinel index {} is past the end of {} bytes",
+            sentinel_index,
+            bytes.len()
+        );
 
         Self {
-            inner,
+            bytes,
             sentinel_index,
         }
     }
 
+    /// Computes the Burrows-Wheeler transform: the last column of the sorted rotation matrix.
+    ///
+    /// ```
+    /// use rust_bwt::BWTStr;
+    ///
+    /// let transformed = BWTStr::new(b"banana".to_vec()).forward_transform();
+    /// assert_eq!(transformed.to_bytes(), b"annbaa");
+    /// assert_eq!(transformed.reverse_transform().to_bytes(), b"banana");
+    /// ```
     pub fn forward_transform(&self) -> Self {
-        let rotations = self.all_rotations_sorted();
-
-        let inner = rotations
-            .iter()
-            .filter_map(|rotation| {
-                if rotation.sentinel_index == self.len() {
-                    None
-                } else {
-                    Some(rotation.inner.iter().last().unwrap().clone())
-                }
-            })
-            .collect();
+        self.forward_transform_with(SuffixSort::Auto)
+    }
 
-        let sentinel_index = rotations
-            .iter()
-            .position(|rotation| rotation.sentinel_index == self.len())
-            .unwrap();
+    /// Like [`forward_transform`](Self::forward_transform), sorting the rotations with
+    /// `suffix_sort`. The result is the same whichever is used.
+    pub fn forward_transform_with(&self, suffix_sort: SuffixSort) -> Self {
+        // The bytes, starting just after the sentinel, so that it comes last. Since the sentinel
+        // is unique and sorts first, every rotation then compares like its suffix up to it.
+        let rotated = [
+            &self.bytes[self.sentinel_index..],
+            &self.bytes[..self.sentinel_index],
+        ]
+        .concat();
+
+        // Each row's last symbol is the one just before the rotation's start. Only the rotation
+        // starting at 0 has the sentinel there.
+        let mut bytes = Vec::with_capacity(rotated.len());
+        let mut sentinel_index = 0;
+        for (row, start) in sorted_suffixes(&rotated, suffix_sort)
+            .into_iter()
+            .enumerate()
+        {
+            match start.checked_sub(1) {
+                Some(before) => bytes.push(rotated[before]),
+                None => sentinel_index = row,
+            }
+        }
+        trace!(
+            "forward transform of {} bytes: sentinel at {}",
+            bytes.len(),
+            sentinel_index
+        );
 
         Self {
-            inner,
-            sentinel_index: sentinel_index,
+            bytes,
+            sentinel_index,
         }
     }
 
+    /// Inverts [`forward_transform`](Self::forward_transform). The result is the original bytes
+    /// with the sentinel at the end, as [`new`](Self::new) builds it.
     pub fn reverse_transform(&self) -> Self {
-        enum Column {
-            Left,
-            Right,
-        }
         use BWTByte::*;
-        use Column::*;
 
-        let right = self.clone();
-        let left = right.as_sorted();
+        // LF-mapping: the rank-th occurrence of b in the last column is the rank-th occurrence of
+        // b in the (sorted) first column, which sits at row C[b] + rank
+        let c_array = self.c_array();
         let ranks = self.rank_vec();
 
-        let mut inner = VecDeque::new();
+        // The walk visits the original bytes from last to first, ending at the sentinel
+        let mut bytes = vec![0; self.bytes.len()];
+        let mut filled = bytes.len();
 
-        let mut col = Left;
         let mut i = 0;
-        loop {
-            match (&col, &right.inner[i]) {
-                (_, Sentinel) => {
-                    break;
-                }
-                (Left, _) => {
-                    col = Right;
-                }
-                (Right, Byte(b)) => {
-                    inner.push_front(Byte(*b));
-
-                    let rank = ranks[i];
-
-                    i = left
-                        .inner
-                        .iter()
-                        .enumerate()
-                        .filter(|(_, ib)| Byte(*b) == **ib)
-                        .nth(rank)
-                        .unwrap()
-                        .0;
-
-                    col = Left;
-                }
-            }
+        while let Byte(b) = self.symbol(i) {
+            filled -= 1;
+            bytes[filled] = b;
+            i = c_array[b as usize + 1] + ranks[i];
         }
 
-        let sentinel_index = inner.len();
+        let sentinel_index = bytes.len();
+        trace!(
+            "reverse transform of {} bytes from sentinel at {}",
+            sentinel_index,
+            self.sentinel_index
+        );
         Self {
-            inner,
+            bytes,
             sentinel_index,
         }
     }
 
-    pub fn rle_read<F: io::Read + io::Seek>(f: &mut F) -> io::Result<Self> {
-        use io::{BufRead, BufReader, Read, SeekFrom};
+    /// Like [`reverse_transform`](Self::reverse_transform), but checks the LF-mapping walk
+    /// instead of trusting the input. LF-mapping is a permutation of the rows, so the walk always
+    /// ends at the sentinel; a corrupted column shows up as it getting there too soon, on a cycle
+    /// that misses some of the bytes. That fails with [`BwtError::InconsistentTransform`] naming
+    /// the step and the sentinel's row, rather than producing garbage.
+    pub fn reverse_transform_checked(&self) -> Result<Self, BwtError> {
         use BWTByte::*;
 
-        const READ_BUFFER_CAP: usize = 1 << 16; // 64 KiB
-        const USIZE_BYTE_CNT: u32 = usize::BITS / 8;
+        let c_array = self.c_array();
+        let ranks = self.rank_vec();
 
-        f.seek(SeekFrom::Start(0))?;
+        // Collected from last to first, then reversed
+        let mut bytes = Vec::with_capacity(self.bytes.len());
 
-        // Create BufReader
-        let mut reader = BufReader::with_capacity(READ_BUFFER_CAP, f);
+        let mut i = 0;
+        while let Byte(b) = self.symbol(i) {
+            bytes.push(b);
+            i = c_array[b as usize + 1] + ranks[i];
+        }
 
-        // Read sentinel index bytes and parse into usize
-        let mut sentinel_index_bytes = [0u8; USIZE_BYTE_CNT as _];
-        assert_eq!(reader.read(&mut sentinel_index_bytes)?, 8);
-        let sentinel_index = usize::from_le_bytes(sentinel_index_bytes);
+        if bytes.len() != self.bytes.len() {
+            return Err(BwtError::InconsistentTransform {
+                step: bytes.len(),
+                row: i,
+            });
+        }
 
-        let mut inner = VecDeque::new();
-        let mut count_bytes = [0u8; 2];
+        bytes.reverse();
+        let sentinel_index = bytes.len();
+        trace!(
+            "checked reverse transform of {} bytes from sentinel at {}",
+            sentinel_index,
+            self.sentinel_index
+        );
+        Ok(Self {
+            bytes,
+            sentinel_index,
+        })
+    }
 
-        // Loop until end of input
-        while let Ok(Some(byte)) = reader.fill_buf().map(|buf| buf.get(0).cloned()) {
-            reader.consume(1);
+    /// Reads a transformed string previously written by [`rle_write`](Self::rle_write), starting
+    /// at the reader's current position. The reader is consumed front to back, so it doesn't need
+    /// to be seekable.
+    #[cfg(feature = "std")]
+    pub fn rle_read<F: io::Read>(f: &mut F) -> Result<Self, BwtError> {
+        Self::rle_read_counts(f, RunCounts::Varint, usize::MAX)
+    }
 
-            // Read run-length
-            reader.read_exact(&mut count_bytes)?;
-            let count = u16::from_le_bytes(count_bytes);
+    /// Like [`rle_read`](Self::rle_read), but with run lengths stored as `counts`, and failing
+    /// with [`BwtError::OutputTooLarge`] instead of reading more than `limit` bytes
+    #[cfg(feature = "std")]
+    pub(crate) fn rle_read_counts<F: io::Read>(
+        f: &mut F,
+        counts: RunCounts,
+        limit: usize,
+    ) -> Result<Self, BwtError> {
+        use io::BufReader;
 
-            inner.extend(std::iter::repeat(Byte(byte)).take(count as usize));
-        }
+        const READ_BUFFER_CAP: usize = 1 << 16; // 64 KiB
 
-        // Insert sentintel
-        inner.insert(sentinel_index, Sentinel);
+        // Create BufReader
+        let mut reader = BufReader::with_capacity(READ_BUFFER_CAP, f);
 
-        Ok(Self {
-            sentinel_index,
-            inner,
-        })
+        // Read the fixed-width (u64) sentinel index and make sure it fits in this platform's usize
+        let mut sentinel_index_bytes = [0u8; SENTINEL_INDEX_BYTE_CNT];
+        read_field(&mut reader, &mut sentinel_index_bytes, "sentinel index", 0)?;
+        let sentinel_index = usize::try_from(u64::from_le_bytes(sentinel_index_bytes))
+            .map_err(|_| BwtError::InvalidSentinelIndex)?;
+
+        let bytes = read_runs(&mut reader, counts, limit, SENTINEL_INDEX_BYTE_CNT as u64)?;
+
+        // Insert sentintel
+        if sentinel_index > bytes.len() {
+            return Err(BwtError::InvalidSentinelIndex);
+        }
+        Ok(Self::new_with_sentinel(bytes, sentinel_index))
     }
 
+    /// Writes the sentinel index as a little-endian `u64`, followed by `(byte, count)` runs. Each
+    /// count is an unsigned LEB128 varint: seven bits per byte, least significant group first, with
+    /// the top bit set on every byte but the last. Runs up to 127 long cost one byte of count.
+    ///
+    /// ```
+    /// use rust_bwt::BWTStr;
+    ///
+    /// let transformed = BWTStr::new(b"banana".to_vec()).forward_transform();
+    /// let mut written = Vec::new();
+    /// transformed.rle_write(&mut written)?;
+    /// assert_eq!(BWTStr::rle_read(&mut &written[..])?, transformed);
+    /// # Ok::<(), rust_bwt::BwtError>(())
+    /// ```
+    #[cfg(feature = "std")]
     pub fn rle_write<F: io::Write>(&self, f: &mut F) -> io::Result<()> {
         use io::{BufWriter, Write};
-        use BWTByte::*;
 
         // First, create a BufWriter
         let mut writer = BufWriter::new(f);
 
-        // Write first the position of the sentinel character
-        writer.write(self.sentinel_index.to_le_bytes().as_slice())?;
+        // Write first the position of the sentinel character, always as a u64 so archives are
+        // portable between 32- and 64-bit platforms
+        write_sentinel_index(&mut writer, self.sentinel_index)?;
 
-        // Now, the run-length encoding
-        let mut iter = self.inner.iter().peekable();
-        while let Some(b) = iter.peek() {
-            match **b {
-                Byte(b) => {
-                    let mut cnt = 1_u16;
-                    iter.next(); // Consume this occurrence of b
-
-                    while let Some(ibwt) = iter.peek() {
-                        if ibwt.is_byte_and(|byte| byte == &b) {
-                            cnt += 1;
-                            iter.next();
-                        } else {
-                            break;
-                        }
-
-                        // We only write two bytes for the run-length
-                        if cnt == u16::MAX {
-                            writer.write(&[b])?;
-                            writer.write(cnt.to_le_bytes().as_slice())?;
-                            cnt = 0;
-                        }
-                    }
-
-                    // Byte b occurred cnt times in a row before we got to some other byte
-                    // Write the byte first, then two bytes for the number of times we saw it
-                    writer.write(&[b])?;
-                    writer.write(cnt.to_le_bytes().as_slice())?;
-                }
-                Sentinel => {
-                    iter.next();
-                    continue;
-                }
-            }
-        }
+        // Now, the run-length encoding. The sentinel isn't written, but still ends any run it
+        // interrupts.
+        let (before, after) = self.bytes.split_at(self.sentinel_index);
+        rle_write_bytes(&mut writer, before)?;
+        rle_write_bytes(&mut writer, after)?;
 
         writer.flush()?;
         Ok(())
     }
 
-    fn rotate(&mut self) {
-        if self.inner.is_empty() {
-            return;
+    /// Maps each run length to how many runs of equal bytes have that length, counting runs the
+    /// way [`rle_write`](Self::rle_write) does: the sentinel is skipped and ends any run it
+    /// interrupts, and a run of any length counts once, as one varint count would store it. Call it
+    /// on a transformed string to see how well the run-length stage will do.
+    pub fn run_length_histogram(&self) -> BTreeMap<usize, u64> {
+        let mut histogram = BTreeMap::new();
+
+        let (before, after) = self.bytes.split_at(self.sentinel_index);
+        for run in before
+            .chunk_by(|a, b| a == b)
+            .chain(after.chunk_by(|a, b| a == b))
+        {
+            *histogram.entry(run.len()).or_insert(0) += 1;
         }
 
-        // Perform rotation
-        let front = self.inner.pop_front().unwrap();
-        self.inner.push_back(front);
-
-        // Update sentinel_index
-        self.sentinel_index = (self.sentinel_index + self.len() - 1) % self.len();
-    }
-
-    fn all_rotations_sorted(&self) -> Vec<BWTStr> {
-        let mut rotations = self.all_rotations();
-        Self::lex_sort(&mut rotations);
-        rotations
+        histogram
     }
 
-    fn all_rotations(&self) -> Vec<BWTStr> {
-        let mut rotations = Vec::new();
-        let mut cur = self.clone();
-
-        rotations.push(cur.clone());
-        for _ in 0..self.len() {
-            cur.rotate();
-            rotations.push(cur.clone());
+    /// The C array used by LF-mapping and FM-index backward search: for each symbol, the number of
+    /// symbols in this string that sort strictly before it. Equivalently, the row of the sorted
+    /// first column where that symbol first appears.
+    ///
+    /// Slot 0 is the sentinel (always 0, since it sorts first) and slot `b + 1` is byte `b`. It only
+    /// depends on symbol counts, so it is the same for a string and its transform.
+    pub fn c_array(&self) -> [usize; Self::BYTE_RANGE + 1] {
+        let mut counts = [0_usize; Self::BYTE_RANGE + 1];
+
+        counts[0] = 1;
+        for b in &self.bytes {
+            counts[*b as usize + 1] += 1;
         }
-        rotations
-    }
-
-    fn lex_sort(bwt_string_vec: &mut Vec<BWTStr>) {
-        bwt_string_vec.sort_by(|a, b| a.inner.iter().cmp(b.inner.iter()));
-    }
-
-    fn as_sorted(&self) -> Self {
-        let mut inner = self.inner.clone();
-        inner.make_contiguous().sort_by(|a, b| a.cmp(&b));
 
-        Self {
-            inner,
-            sentinel_index: 0,
+        let mut c_array = [0_usize; Self::BYTE_RANGE + 1];
+        let mut total = 0;
+        for (c, count) in c_array.iter_mut().zip(counts) {
+            *c = total;
+            total += count;
         }
+
+        c_array
     }
 
     fn rank_vec(&self) -> Vec<usize> {
@@ -261,13 +299,13 @@ impl BWTStr {
         let mut num_occurrences = [0_usize; Self::BYTE_RANGE];
         let mut ranks = Vec::with_capacity(self.len());
 
-        for bwt_byte in &self.inner {
+        for bwt_byte in self.symbols() {
             match bwt_byte {
                 Sentinel => ranks.push(0), // We only ever have one sentinel
                 Byte(b) => {
-                    let rank = num_occurrences[*b as usize];
+                    let rank = num_occurrences[b as usize];
                     ranks.push(rank);
-                    num_occurrences[*b as usize] += 1;
+                    num_occurrences[b as usize] += 1;
                 }
             }
         }
@@ -275,51 +313,120 @@ impl BWTStr {
         ranks
     }
 
+    /// The symbol at `i`, which must be less than [`len`](Self::len)
+    fn symbol(&self, i: usize) -> BWTByte {
+        match i.cmp(&self.sentinel_index) {
+            cmp::Ordering::Less => BWTByte::Byte(self.bytes[i]),
+            cmp::Ordering::Equal => BWTByte::Sentinel,
+            cmp::Ordering::Greater => BWTByte::Byte(self.bytes[i - 1]),
+        }
+    }
+
+    /// The symbols of this string in order, the sentinel included.
+    pub fn symbols(&self) -> impl ExactSizeIterator<Item = BWTByte> + '_ {
+        (0..self.len()).map(|i| self.symbol(i))
+    }
+
+    /// The bytes of this string, without the sentinel
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Move-to-front encodes the bytes of this string (see [`mtf_encode`](crate::mtf_encode)),
+    /// leaving the sentinel where it is.
+    pub fn mtf_encode(&self) -> Self {
+        Self::new_with_sentinel(crate::mtf_encode(&self.bytes), self.sentinel_index)
+    }
+
+    /// Reverses [`mtf_encode`](Self::mtf_encode)
+    pub fn mtf_decode(&self) -> Self {
+        Self::new_with_sentinel(crate::mtf_decode(&self.bytes), self.sentinel_index)
+    }
+
+    /// The bytes of this string, skipping the sentinel
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.bytes.clone()
+    }
+
+    /// Like [`to_bytes`](Self::to_bytes), but yields the bytes one at a time, without copying
+    /// them into a new buffer first.
+    ///
+    /// ```
+    /// use rust_bwt::BWTStr;
+    ///
+    /// let original = BWTStr::new(b"banana".to_vec()).forward_transform().reverse_transform();
+    /// let bytes = original.bytes();
+    /// assert_eq!(bytes.len(), 6);
+    /// assert!(bytes.eq(b"banana".iter().copied()));
+    /// ```
+    pub fn bytes(self) -> impl ExactSizeIterator<Item = u8> {
+        self.bytes.into_iter()
+    }
+
+    /// Splits this string into its bytes, without the sentinel, and the sentinel's position. The
+    /// inverse of [`new_with_sentinel`](Self::new_with_sentinel).
+    pub fn into_transformed_parts(self) -> (Vec<u8>, usize) {
+        (self.bytes, self.sentinel_index)
+    }
+
+    /// The position of the sentinel within [`symbols`](Self::symbols)
+    pub fn sentinel_index(&self) -> usize {
+        self.sentinel_index
+    }
+
+    /// The number of symbols, counting the sentinel
     pub fn len(&self) -> usize {
-        self.inner.len()
+        self.bytes.len() + 1
+    }
+
+    /// Always false: there's at least the sentinel
+    pub fn is_empty(&self) -> bool {
+        false
     }
 
     const BYTE_RANGE: usize = 256;
 }
 
+/// The sentinel index is always serialized as a little-endian `u64`, regardless of platform
+#[cfg(feature = "std")]
+const SENTINEL_INDEX_BYTE_CNT: usize = (u64::BITS / 8) as usize;
+
 impl fmt::Display for BWTStr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let queue = self
-            .inner
-            .clone()
-            .iter()
+        let symbols = self
+            .symbols()
             .map(|bwt_byte| match bwt_byte {
                 BWTByte::Sentinel => "$".into(),
                 BWTByte::Byte(b) => b.to_string(),
             })
             .collect::<Vec<_>>();
 
-        write!(f, "{}", queue.join(", "))
+        write!(f, "{}", symbols.join(", "))
     }
 }
 
 impl cmp::PartialOrd for BWTByte {
     fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl cmp::Ord for BWTByte {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
         use cmp::Ordering::*;
         use BWTByte::*;
 
-        Some(match (self, other) {
+        match (self, other) {
             (Sentinel, Sentinel) => Equal,
             (Sentinel, Byte(_)) => Less,
             (Byte(_), Sentinel) => Greater,
-            (Byte(a), Byte(b)) => a.cmp(&b),
-        })
-    }
-}
-
-impl cmp::Ord for BWTByte {
-    fn cmp(&self, other: &Self) -> cmp::Ordering {
-        self.partial_cmp(other).unwrap()
+            (Byte(a), Byte(b)) => a.cmp(b),
+        }
     }
 }
 
 impl BWTByte {
-    fn is_sentinel(&self) -> bool {
+    pub fn is_sentinel(&self) -> bool {
         use BWTByte::*;
 
         match self {
@@ -327,13 +434,321 @@ impl BWTByte {
             Sentinel => true,
         }
     }
+}
 
-    fn is_byte_and<P: FnOnce(&u8) -> bool>(&self, predicate: P) -> bool {
-        use BWTByte::*;
+// Every field of the RLE format goes through these, so each is always written in full
 
-        match self {
-            Sentinel => false,
-            Byte(b) => predicate(b),
+#[cfg(feature = "std")]
+fn write_sentinel_index<W: io::Write>(writer: &mut W, sentinel_index: usize) -> io::Result<()> {
+    writer.write_all((sentinel_index as u64).to_le_bytes().as_slice())
+}
+
+/// Writes the byte first, then the number of times it repeats as a varint
+#[cfg(feature = "std")]
+fn write_run<W: io::Write>(writer: &mut W, b: u8, mut cnt: u64) -> io::Result<()> {
+    // A u64 takes at most ten 7-bit groups
+    let mut run = [0u8; 11];
+    run[0] = b;
+    let mut len = 1;
+    loop {
+        let group = (cnt & 0x7F) as u8;
+        cnt >>= 7;
+        if cnt == 0 {
+            run[len] = group;
+            len += 1;
+            break;
+        }
+        run[len] = group | 0x80;
+        len += 1;
+    }
+    writer.write_all(&run[..len])
+}
+
+/// Writes `bytes` as `(byte, varint count)` runs, like [`BWTStr::rle_write`] but with no sentinel
+/// index, for transforms that have no sentinel
+#[cfg(feature = "std")]
+pub(crate) fn rle_write_bytes<W: io::Write>(writer: &mut W, bytes: &[u8]) -> io::Result<()> {
+    for run in bytes.chunk_by(|a, b| a == b) {
+        write_run(writer, run[0], run.len() as u64)?;
+    }
+    Ok(())
+}
+
+/// Reverses [`rle_write_bytes`], failing with [`BwtError::OutputTooLarge`] instead of reading more
+/// than `limit` bytes
+#[cfg(feature = "std")]
+pub(crate) fn rle_read_bytes(
+    mut input: &[u8],
+    counts: RunCounts,
+    limit: usize,
+) -> Result<Vec<u8>, BwtError> {
+    read_runs(&mut input, counts, limit, 0)
+}
+
+/// Reads `(byte, count)` runs until the end of `reader`. `offset` is where the first run starts, for
+/// error messages.
+#[cfg(feature = "std")]
+fn read_runs<R: io::BufRead>(
+    reader: &mut R,
+    counts: RunCounts,
+    limit: usize,
+    mut offset: u64,
+) -> Result<Vec<u8>, BwtError> {
+    let mut bytes = Vec::new();
+
+    // Loop until end of input
+    while let Some(byte) = reader.fill_buf()?.first().cloned() {
+        reader.consume(1);
+        offset += 1;
+
+        // Read run-length
+        let count = match counts {
+            RunCounts::U16 => {
+                let mut count_bytes = [0u8; 2];
+                read_field(reader, &mut count_bytes, "run count", offset)?;
+                offset += count_bytes.len() as u64;
+                u16::from_le_bytes(count_bytes) as usize
+            }
+            RunCounts::Varint => {
+                let count_offset = offset;
+                let mut count = 0_u64;
+                let mut shift = 0;
+                loop {
+                    let mut varint_byte = [0u8];
+                    read_field(reader, &mut varint_byte, "run count", count_offset)?;
+                    offset += 1;
+                    let [varint_byte] = varint_byte;
+
+                    let bits = (varint_byte & 0x7F) as u64;
+                    if shift >= u64::BITS || (bits << shift) >> shift != bits {
+                        return Err(BwtError::CorruptStream("run length is too long"));
+                    }
+                    count |= bits << shift;
+                    shift += 7;
+
+                    if varint_byte & 0x80 == 0 {
+                        break;
+                    }
+                }
+                usize::try_from(count)
+                    .map_err(|_| BwtError::CorruptStream("run length is too long"))?
+            }
+        };
+        if count == 0 {
+            // Run writers never emit one, so the stream is damaged
+            return Err(BwtError::CorruptStream("zero-length run"));
+        }
+        if count > limit - bytes.len() {
+            return Err(BwtError::OutputTooLarge {
+                limit: limit as u64,
+            });
+        }
+
+        bytes.extend(core::iter::repeat_n(byte, count));
+    }
+
+    Ok(bytes)
+}
+
+/// Fills `buf`. A stream that ends mid-field is truncated, not an I/O failure. `offset` is where
+/// the field starts.
+#[cfg(feature = "std")]
+fn read_field<R: io::Read>(
+    reader: &mut R,
+    buf: &mut [u8],
+    field: &'static str,
+    offset: u64,
+) -> Result<(), BwtError> {
+    reader.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => BwtError::UnexpectedEof { field, offset },
+        _ => BwtError::Io(e),
+    })
+}
+
+/// How [`BWTStr::rle_read_counts`] expects run lengths to be stored
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RunCounts {
+    /// Fixed-width little-endian `u16`s, with longer runs split up. Written by format version 1
+    /// and everything before it.
+    U16,
+    /// LEB128 varints, as [`BWTStr::rle_write`] writes now
+    Varint,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `count` inputs of random lengths over random alphabets, from a fixed seed, after the edge
+    /// cases: empty, a single byte, all one byte, and every byte value
+    fn generated_inputs(count: usize) -> Vec<Vec<u8>> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 32) as usize
+        };
+
+        let mut inputs = vec![Vec::new(), vec![0], vec![0xFF], vec![7; 1000]];
+        inputs.extend((0..count).map(|_| {
+            let len = match next() % 4 {
+                0 => next() % 8,
+                1 => next() % 100,
+                _ => next() % 3000,
+            };
+            let alphabet = [1, 2, 3, 4, 26, 256][next() % 6];
+            let base = next() % (257 - alphabet);
+            (0..len).map(|_| (base + next() % alphabet) as u8).collect()
+        }));
+        inputs
+    }
+
+    #[test]
+    fn generated_inputs_round_trip() {
+        for input in generated_inputs(300) {
+            let transformed = BWTStr::new(input.clone()).forward_transform();
+            assert_eq!(transformed.reverse_transform().to_bytes(), input);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "sentinel index 7 is past the end of 6 bytes")]
+    fn rejects_a_sentinel_past_the_end() {
+        BWTStr::new_with_sentinel(b"annbaa".to_vec(), 7);
+    }
+
+    #[test]
+    fn counts_runs_around_the_sentinel() {
+        // banana transforms to "annb$aa": runs a, nn and b, then aa after the sentinel
+        let transformed = BWTStr::new(b"banana").forward_transform();
+        assert_eq!(
+            transformed.run_length_histogram(),
+            BTreeMap::from([(1, 2), (2, 2)])
+        );
+
+        // A sentinel inside a run splits it in two
+        let transformed = BWTStr::new_with_sentinel(b"aaaaa".to_vec(), 2);
+        assert_eq!(
+            transformed.run_length_histogram(),
+            BTreeMap::from([(2, 1), (3, 1)])
+        );
+    }
+
+    #[test]
+    fn round_trips() {
+        for input in [
+            &b""[..],
+            b"a",
+            b"banana",
+            b"mississippi",
+            b"\x00\xff\x00\xff\x00",
+        ] {
+            for suffix_sort in [SuffixSort::Auto, SuffixSort::SaIs, SuffixSort::Doubling] {
+                let transformed = BWTStr::new(input).forward_transform_with(suffix_sort);
+                assert_eq!(transformed.reverse_transform().to_bytes(), input);
+                assert_eq!(
+                    transformed.reverse_transform_checked().unwrap().to_bytes(),
+                    input
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn rle_round_trips() {
+        let long_run = [vec![b'a'; 200], vec![b'b'; 20_000]].concat();
+        for input in [&b""[..], b"banana", b"\x00\x00\x00", &long_run] {
+            let transformed = BWTStr::new(input).forward_transform();
+            let mut written = Vec::new();
+            transformed.rle_write(&mut written).unwrap();
+            assert_eq!(BWTStr::rle_read(&mut &written[..]).unwrap(), transformed);
+        }
+
+        let mut written = Vec::new();
+        rle_write_bytes(&mut written, &long_run).unwrap();
+        // Two runs, the second with a three-byte count
+        assert_eq!(written, [b'a', 200, 1, b'b', 0xA0, 0x9C, 0x01]);
+        assert_eq!(
+            rle_read_bytes(&written, RunCounts::Varint, usize::MAX).unwrap(),
+            long_run
+        );
+        assert!(matches!(
+            rle_read_bytes(&written, RunCounts::Varint, 20_199),
+            Err(BwtError::OutputTooLarge { limit: 20_199 })
+        ));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn rle_read_rejects_malformed_runs() {
+        let read = |runs: &[u8]| rle_read_bytes(runs, RunCounts::Varint, usize::MAX);
+        for truncated in [&b"a"[..], &[b'a', 0x80]] {
+            assert!(matches!(
+                read(truncated),
+                Err(BwtError::UnexpectedEof {
+                    field: "run count",
+                    offset: 1
+                })
+            ));
+        }
+        assert!(matches!(
+            read(&[b'a', 0]),
+            Err(BwtError::CorruptStream("zero-length run"))
+        ));
+        // A count that doesn't fit in 64 bits
+        let mut too_long = vec![b'a'];
+        too_long.extend([0xFF; 9]);
+        too_long.push(0x7F);
+        assert!(matches!(
+            read(&too_long),
+            Err(BwtError::CorruptStream("run length is too long"))
+        ));
+
+        // A sentinel index past the end of the bytes
+        let mut written = 3_u64.to_le_bytes().to_vec();
+        written.extend([b'a', 2]);
+        assert!(matches!(
+            BWTStr::rle_read(&mut &written[..]),
+            Err(BwtError::InvalidSentinelIndex)
+        ));
+    }
+
+    #[test]
+    fn checked_reverse_rejects_a_short_cycle() {
+        // "banana" transforms to "annbaa" with the sentinel at 4. Swapping the first two bytes
+        // closes the walk's cycle at the sentinel's row after "banan", missing a byte.
+        let corrupted = BWTStr::new_with_sentinel(b"nanbaa".to_vec(), 4);
+        assert!(matches!(
+            corrupted.reverse_transform_checked(),
+            Err(BwtError::InconsistentTransform { step: 5, row: 4 })
+        ));
+    }
+
+    #[test]
+    fn checked_reverse_accepts_only_whole_cycles() {
+        let transformed = BWTStr::new(b"abracadabra".to_vec()).forward_transform();
+        let (bytes, sentinel_index) = transformed.into_transformed_parts();
+        let mut rejected = 0;
+        for a in 0..bytes.len() {
+            for b in a + 1..bytes.len() {
+                let mut swapped = bytes.clone();
+                swapped.swap(a, b);
+                let corrupted = BWTStr::new_with_sentinel(swapped, sentinel_index);
+                match corrupted.reverse_transform_checked() {
+                    // A swap that keeps a single cycle is the transform of some other string
+                    Ok(reversed) => assert_eq!(reversed.forward_transform(), corrupted),
+                    Err(BwtError::InconsistentTransform { step, row }) => {
+                        assert!(step < bytes.len());
+                        assert_eq!(row, sentinel_index);
+                        rejected += 1;
+                    }
+                    Err(e) => panic!("unexpected error {e:?}"),
+                }
+            }
         }
+        assert!(rejected > 0);
     }
 }
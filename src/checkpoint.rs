@@ -0,0 +1,149 @@
+//! `compress --checkpoint` and `--resume`: a file next to the output (`out.bwt.checkpoint`) that
+//! records each block once it's on disk, so a long run that crashes or is interrupted can carry on
+//! after the last block it finished instead of starting over.
+//!
+//! A checkpoint is [`MAGIC`] and the block size as a little-endian `u64`, then for each block the
+//! progress reported after it: the blocks, original bytes and compressed bytes so far, each a
+//! little-endian `u64`. These are the block index entries of the blocks after it. A record cut
+//! short by a crash is ignored.
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use rust_bwt::Progress;
+
+const MAGIC: &[u8; 4] = b"RBWK";
+
+const RECORD_LEN: usize = 3 * 8;
+
+/// Path of the checkpoint of `output`
+pub(crate) fn path(output: &Path) -> PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".checkpoint");
+    path.into()
+}
+
+/// A checkpoint being written
+pub(crate) struct Checkpoint {
+    path: PathBuf,
+    file: File,
+    /// Another handle to the output, to sync it with before recording a block
+    output: File,
+    blocks: u64,
+    /// The first error recording a block, reported once compression is done
+    failed: Option<io::Error>,
+}
+
+impl Checkpoint {
+    /// Starts a checkpoint for a new run writing to `output_path` through `output`, replacing any
+    /// checkpoint there
+    pub(crate) fn create(output_path: &Path, output: &File, block_size: usize) -> io::Result<Self> {
+        let path = path(output_path);
+        let mut file = File::create(&path)?;
+        file.write_all(MAGIC)?;
+        file.write_all(&(block_size as u64).to_le_bytes())?;
+        file.sync_data()?;
+        Ok(Self {
+            path,
+            file,
+            output: output.try_clone()?,
+            blocks: 0,
+            failed: None,
+        })
+    }
+
+    /// Opens the checkpoint of an interrupted run writing to `output_path`, to carry on recording
+    /// in it. Returns the progress recorded after each block.
+    pub(crate) fn resume(
+        output_path: &Path,
+        output: &File,
+        block_size: usize,
+    ) -> Result<(Self, Vec<Progress>), Box<dyn Error>> {
+        let path = path(output_path);
+        let mut contents = Vec::new();
+        File::open(&path)
+            .map_err(|e| format!("can't resume without {}: {}", path.display(), e))?
+            .read_to_end(&mut contents)?;
+
+        let records = contents
+            .strip_prefix(MAGIC.as_slice())
+            .ok_or_else(|| format!("{} isn't a checkpoint", path.display()))?;
+        let (recorded_block_size, records) = records
+            .split_first_chunk::<8>()
+            .ok_or_else(|| format!("{} is truncated", path.display()))?;
+        if u64::from_le_bytes(*recorded_block_size) != block_size as u64 {
+            return Err(format!(
+                "{} was written with a block size of {} bytes; resume with the same settings",
+                path.display(),
+                u64::from_le_bytes(*recorded_block_size)
+            )
+            .into());
+        }
+
+        let field = |record: &[u8], i: usize| {
+            u64::from_le_bytes(record[i * 8..(i + 1) * 8].try_into().unwrap())
+        };
+        let written = records
+            .chunks_exact(RECORD_LEN)
+            .map(|record| Progress {
+                blocks: field(record, 0),
+                original_len: field(record, 1),
+                compressed_len: field(record, 2),
+                ..Progress::default()
+            })
+            .collect::<Vec<_>>();
+        if written
+            .last()
+            .is_some_and(|last| last.compressed_len > output.metadata().map_or(0, |m| m.len()))
+        {
+            return Err(format!("{} is ahead of its output", path.display()).into());
+        }
+
+        // Appending after the last whole record, over one cut short
+        let file = File::options().append(true).open(&path)?;
+        file.set_len((MAGIC.len() + 8 + written.len() * RECORD_LEN) as u64)?;
+        let checkpoint = Self {
+            path,
+            file,
+            output: output.try_clone()?,
+            blocks: written.len() as u64,
+            failed: None,
+        };
+        Ok((checkpoint, written))
+    }
+
+    /// Records the block that `done` reports once the output up to it is on disk. Reports after
+    /// the last block, for the end of the stream, aren't recorded.
+    pub(crate) fn record(&mut self, done: Progress) {
+        if done.blocks == self.blocks || self.failed.is_some() {
+            return;
+        }
+        self.blocks = done.blocks;
+
+        let mut record = [0; RECORD_LEN];
+        for (field, value) in
+            record
+                .chunks_exact_mut(8)
+                .zip([done.blocks, done.original_len, done.compressed_len])
+        {
+            field.copy_from_slice(&value.to_le_bytes());
+        }
+        let recorded = self
+            .output
+            .sync_data()
+            .and_then(|()| self.file.write_all(&record))
+            .and_then(|()| self.file.sync_data());
+        self.failed = recorded.err();
+    }
+
+    /// Removes the checkpoint of a finished stream, or reports why recording a block failed
+    pub(crate) fn finish(self) -> io::Result<()> {
+        if let Some(e) = self.failed {
+            return Err(e);
+        }
+        drop(self.file);
+        fs::remove_file(&self.path)
+    }
+}
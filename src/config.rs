@@ -0,0 +1,126 @@
+//! Compression settings from a `--config` file.
+//!
+//! The file is a flat subset of TOML: one `key = value` per line, `#` comments, integers (with
+//! optional `_` separators), booleans and double-quoted strings. Recognized keys mirror the compress
+//! flags:
+//!
+//! ```toml
+//! block_size = 1_048_576
+//! threads = 4
+//! mtf = true
+//! rle0 = true
+//! hybrid_rle = true
+//! entropy = "huffman"
+//! checksum = true
+//! bijective = false
+//! sa_algo = "auto"
+//! ```
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use rust_bwt::{Entropy, Options, SuffixSort};
+
+#[derive(Debug)]
+pub(crate) struct ConfigError {
+    line: usize,
+    reason: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "config line {}: {}", self.line, self.reason)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Reads the config file at `path`, applying its settings on top of `Options::default()`
+pub(crate) fn load(path: &Path) -> Result<Options, Box<dyn std::error::Error>> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("couldn't read config {}: {}", path.display(), e))?;
+    Ok(parse(&text)?)
+}
+
+fn parse(text: &str) -> Result<Options, ConfigError> {
+    let mut options = Options::default();
+
+    for (i, line) in text.lines().enumerate() {
+        let error = |reason: String| ConfigError {
+            line: i + 1,
+            reason,
+        };
+
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') {
+            return Err(error("tables are not supported".into()));
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error(format!("expected `key = value`, found `{}`", line)))?;
+        let (key, value) = (key.trim(), value.trim());
+
+        match key {
+            "block_size" => options.block_size = parse_count(value).map_err(error)?,
+            "threads" => match parse_int(value).map_err(error)? {
+                // Like `--threads 0`, every logical CPU
+                0 => options.threads = Options::default().threads,
+                threads => options.threads = threads,
+            },
+            "mtf" => options.mtf = parse_bool(value).map_err(error)?,
+            "rle0" => options.rle0 = parse_bool(value).map_err(error)?,
+            "hybrid_rle" => options.hybrid_rle = parse_bool(value).map_err(error)?,
+            "checksum" => options.checksum = parse_bool(value).map_err(error)?,
+            "bijective" => options.bijective = parse_bool(value).map_err(error)?,
+            "entropy" => {
+                options.entropy = parse_string(value)
+                    .and_then(|name| name.parse::<Entropy>())
+                    .map_err(error)?
+            }
+            "sa_algo" => {
+                options.suffix_sort = parse_string(value)
+                    .and_then(|name| name.parse::<SuffixSort>())
+                    .map_err(error)?
+            }
+            _ => return Err(error(format!("unknown key `{}`", key))),
+        }
+    }
+
+    Ok(options)
+}
+
+/// A positive integer
+fn parse_count(value: &str) -> Result<usize, String> {
+    parse_int(value)
+        .ok()
+        .filter(|count| *count > 0)
+        .ok_or_else(|| format!("expected a positive integer, found `{}`", value))
+}
+
+/// A non-negative integer
+fn parse_int(value: &str) -> Result<usize, String> {
+    value
+        .replace('_', "")
+        .parse::<usize>()
+        .map_err(|_| format!("expected a non-negative integer, found `{}`", value))
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(format!("expected `true` or `false`, found `{}`", value)),
+    }
+}
+
+fn parse_string(value: &str) -> Result<&str, String> {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .ok_or_else(|| format!("expected a double-quoted string, found `{}`", value))
+}
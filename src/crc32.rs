@@ -0,0 +1,104 @@
+#[cfg(feature = "std")]
+use std::io;
+
+/// Reflected CRC-32 polynomial, as used by zlib, gzip and PNG
+const POLYNOMIAL: u32 = 0xEDB8_8320;
+
+/// Byte-at-a-time lookup table for [`POLYNOMIAL`]
+const TABLE: [u32; 256] = build_table();
+
+const fn build_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// The standard (IEEE) CRC-32 of `data`
+#[cfg(feature = "std")]
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+/// Running standard (IEEE) CRC-32, for data that arrives in pieces. With the `std` feature, also an
+/// [`io::Write`](std::io::Write) sink, so it can checksum the output of
+/// [`decompress_to`](crate::decompress_to) without storing it.
+#[derive(Debug, Clone)]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self { state: !0 }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.state = data.iter().fold(self.state, |crc, b| {
+            (crc >> 8) ^ TABLE[((crc ^ *b as u32) & 0xFF) as usize]
+        });
+    }
+
+    /// The CRC-32 of everything passed to [`update`](Self::update) so far
+    pub fn finish(&self) -> u32 {
+        !self.state
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl io::Write for Crc32 {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_standard_check_value() {
+        let mut crc = Crc32::new();
+        crc.update(b"123456789");
+        assert_eq!(crc.finish(), 0xCBF4_3926);
+        assert_eq!(Crc32::new().finish(), 0);
+    }
+
+    #[test]
+    fn updates_in_pieces() {
+        let data = b"The quick brown fox jumps over the lazy dog";
+        let mut crc = Crc32::new();
+        for piece in data.chunks(5) {
+            crc.update(piece);
+        }
+        assert_eq!(crc.finish(), 0x414F_A339);
+        #[cfg(feature = "std")]
+        assert_eq!(crc32(data), 0x414F_A339);
+    }
+}
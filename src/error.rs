@@ -0,0 +1,103 @@
+use core::error::Error;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io;
+
+/// Everything that can go wrong while reading a compressed stream
+#[derive(Debug)]
+pub enum BwtError {
+    /// The underlying reader failed
+    #[cfg(feature = "std")]
+    Io(io::Error),
+    /// The stream ended in the middle of a header, block, or run
+    TruncatedStream,
+    /// The stream ended in the middle of `field`, which started `offset` bytes into the stream or,
+    /// for fields inside a block, into the block's decoded body
+    UnexpectedEof { field: &'static str, offset: u64 },
+    /// The stored sentinel index doesn't point inside the decoded data
+    InvalidSentinelIndex,
+    /// The stream doesn't start with the expected magic bytes
+    BadMagic,
+    /// The stream was written by a newer, unknown version of the format
+    UnsupportedVersion(u8),
+    /// The stream's header names pipeline stages this version doesn't know
+    UnsupportedFlags(u8),
+    /// A stage's encoded data is malformed
+    CorruptStream(&'static str),
+    /// A block decompressed to bytes whose CRC-32 doesn't match the one stored when it was
+    /// compressed
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// The stream decompressed to a different number of bytes than its header says it holds
+    LengthMismatch { expected: u64, actual: u64 },
+    /// Decompressing would produce more than `limit` bytes, either in total or in a single block
+    OutputTooLarge { limit: u64 },
+    /// A checked reverse transform's LF-mapping walk went wrong at `step`, on `row`
+    InconsistentTransform { step: usize, row: usize },
+    /// The stream has no block index to find a range of its original data by
+    NoIndex,
+    /// A range of the original data was asked for that ends at `end`, past its `len` bytes
+    RangeOutOfBounds { end: u64, len: u64 },
+}
+
+impl fmt::Display for BwtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use BwtError::*;
+
+        match self {
+            #[cfg(feature = "std")]
+            Io(e) => write!(f, "I/O error: {}", e),
+            TruncatedStream => write!(f, "compressed stream is truncated"),
+            UnexpectedEof { field, offset } => write!(
+                f,
+                "unexpected end of file while reading {} at offset {}",
+                field, offset
+            ),
+            InvalidSentinelIndex => write!(f, "sentinel index is out of range"),
+            BadMagic => write!(f, "not a rust-bwt stream (bad magic bytes)"),
+            UnsupportedVersion(v) => write!(f, "unsupported format version {}", v),
+            UnsupportedFlags(flags) => write!(f, "unsupported stage flags {:#010b}", flags),
+            CorruptStream(reason) => write!(f, "corrupt compressed stream: {}", reason),
+            ChecksumMismatch { expected, actual } => write!(
+                f,
+                "checksum mismatch: block should have CRC-32 {:#010x}, but decompressed to {:#010x}",
+                expected, actual
+            ),
+            LengthMismatch { expected, actual } => write!(
+                f,
+                "length mismatch: stream should hold {} bytes, but decompressed to {}",
+                expected, actual
+            ),
+            OutputTooLarge { limit } => {
+                write!(f, "output would be larger than the {} byte limit", limit)
+            }
+            InconsistentTransform { step, row } => write!(
+                f,
+                "transformed data is inconsistent: LF-mapping walk failed at step {} (row {})",
+                step, row
+            ),
+            NoIndex => write!(f, "stream has no block index"),
+            RangeOutOfBounds { end, len } => write!(
+                f,
+                "range ends at byte {}, past the end of the {} byte original data",
+                end, len
+            ),
+        }
+    }
+}
+
+impl Error for BwtError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            #[cfg(feature = "std")]
+            BwtError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for BwtError {
+    fn from(e: io::Error) -> Self {
+        BwtError::Io(e)
+    }
+}
@@ -0,0 +1,312 @@
+use alloc::vec::Vec;
+
+use crate::{BWTByte, BWTStr};
+
+const SYMBOL_CNT: usize = 256;
+
+/// Occurrence counts are stored for every this many symbols of the last column; counting between
+/// checkpoints scans at most this many symbols
+const CHECKPOINT_INTERVAL: usize = 256;
+
+/// Default for [`FmIndex::with_sample_rate`]: every 32nd text position is sampled
+pub const DEFAULT_SAMPLE_RATE: usize = 32;
+
+/// An FM-index over one transformed block: its last column, the C array, and occurrence counts
+/// checkpointed every [`CHECKPOINT_INTERVAL`] rows, plus a sampled suffix array. Supports counting
+/// and locating the occurrences of a pattern in the original text by backward search, without
+/// reversing the transform.
+#[derive(Debug, Clone)]
+pub struct FmIndex {
+    /// The last column, with [`BWTByte::Sentinel`] stored as a placeholder `0` at
+    /// `sentinel_index`
+    last_column: Vec<u8>,
+    sentinel_index: usize,
+    c_array: [usize; SYMBOL_CNT + 1],
+    /// `checkpoints[k][b]` is the number of `b`s in `last_column[..k * CHECKPOINT_INTERVAL]`,
+    /// not counting the sentinel
+    checkpoints: Vec<[usize; SYMBOL_CNT]>,
+    /// `(row, text position)` for every row whose suffix starts at a multiple of the sample rate,
+    /// sorted by row
+    samples: Vec<(usize, usize)>,
+}
+
+impl FmIndex {
+    /// Indexes `transformed`, the output of [`BWTStr::forward_transform`], sampling every
+    /// [`DEFAULT_SAMPLE_RATE`]th text position
+    pub fn new(transformed: &BWTStr) -> Self {
+        Self::with_sample_rate(transformed, DEFAULT_SAMPLE_RATE)
+    }
+
+    /// Like [`new`](Self::new), but keeps the suffix array position of every `sample_rate`th byte
+    /// of the text. Lower rates make [`locate`](Self::locate) faster and the index larger. Must be
+    /// nonzero.
+    pub fn with_sample_rate(transformed: &BWTStr, sample_rate: usize) -> Self {
+        let last_column = transformed
+            .symbols()
+            .map(|bwt_byte| match bwt_byte {
+                BWTByte::Byte(b) => b,
+                BWTByte::Sentinel => 0,
+            })
+            .collect::<Vec<_>>();
+        let sentinel_index = transformed.sentinel_index();
+
+        let mut checkpoints = Vec::with_capacity(last_column.len() / CHECKPOINT_INTERVAL + 1);
+        let mut counts = [0_usize; SYMBOL_CNT];
+        for (row, b) in last_column.iter().enumerate() {
+            if row % CHECKPOINT_INTERVAL == 0 {
+                checkpoints.push(counts);
+            }
+            if row != sentinel_index {
+                counts[*b as usize] += 1;
+            }
+        }
+        if last_column.len() % CHECKPOINT_INTERVAL == 0 {
+            checkpoints.push(counts);
+        }
+
+        let mut index = Self {
+            last_column,
+            sentinel_index,
+            c_array: transformed.c_array(),
+            checkpoints,
+            samples: Vec::new(),
+        };
+
+        // Row 0 is the suffix holding only the sentinel, at the end of the text; each LF-mapping
+        // step moves to the suffix starting one byte earlier
+        let mut row = 0;
+        for position in (0..index.last_column.len()).rev() {
+            if position % sample_rate == 0 {
+                index.samples.push((row, position));
+            }
+            if row == index.sentinel_index {
+                break;
+            }
+            row = index.lf(row);
+        }
+        index.samples.sort_unstable();
+
+        index
+    }
+
+    /// How many times `pattern` occurs in the original text, overlapping occurrences included. An
+    /// empty pattern occurs once per byte.
+    pub fn count(&self, pattern: &[u8]) -> usize {
+        self.rows(pattern).len()
+    }
+
+    /// The 0-based positions in the original text of every occurrence of `pattern`, overlapping
+    /// occurrences included, in increasing order. An empty pattern occurs at every byte.
+    pub fn locate(&self, pattern: &[u8]) -> Vec<usize> {
+        let mut positions = self
+            .rows(pattern)
+            .map(|mut row| {
+                // Step back through the text until reaching a sampled position
+                let mut steps = 0;
+                loop {
+                    if let Ok(i) = self.samples.binary_search_by_key(&row, |(row, _)| *row) {
+                        return self.samples[i].1 + steps;
+                    }
+                    row = self.lf(row);
+                    steps += 1;
+                }
+            })
+            .collect::<Vec<_>>();
+        positions.sort_unstable();
+        positions
+    }
+
+    /// The rows whose suffixes start with `pattern`. Doesn't include the sentinel's row for an
+    /// empty pattern.
+    fn rows(&self, pattern: &[u8]) -> core::ops::Range<usize> {
+        if pattern.is_empty() {
+            return 1..self.last_column.len();
+        }
+
+        // Rows lo..hi are the rotations starting with the suffix of `pattern` matched so far;
+        // prepending b narrows them to the rows starting with b that come from them
+        let (mut lo, mut hi) = (0, self.last_column.len());
+        for b in pattern.iter().rev() {
+            let first_row = self.c_array[*b as usize + 1];
+            lo = first_row + self.occ(*b, lo);
+            hi = first_row + self.occ(*b, hi);
+            if lo >= hi {
+                return 0..0;
+            }
+        }
+
+        lo..hi
+    }
+
+    /// The first `len` bytes of the original text, or all of it if it's shorter, read by walking
+    /// forward from the row of the whole text
+    pub fn text_start(&self, len: usize) -> Vec<u8> {
+        let len = len.min(self.last_column.len() - 1);
+        let mut bytes = Vec::with_capacity(len);
+        let mut row = self.sentinel_index;
+        for _ in 0..len {
+            bytes.push(self.first(row));
+            row = self.fl(row);
+        }
+        bytes
+    }
+
+    /// The last `len` bytes of the original text, or all of it if it's shorter, read by walking
+    /// back from the row of the sentinel alone
+    pub fn text_end(&self, len: usize) -> Vec<u8> {
+        let len = len.min(self.last_column.len() - 1);
+        let mut bytes = Vec::with_capacity(len);
+        let mut row = 0;
+        for _ in 0..len {
+            bytes.push(self.last_column[row]);
+            row = self.lf(row);
+        }
+        bytes.reverse();
+        bytes
+    }
+
+    /// The byte in the first column at `row`, which must not be the sentinel's row 0
+    fn first(&self, row: usize) -> u8 {
+        // The last symbol whose first row is at or before `row`; slot 0 is the sentinel
+        (self.c_array.partition_point(|first_row| *first_row <= row) - 2) as u8
+    }
+
+    /// The inverse of LF-mapping: the row of the suffix starting one byte after `row`'s. `row`
+    /// must not be the sentinel's row 0.
+    fn fl(&self, row: usize) -> usize {
+        let b = self.first(row);
+        // `row` is the rank-th row starting with b, which LF-maps from the rank-th b in the last
+        // column
+        let rank = row - self.c_array[b as usize + 1];
+        let checkpoint = self
+            .checkpoints
+            .partition_point(|counts| counts[b as usize] <= rank)
+            - 1;
+
+        let mut count = self.checkpoints[checkpoint][b as usize];
+        for last_row in checkpoint * CHECKPOINT_INTERVAL.. {
+            if self.last_column[last_row] == b && last_row != self.sentinel_index {
+                if count == rank {
+                    return last_row;
+                }
+                count += 1;
+            }
+        }
+        unreachable!("every row of the first column comes from one of the last")
+    }
+
+    /// LF-mapping: the row of the suffix starting one byte before `row`'s. `row` must not be the
+    /// sentinel's.
+    fn lf(&self, row: usize) -> usize {
+        let b = self.last_column[row];
+        self.c_array[b as usize + 1] + self.occ(b, row)
+    }
+
+    /// The number of `b`s in the first `row` rows of the last column
+    fn occ(&self, b: u8, row: usize) -> usize {
+        let checkpoint = row / CHECKPOINT_INTERVAL;
+        let scanned_from = checkpoint * CHECKPOINT_INTERVAL;
+
+        let mut count = self.checkpoints[checkpoint][b as usize];
+        count += self.last_column[scanned_from..row]
+            .iter()
+            .filter(|last| **last == b)
+            .count();
+        if b == 0 && (scanned_from..row).contains(&self.sentinel_index) {
+            // The sentinel's placeholder isn't a real 0
+            count -= 1;
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    fn index(text: &[u8]) -> FmIndex {
+        FmIndex::new(&BWTStr::new(text.to_vec()).forward_transform())
+    }
+
+    #[test]
+    fn counts_known_patterns() {
+        let index = index(b"abracadabra");
+        for (pattern, count) in [
+            (&b"a"[..], 5),
+            (b"abra", 2),
+            (b"bra", 2),
+            (b"cad", 1),
+            (b"abracadabra", 1),
+            (b"dab", 1),
+            (b"zebra", 0),
+            (b"abracadabrab", 0),
+            (b"", 11),
+        ] {
+            assert_eq!(index.count(pattern), count, "{:?}", pattern);
+        }
+    }
+
+    #[test]
+    fn locates_at_the_edges_of_the_text() {
+        let text = b"abracadabra";
+        for sample_rate in [1, 2, 3, 32] {
+            let index = FmIndex::with_sample_rate(
+                &BWTStr::new(text.to_vec()).forward_transform(),
+                sample_rate,
+            );
+            assert_eq!(index.locate(b"a"), [0, 3, 5, 7, 10]);
+            assert_eq!(index.locate(b"abra"), [0, 7]);
+            assert_eq!(index.locate(b"ra"), [2, 9]);
+            assert_eq!(index.locate(text), [0]);
+            assert!(index.locate(b"abracadabraa").is_empty());
+            assert_eq!(index.locate(b""), (0..text.len()).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn handles_tiny_texts() {
+        let empty = index(b"");
+        assert_eq!(empty.count(b""), 0);
+        assert_eq!(empty.count(b"a"), 0);
+        assert!(empty.locate(b"a").is_empty());
+
+        let one = index(b"\x00");
+        // The sentinel's placeholder is a 0 too, but never matches
+        assert_eq!(one.count(b"\x00"), 1);
+        assert_eq!(one.count(b"\x00\x00"), 0);
+        assert_eq!(one.locate(b"\x00"), [0]);
+    }
+
+    #[test]
+    fn counts_across_checkpoints() {
+        // Zeros make the sentinel's placeholder matter in every checkpoint's count
+        let text = [vec![0; 700], vec![1; 300], vec![0; 100]].concat();
+        let index = index(&text);
+        assert_eq!(index.count(&[0]), 800);
+        assert_eq!(index.count(&[0; 100]), 602);
+        assert_eq!(index.count(&[0, 1]), 1);
+        assert_eq!(index.locate(&[1, 0]), [999]);
+        assert_eq!(index.locate(&[0; 600]), (0..=100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn reads_the_ends_of_the_text() {
+        // Long enough for several checkpoints, with every byte value
+        let text = (0..3000_u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect::<Vec<_>>();
+        let index = index(&text);
+        for len in [0, 1, 2, 255, 256, 257, 2999, 3000, 4000] {
+            let len_in_text = len.min(text.len());
+            assert_eq!(index.text_start(len), text[..len_in_text]);
+            assert_eq!(index.text_end(len), text[text.len() - len_in_text..]);
+        }
+
+        let empty = self::index(b"");
+        assert!(empty.text_start(3).is_empty());
+        assert!(empty.text_end(3).is_empty());
+    }
+}
@@ -0,0 +1,288 @@
+use alloc::collections::BinaryHeap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+
+use crate::BwtError;
+
+/// Codes are never longer than this, so they always fit comfortably in the bit buffers below
+const MAX_CODE_LEN: u8 = 24;
+
+const SYMBOL_CNT: usize = 256;
+
+/// Size of the little-endian decoded length at the start of the output
+const LEN_BYTE_CNT: usize = (u64::BITS / 8) as usize;
+
+/// Size of the bitmap of which byte values occur
+const PRESENCE_BYTE_CNT: usize = SYMBOL_CNT / 8;
+
+/// Canonical Huffman codes `input`.
+///
+/// The output is the decoded length as a little-endian `u64`, a 32-byte bitmap of which byte
+/// values occur, one code-length byte per occurring value (in increasing byte order), and then the
+/// codes themselves, packed most significant bit first and zero-padded to a whole byte. Only the
+/// code lengths are stored: codes are assigned canonically, in order of (length, byte value).
+pub fn huffman_encode(input: &[u8]) -> Vec<u8> {
+    let mut freqs = [0_u64; SYMBOL_CNT];
+    for b in input {
+        freqs[*b as usize] += 1;
+    }
+
+    let lengths = code_lengths(&freqs);
+    let codes = canonical_codes(&lengths);
+
+    let mut output = Vec::with_capacity(LEN_BYTE_CNT + PRESENCE_BYTE_CNT + input.len() / 2);
+    output.extend_from_slice((input.len() as u64).to_le_bytes().as_slice());
+
+    let mut presence = [0_u8; PRESENCE_BYTE_CNT];
+    for (symbol, len) in lengths.iter().enumerate() {
+        if *len > 0 {
+            presence[symbol / 8] |= 1 << (symbol % 8);
+        }
+    }
+    output.extend_from_slice(&presence);
+    output.extend(lengths.iter().filter(|len| **len > 0));
+
+    let mut bits = 0_u64;
+    let mut bit_cnt = 0_u32;
+    for b in input {
+        let len = lengths[*b as usize] as u32;
+        bits = (bits << len) | codes[*b as usize] as u64;
+        bit_cnt += len;
+        while bit_cnt >= 8 {
+            bit_cnt -= 8;
+            output.push((bits >> bit_cnt) as u8);
+        }
+    }
+    if bit_cnt > 0 {
+        output.push((bits << (8 - bit_cnt)) as u8);
+    }
+
+    output
+}
+
+/// Reverses [`huffman_encode`]
+pub fn huffman_decode(input: &[u8]) -> Result<Vec<u8>, BwtError> {
+    let (len_bytes, rest) = input
+        .split_at_checked(LEN_BYTE_CNT)
+        .ok_or(BwtError::TruncatedStream)?;
+    let len = u64::from_le_bytes(len_bytes.try_into().unwrap());
+    let (presence, mut rest) = rest
+        .split_at_checked(PRESENCE_BYTE_CNT)
+        .ok_or(BwtError::TruncatedStream)?;
+
+    let mut lengths = [0_u8; SYMBOL_CNT];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        if presence[symbol / 8] & (1 << (symbol % 8)) != 0 {
+            let (len, after) = rest.split_first().ok_or(BwtError::TruncatedStream)?;
+            if *len == 0 || *len > MAX_CODE_LEN {
+                return Err(BwtError::CorruptStream("invalid Huffman code length"));
+            }
+            *length = *len;
+            rest = after;
+        }
+    }
+
+    // Canonical decoding tables: for each length, the first code of that length and where its
+    // symbols start in the (length, symbol)-ordered symbol list
+    let symbols = sorted_symbols(&lengths);
+    let mut count = [0_u32; MAX_CODE_LEN as usize + 1];
+    for len in lengths.iter().filter(|len| **len > 0) {
+        count[*len as usize] += 1;
+    }
+    let mut first_code = [0_u32; MAX_CODE_LEN as usize + 1];
+    let mut first_index = [0_u32; MAX_CODE_LEN as usize + 1];
+    let (mut code, mut index) = (0_u32, 0_u32);
+    for len in 1..=MAX_CODE_LEN as usize {
+        first_code[len] = code;
+        first_index[len] = index;
+        code = (code + count[len]) << 1;
+        index += count[len];
+    }
+
+    // Every decoded symbol takes at least one bit, which bounds a believable length
+    if len > rest.len() as u64 * 8 {
+        return Err(BwtError::TruncatedStream);
+    }
+    let mut output = Vec::with_capacity(len as usize);
+    let mut bits = rest
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1));
+    while (output.len() as u64) < len {
+        let mut code = 0_u32;
+        let mut code_len = 0;
+        loop {
+            code = (code << 1) | bits.next().ok_or(BwtError::TruncatedStream)? as u32;
+            code_len += 1;
+            if code_len > MAX_CODE_LEN as usize {
+                return Err(BwtError::CorruptStream("invalid Huffman code"));
+            }
+            if code.wrapping_sub(first_code[code_len]) < count[code_len] {
+                let index = first_index[code_len] + code - first_code[code_len];
+                output.push(symbols[index as usize]);
+                break;
+            }
+        }
+    }
+
+    Ok(output)
+}
+
+/// Huffman code lengths for each byte value, 0 for values that don't occur. If the optimal code
+/// would be longer than [`MAX_CODE_LEN`], the frequencies are flattened and the code rebuilt.
+fn code_lengths(freqs: &[u64; SYMBOL_CNT]) -> [u8; SYMBOL_CNT] {
+    let mut freqs = *freqs;
+
+    loop {
+        let mut lengths = [0_u8; SYMBOL_CNT];
+
+        // Leaves are 0..SYMBOL_CNT; internal nodes are appended after them
+        let mut parents = vec![usize::MAX; SYMBOL_CNT];
+        let mut heap = BinaryHeap::new();
+        for (symbol, freq) in freqs.iter().enumerate() {
+            if *freq > 0 {
+                heap.push(Reverse((*freq, symbol)));
+            }
+        }
+
+        match heap.len() {
+            0 => return lengths,
+            1 => {
+                // A lone symbol still needs a one-bit code
+                let Reverse((_, symbol)) = heap.pop().unwrap();
+                lengths[symbol] = 1;
+                return lengths;
+            }
+            _ => {}
+        }
+
+        while heap.len() > 1 {
+            let Reverse((freq_a, a)) = heap.pop().unwrap();
+            let Reverse((freq_b, b)) = heap.pop().unwrap();
+            let node = parents.len();
+            parents.push(usize::MAX);
+            parents[a] = node;
+            parents[b] = node;
+            heap.push(Reverse((freq_a + freq_b, node)));
+        }
+
+        let mut too_long = false;
+        for symbol in 0..SYMBOL_CNT {
+            if freqs[symbol] == 0 {
+                continue;
+            }
+            let mut depth = 0_usize;
+            let mut node = symbol;
+            while parents[node] != usize::MAX {
+                node = parents[node];
+                depth += 1;
+            }
+            too_long |= depth > MAX_CODE_LEN as usize;
+            lengths[symbol] = depth.min(MAX_CODE_LEN as usize) as u8;
+        }
+
+        if !too_long {
+            return lengths;
+        }
+        for freq in freqs.iter_mut().filter(|freq| **freq > 0) {
+            *freq = (*freq).div_ceil(2);
+        }
+    }
+}
+
+/// Symbols with a code, ordered by (code length, byte value)
+fn sorted_symbols(lengths: &[u8; SYMBOL_CNT]) -> Vec<u8> {
+    let mut symbols = (0..SYMBOL_CNT)
+        .filter(|symbol| lengths[*symbol] > 0)
+        .map(|symbol| symbol as u8)
+        .collect::<Vec<_>>();
+    symbols.sort_by_key(|symbol| lengths[*symbol as usize]);
+    symbols
+}
+
+/// Assigns canonical codes: consecutive within a length, in order of (length, byte value)
+fn canonical_codes(lengths: &[u8; SYMBOL_CNT]) -> [u32; SYMBOL_CNT] {
+    let mut codes = [0_u32; SYMBOL_CNT];
+    let mut code = 0_u32;
+    let mut prev_len = 0;
+
+    for symbol in sorted_symbols(lengths) {
+        let len = lengths[symbol as usize];
+        code <<= len - prev_len;
+        codes[symbol as usize] = code;
+        code += 1;
+        prev_len = len;
+    }
+
+    codes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes `0..symbols` with frequencies following the Fibonacci numbers, which gives the
+    /// deepest possible Huffman tree for their count: one level per symbol
+    fn fibonacci(symbols: usize) -> Vec<u8> {
+        let (mut a, mut b) = (1_usize, 1_usize);
+        let mut bytes = Vec::new();
+        for symbol in 0..symbols {
+            bytes.extend(core::iter::repeat_n(symbol as u8, a));
+            (a, b) = (b, a + b);
+        }
+        bytes
+    }
+
+    #[test]
+    fn round_trips() {
+        let every_byte = (0..=255).collect::<Vec<u8>>();
+        let skewed = fibonacci(30);
+        for input in [
+            &b""[..],
+            b"a",
+            b"aaaaaaaa",
+            b"abracadabra",
+            &every_byte,
+            &every_byte.repeat(3),
+            &skewed,
+        ] {
+            assert_eq!(huffman_decode(&huffman_encode(input)).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn limits_code_lengths() {
+        let mut freqs = [0; SYMBOL_CNT];
+        for b in fibonacci(30) {
+            freqs[b as usize] += 1;
+        }
+        // The unlimited code would be 29 bits long
+        let lengths = code_lengths(&freqs);
+        assert!(*lengths.iter().max().unwrap() <= MAX_CODE_LEN);
+        assert_eq!(lengths.iter().filter(|len| **len > 0).count(), 30);
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        let encoded = huffman_encode(b"abracadabra");
+        for len in [
+            0,
+            LEN_BYTE_CNT,
+            LEN_BYTE_CNT + PRESENCE_BYTE_CNT,
+            encoded.len() - 1,
+        ] {
+            assert!(matches!(
+                huffman_decode(&encoded[..len]),
+                Err(BwtError::TruncatedStream)
+            ));
+        }
+
+        // The lengths of "a", "b", "c", "d" and "r" follow the presence bitmap
+        let mut bad_length = encoded.clone();
+        bad_length[LEN_BYTE_CNT + PRESENCE_BYTE_CNT] = MAX_CODE_LEN + 1;
+        assert!(matches!(
+            huffman_decode(&bad_length),
+            Err(BwtError::CorruptStream(_))
+        ));
+    }
+}
@@ -0,0 +1,211 @@
+use alloc::vec::Vec;
+
+use crate::BwtError;
+
+/// Introduces either a run or, followed by a zero, a literal `ESCAPE` byte
+const ESCAPE: u8 = 0xFF;
+
+/// Runs at least this long are cheaper as `ESCAPE, count, byte` than as literals. Runs of the
+/// escape byte itself pay off from length 2, since each literal one costs two bytes. Only the
+/// encoder uses it: a run of any length decodes the same way, so it isn't stored in the header.
+const MIN_RUN: usize = 4;
+
+/// Literal/run hybrid encoding, for transformed data without MTF where most runs are short.
+///
+/// Bytes are copied as they are, except that a run of `n` copies of `b` (`n >= 2`) may be written
+/// as `ESCAPE`, the varint `n - 1`, and then `b`, and a literal `ESCAPE` is written as `ESCAPE, 0`.
+/// Runs are only used where they're shorter than the literals would be, so an input without runs
+/// costs nothing beyond its `0xFF` bytes.
+pub fn hybrid_rle_encode(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len());
+
+    let mut rest = input;
+    while let Some(&b) = rest.first() {
+        let run_len = rest.iter().take_while(|byte| **byte == b).count();
+        rest = &rest[run_len..];
+
+        if run_len >= MIN_RUN || (b == ESCAPE && run_len >= 2) {
+            output.push(ESCAPE);
+            push_varint(&mut output, run_len as u64 - 1);
+            output.push(b);
+        } else if b == ESCAPE {
+            output.extend_from_slice(&[ESCAPE, 0]);
+        } else {
+            output.extend(core::iter::repeat_n(b, run_len));
+        }
+    }
+
+    output
+}
+
+/// Reverses [`hybrid_rle_encode`]
+pub fn hybrid_rle_decode(input: &[u8]) -> Result<Vec<u8>, BwtError> {
+    hybrid_rle_decode_limited(input, usize::MAX)
+}
+
+/// Like [`hybrid_rle_decode`], but fails with [`BwtError::OutputTooLarge`] instead of producing
+/// more than `limit` bytes, before allocating room for them
+pub(crate) fn hybrid_rle_decode_limited(input: &[u8], limit: usize) -> Result<Vec<u8>, BwtError> {
+    let too_large = BwtError::OutputTooLarge {
+        limit: limit as u64,
+    };
+    let mut output = Vec::with_capacity(input.len());
+
+    let mut rest = input;
+    while let Some((&b, after)) = rest.split_first() {
+        rest = after;
+        if output.len() == limit {
+            return Err(too_large);
+        }
+        if b != ESCAPE {
+            output.push(b);
+            continue;
+        }
+
+        match read_varint(&mut rest)? {
+            0 => output.push(ESCAPE),
+            extra => {
+                let (&b, after) = rest.split_first().ok_or(BwtError::TruncatedStream)?;
+                rest = after;
+                let run_len = usize::try_from(extra)
+                    .ok()
+                    .and_then(|extra| extra.checked_add(1))
+                    .ok_or(BwtError::CorruptStream("run length is too long"))?;
+                if run_len > limit - output.len() {
+                    return Err(too_large);
+                }
+                output.extend(core::iter::repeat_n(b, run_len));
+            }
+        }
+    }
+
+    Ok(output)
+}
+
+/// Appends `value` as an unsigned LEB128 varint
+fn push_varint(output: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        output.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+/// Reads an unsigned LEB128 varint off the front of `input`
+fn read_varint(input: &mut &[u8]) -> Result<u64, BwtError> {
+    let mut value = 0_u64;
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = input.split_first().ok_or(BwtError::TruncatedStream)?;
+        *input = rest;
+
+        let bits = (byte & 0x7F) as u64;
+        if shift >= u64::BITS || (bits << shift) >> shift != bits {
+            return Err(BwtError::CorruptStream("run length is too long"));
+        }
+        value |= bits << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    #[test]
+    fn writes_only_long_runs_as_runs() {
+        assert_eq!(hybrid_rle_encode(b"aaab"), b"aaab");
+        assert_eq!(hybrid_rle_encode(b"aaaab"), vec![ESCAPE, 3, b'a', b'b']);
+        assert_eq!(hybrid_rle_encode(&[ESCAPE]), vec![ESCAPE, 0]);
+        assert_eq!(hybrid_rle_encode(&[ESCAPE; 2]), vec![ESCAPE, 1, ESCAPE]);
+        assert_eq!(hybrid_rle_encode(&[7; 201]), vec![ESCAPE, 0xC8, 0x01, 7]);
+    }
+
+    /// `len` bytes that don't compress, from a fixed seed
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn expands_only_by_escaped_bytes() {
+        for len in [0, 1, 255, 4096, 100_000] {
+            let input = noise(len);
+            let escapes = input.iter().filter(|b| **b == ESCAPE).count();
+            assert!(hybrid_rle_encode(&input).len() <= len + escapes);
+
+            let no_escapes = input.iter().map(|b| b & 0x7F).collect::<Vec<_>>();
+            assert!(hybrid_rle_encode(&no_escapes).len() <= len);
+        }
+
+        // The worst case, lone escape bytes, costs half as much again as the input
+        let input = [ESCAPE, 0].repeat(1000);
+        assert_eq!(hybrid_rle_encode(&input).len(), 3000);
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut inputs = vec![Vec::new(), vec![ESCAPE], vec![0, ESCAPE, 0]];
+        inputs.push((0..=255).collect());
+        inputs.push(noise(10_000));
+        for run_len in [1, 2, 3, 4, 5, 127, 128, 129, 100_000] {
+            for b in [0, b'x', ESCAPE] {
+                let mut input = vec![b; run_len];
+                input.push(b'y');
+                inputs.push(input);
+            }
+        }
+
+        for input in &inputs {
+            assert_eq!(
+                hybrid_rle_decode(&hybrid_rle_encode(input)).unwrap(),
+                *input
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        for truncated in [&[ESCAPE][..], &[ESCAPE, 0x80], &[ESCAPE, 3]] {
+            assert!(matches!(
+                hybrid_rle_decode(truncated),
+                Err(BwtError::TruncatedStream)
+            ));
+        }
+        // A count that doesn't fit in 64 bits
+        let mut too_long = vec![ESCAPE];
+        too_long.extend([0xFF; 9]);
+        too_long.extend([0x7F, b'a']);
+        assert!(matches!(
+            hybrid_rle_decode(&too_long),
+            Err(BwtError::CorruptStream(_))
+        ));
+    }
+
+    #[test]
+    fn stops_at_the_limit() {
+        let encoded = hybrid_rle_encode(&[7; 100]);
+        assert_eq!(hybrid_rle_decode_limited(&encoded, 100).unwrap().len(), 100);
+        assert!(matches!(
+            hybrid_rle_decode_limited(&encoded, 99),
+            Err(BwtError::OutputTooLarge { limit: 99 })
+        ));
+        assert!(matches!(
+            hybrid_rle_decode_limited(b"abc", 2),
+            Err(BwtError::OutputTooLarge { limit: 2 })
+        ));
+    }
+}
@@ -0,0 +1,117 @@
+//! The block index at the end of a stream with checksums, from format version 3 on, which lets
+//! [`decompress_range`](crate::decompress_range) seek straight to the blocks it needs.
+//!
+//! It follows the end-of-stream marker: an entry per block, each the offset of the block's length
+//! prefix from the start of the stream and the offset of its first byte in the original data, then
+//! a footer of the block count, the original length and the length of the whole stream, index
+//! included, all little-endian `u64`s, and [`INDEX_MAGIC`]. A reader with the whole file finds the
+//! footer at its end; one reading the stream front to back has counted the blocks, so it knows how
+//! long the index is.
+
+use crate::BwtError;
+
+/// Ends the index, so a file that ends in one can be told from one that doesn't
+pub(crate) const INDEX_MAGIC: [u8; 4] = *b"RBWI";
+
+/// Size of one block's entry: its compressed and original offsets
+const ENTRY_LEN: u64 = 2 * 8;
+
+/// Size of the footer: block count, original length, stream length and [`INDEX_MAGIC`]
+pub(crate) const FOOTER_LEN: u64 = 3 * 8 + INDEX_MAGIC.len() as u64;
+
+/// Where one block starts, in the stream and in the original data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct IndexEntry {
+    pub(crate) compressed_offset: u64,
+    pub(crate) original_offset: u64,
+}
+
+/// What the footer says
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Footer {
+    pub(crate) blocks: u64,
+    pub(crate) original_len: u64,
+    pub(crate) stream_len: u64,
+}
+
+/// Collects the entries as blocks are written
+#[derive(Debug, Default)]
+pub(crate) struct IndexWriter {
+    entries: Vec<u8>,
+    blocks: u64,
+}
+
+impl IndexWriter {
+    pub(crate) fn push(&mut self, entry: IndexEntry) {
+        self.entries
+            .extend_from_slice(&entry.compressed_offset.to_le_bytes());
+        self.entries
+            .extend_from_slice(&entry.original_offset.to_le_bytes());
+        self.blocks += 1;
+    }
+
+    /// The whole index, for a stream that is `stream_len` bytes long up to it and holds
+    /// `original_len` bytes of original data
+    pub(crate) fn finish(self, stream_len: u64, original_len: u64) -> Vec<u8> {
+        let mut index = self.entries;
+        let stream_len = stream_len + index.len() as u64 + FOOTER_LEN;
+        for field in [self.blocks, original_len, stream_len] {
+            index.extend_from_slice(&field.to_le_bytes());
+        }
+        index.extend_from_slice(&INDEX_MAGIC);
+        index
+    }
+}
+
+/// Size of the index of a stream of `blocks` blocks
+pub(crate) fn index_len(blocks: u64) -> u64 {
+    blocks.saturating_mul(ENTRY_LEN).saturating_add(FOOTER_LEN)
+}
+
+/// Reads a footer, the last [`FOOTER_LEN`] bytes of a stream. Returns `None` if it doesn't end in
+/// [`INDEX_MAGIC`].
+pub(crate) fn parse_footer(footer: &[u8]) -> Option<Footer> {
+    let (fields, magic) = footer.split_last_chunk::<{ INDEX_MAGIC.len() }>()?;
+    if *magic != INDEX_MAGIC || fields.len() != 3 * 8 {
+        return None;
+    }
+    let field = |i: usize| u64::from_le_bytes(fields[i * 8..(i + 1) * 8].try_into().unwrap());
+    Some(Footer {
+        blocks: field(0),
+        original_len: field(1),
+        stream_len: field(2),
+    })
+}
+
+/// The original length in the footer at the end of `stream`, if it ends in one that says the stream
+/// is exactly that long
+pub(crate) fn stored_original_len(stream: &[u8]) -> Option<u64> {
+    let footer_start = stream.len().checked_sub(FOOTER_LEN as usize)?;
+    parse_footer(&stream[footer_start..])
+        .filter(|footer| footer.stream_len == stream.len() as u64)
+        .map(|footer| footer.original_len)
+}
+
+/// Reads the entries of an index, without its footer
+pub(crate) fn parse_entries(entries: &[u8]) -> Vec<IndexEntry> {
+    entries
+        .chunks_exact(ENTRY_LEN as usize)
+        .map(|entry| IndexEntry {
+            compressed_offset: u64::from_le_bytes(entry[..8].try_into().unwrap()),
+            original_offset: u64::from_le_bytes(entry[8..].try_into().unwrap()),
+        })
+        .collect()
+}
+
+/// Checks an index read front to back after `blocks` blocks. Its entries aren't needed then, so
+/// only its footer is checked.
+pub(crate) fn check(index: &[u8], blocks: u64) -> Result<(), BwtError> {
+    let footer = index
+        .len()
+        .checked_sub(FOOTER_LEN as usize)
+        .and_then(|entries_len| parse_footer(&index[entries_len..]));
+    match footer {
+        Some(footer) if footer.blocks == blocks => Ok(()),
+        _ => Err(BwtError::CorruptStream("malformed block index")),
+    }
+}
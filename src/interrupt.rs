@@ -0,0 +1,85 @@
+//! Ctrl-C (and `kill`) during `compress` or `decompress`: the signal only raises a flag, the next
+//! read of the input fails, and the command removes the output it had started before exiting with
+//! status 128 + the signal number. A second signal kills the process outright.
+
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// The signal that arrived, or 0 if none has
+static RECEIVED: AtomicI32 = AtomicI32::new(0);
+
+/// Starts catching SIGINT and SIGTERM. Signals that were ignored when the process started (as
+/// they are for background jobs of a non-interactive shell) stay ignored.
+pub(crate) fn install() {
+    #[cfg(unix)]
+    sys::install();
+}
+
+/// The signal that arrived since [`install`], if any
+pub(crate) fn received() -> Option<i32> {
+    match RECEIVED.load(Ordering::Relaxed) {
+        0 => None,
+        signal => Some(signal),
+    }
+}
+
+/// The exit status for having been stopped by `signal`, as a shell reports it
+pub(crate) fn exit_code(signal: i32) -> i32 {
+    128 + signal
+}
+
+/// Passes reads through to `inner`, failing once a signal has arrived. The pipeline reads its
+/// input a block at a time, so it stops within a block of the signal.
+pub(crate) struct Interruptible<R>(pub(crate) R);
+
+impl<R: Read> Read for Interruptible<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if received().is_some() {
+            return Err(io::Error::other("interrupted"));
+        }
+        self.0.read(buf)
+    }
+}
+
+impl<R: Seek> Seek for Interruptible<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+/// std has no signal API, but it always links the C library, whose `signal` is enough here
+#[cfg(unix)]
+mod sys {
+    use std::ffi::c_int;
+    use std::sync::atomic::Ordering;
+
+    const SIGINT: c_int = 2;
+    const SIGTERM: c_int = 15;
+    const SIG_DFL: usize = 0;
+    const SIG_IGN: usize = 1;
+
+    extern "C" {
+        fn signal(signum: c_int, handler: usize) -> usize;
+    }
+
+    pub(super) fn install() {
+        for signum in [SIGINT, SIGTERM] {
+            // SAFETY: `on_signal` has the handler signature `signal` expects, and only does
+            // async-signal-safe things: an atomic store and another call to `signal`
+            unsafe {
+                if signal(signum, on_signal as extern "C" fn(c_int) as usize) == SIG_IGN {
+                    signal(signum, SIG_IGN);
+                }
+            }
+        }
+    }
+
+    extern "C" fn on_signal(signum: c_int) {
+        super::RECEIVED.store(signum, Ordering::Relaxed);
+        // The next one takes the default action, so an impatient second Ctrl-C still works
+        // SAFETY: see `install`
+        unsafe {
+            signal(signum, SIG_DFL);
+        }
+    }
+}
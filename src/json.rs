@@ -0,0 +1,172 @@
+//! The JSON form of a raw transform, as written and read by `transform --json`:
+//!
+//! ```json
+//! {"bwt":"annb$aa","sentinel_index":4}
+//! ```
+//!
+//! `bwt` is the transformed string with `$` at `sentinel_index`. Every other byte is the character
+//! with the same code point (U+0000 to U+00FF), escaped unless it's printable ASCII, so any data
+//! round-trips.
+//!
+//! `info --json` builds its own objects, quoting text with [`string`].
+
+/// Formats the transform of `bytes` with its sentinel at `sentinel_index`
+pub(crate) fn to_json(bytes: &[u8], sentinel_index: usize) -> String {
+    let mut bwt = String::with_capacity(bytes.len() + 1);
+    for (i, b) in bytes.iter().enumerate() {
+        if i == sentinel_index {
+            bwt.push('$');
+        }
+        push_escaped(&mut bwt, *b);
+    }
+    if sentinel_index == bytes.len() {
+        bwt.push('$');
+    }
+
+    format!(
+        "{{\"bwt\":\"{}\",\"sentinel_index\":{}}}",
+        bwt, sentinel_index
+    )
+}
+
+/// `text` as a quoted JSON string
+pub(crate) fn string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match u8::try_from(c) {
+            Ok(b) => push_escaped(&mut quoted, b),
+            Err(_) => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn push_escaped(out: &mut String, b: u8) {
+    match b {
+        b'"' => out.push_str("\\\""),
+        b'\\' => out.push_str("\\\\"),
+        b'\n' => out.push_str("\\n"),
+        b'\r' => out.push_str("\\r"),
+        b'\t' => out.push_str("\\t"),
+        b' '..=b'~' => out.push(b as char),
+        _ => out.push_str(&format!("\\u{:04x}", b)),
+    }
+}
+
+/// Parses the output of [`to_json`] back into the transformed bytes, without the sentinel, and
+/// the sentinel's position
+pub(crate) fn from_json(text: &str) -> Result<(Vec<u8>, usize), String> {
+    let mut parser = Parser { rest: text };
+    let mut bwt = None;
+    let mut sentinel_index = None;
+
+    parser.expect('{')?;
+    loop {
+        let key = parser.string()?;
+        parser.expect(':')?;
+        match &key[..] {
+            b"bwt" => bwt = Some(parser.string()?),
+            b"sentinel_index" => sentinel_index = Some(parser.number()?),
+            _ => return Err(format!("unknown key `{}`", String::from_utf8_lossy(&key))),
+        }
+        if !parser.eat(',') {
+            break;
+        }
+    }
+    parser.expect('}')?;
+    if !parser.rest.trim().is_empty() {
+        return Err("unexpected data after the JSON object".into());
+    }
+
+    let mut bwt = bwt.ok_or("missing `bwt`")?;
+    let sentinel_index = sentinel_index.ok_or("missing `sentinel_index`")?;
+    if bwt.get(sentinel_index) != Some(&b'$') {
+        return Err(format!(
+            "`bwt` has no `$` at sentinel index {}",
+            sentinel_index
+        ));
+    }
+    bwt.remove(sentinel_index);
+
+    Ok((bwt, sentinel_index))
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl Parser<'_> {
+    /// Skips whitespace, then consumes `c` if it's next
+    fn eat(&mut self, c: char) -> bool {
+        self.rest = self.rest.trim_start();
+        match self.rest.strip_prefix(c) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(format!("expected `{}`", c))
+        }
+    }
+
+    fn number(&mut self) -> Result<usize, String> {
+        self.rest = self.rest.trim_start();
+        let len = self
+            .rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(self.rest.len());
+        let (digits, rest) = self.rest.split_at(len);
+        self.rest = rest;
+        digits
+            .parse()
+            .map_err(|_| "expected a non-negative integer".into())
+    }
+
+    /// A string whose characters are all at most U+00FF, as the bytes with those values
+    fn string(&mut self) -> Result<Vec<u8>, String> {
+        self.expect('"')?;
+
+        let mut bytes = Vec::new();
+        let mut chars = self.rest.chars();
+        loop {
+            let c = match chars.next().ok_or("unterminated string")? {
+                '"' => break,
+                '\\' => match chars.next().ok_or("unterminated string")? {
+                    '"' => '"',
+                    '\\' => '\\',
+                    '/' => '/',
+                    'b' => '\u{8}',
+                    'f' => '\u{c}',
+                    'n' => '\n',
+                    'r' => '\r',
+                    't' => '\t',
+                    'u' => {
+                        let hex = chars.as_str().get(..4).ok_or("truncated \\u escape")?;
+                        chars = chars.as_str()[4..].chars();
+                        u32::from_str_radix(hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| format!("invalid escape `\\u{}`", hex))?
+                    }
+                    other => return Err(format!("invalid escape `\\{}`", other)),
+                },
+                c => c,
+            };
+            let b = u8::try_from(c)
+                .map_err(|_| format!("character {:?} is above U+00FF, so isn't a byte", c))?;
+            bytes.push(b);
+        }
+        self.rest = chars.as_str();
+
+        Ok(bytes)
+    }
+}
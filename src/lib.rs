@@ -0,0 +1,677 @@
+//! Burrows-Wheeler transform + run-length encoding, as used by the `rust-bwt` CLI.
+//!
+//! The public API is intentionally small:
+//!
+//! - [`BWTStr`], a byte string carrying a single sentinel, with [`BWTStr::new`],
+//!   [`BWTStr::forward_transform`], [`BWTStr::reverse_transform`], and the on-disk format via
+//!   [`BWTStr::rle_write`] and [`BWTStr::rle_read`]
+//! - [`SuffixSort`], picking the suffix sorting algorithm behind the forward transform
+//! - [`BWTByte`], a single symbol of a [`BWTStr`]: either a byte or the sentinel
+//! - [`bijective_forward`] and [`bijective_reverse`], a transform of raw bytes that needs no
+//!   sentinel
+//! - [`rotation_forward`] and [`rotation_reverse`], the sentinel-free transform bzip2 runs on each
+//!   block, with its primary index
+//! - [`compress_bytes`], [`compress_bytes_with`], [`compress_to`], [`decompress_bytes`] and
+//!   [`decompress_to`], which run the whole pipeline over in-memory buffers, configured by
+//!   [`Options`] and [`DecompressOptions`], [`compress_chunks`], which runs it lazily over a
+//!   stream of chunks, and [`compress_stream`] and [`decompress_stream`] (plus `_with` variants,
+//!   `_timed` ones returning [`StageTimes`], and `_progress` ones reporting [`Progress`]), which
+//!   run it a block at a time between a reader and a writer
+//! - [`compress_stream_resume`], which carries on a stream whose compression was stopped
+//! - [`decompress_range`], which seeks straight to the blocks holding part of the original data
+//! - [`stream_header`] and [`stream_info`], describing a compressed stream's format and blocks
+//!   without decompressing it
+//! - [`FmIndex`], for substring search over a transformed block, and [`count_matches`] and
+//!   [`locate_matches`], which search a whole compressed stream with it
+//! - [`Crc32`], the checksum stored with each block, for checking decompressed output as a whole
+//! - [`BwtError`], describing why a compressed stream couldn't be read
+//! - [`set_logger`], which installs a [`Log`] to receive diagnostics from the pipeline
+//! - [`mtf_encode`] and [`mtf_decode`], the move-to-front stage between the BWT and RLE
+//! - [`rle0_encode`] and [`rle0_decode`], the zero-run stage that can follow MTF
+//! - [`hybrid_rle_encode`] and [`hybrid_rle_decode`], the literal/run stage used without RLE0
+//! - [`huffman_encode`] and [`huffman_decode`], and [`range_encode`] and [`range_decode`], the
+//!   final entropy-coding passes, picked by [`Entropy`]
+//!
+//! The `rust-bwt` binary is built by the default `cli` feature. Depend on this crate with
+//! `default-features = false` to use the library without clap.
+//!
+//! Everything that needs the standard library is behind the default `std` feature: the block
+//! pipeline and the functions built on it, [`Crc32`]'s [`Write`](std::io::Write) impl,
+//! [`BwtError::Io`], [`set_logger`], and [`BWTStr::rle_write`] and [`BWTStr::rle_read`]. Without
+//! it the crate is `no_std` and needs only `alloc`, for embedded targets: the transforms, the
+//! MTF, RLE0, hybrid RLE, Huffman and range stages over slices, and [`FmIndex`] all remain.
+//!
+//! ```
+//! let compressed = rust_bwt::compress_bytes(b"banana bandana");
+//! assert_eq!(rust_bwt::decompress_bytes(&compressed)?, b"banana bandana");
+//! # Ok::<(), rust_bwt::BwtError>(())
+//! ```
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use core::ops::{AddAssign, Range};
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
+use std::thread;
+
+mod bijective;
+#[cfg(feature = "std")]
+mod block;
+mod bwtstring;
+mod crc32;
+mod error;
+mod fmindex;
+mod huffman;
+mod hybrid;
+#[cfg(feature = "std")]
+mod index;
+mod log;
+mod mtf;
+#[cfg(feature = "std")]
+mod parallel;
+mod range;
+mod rle0;
+mod rotation;
+mod suffix;
+
+pub use bijective::{bijective_forward, bijective_reverse};
+pub use bwtstring::{BWTByte, BWTStr};
+pub use crc32::Crc32;
+pub use error::BwtError;
+pub use fmindex::{FmIndex, DEFAULT_SAMPLE_RATE};
+pub use huffman::{huffman_decode, huffman_encode};
+pub use hybrid::{hybrid_rle_decode, hybrid_rle_encode};
+#[cfg(feature = "std")]
+pub use log::set_logger;
+pub use log::{Level, Log};
+pub use mtf::{mtf_decode, mtf_encode};
+pub use range::{range_decode, range_encode};
+pub use rle0::{rle0_decode, rle0_encode};
+pub use rotation::{rotation_forward, rotation_reverse};
+
+/// Default number of input bytes per block
+pub const DEFAULT_BLOCK_SIZE: usize = 1 << 20; // 1 MiB
+
+/// Settings for [`compress_bytes_with`]
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// Number of input bytes transformed independently per block. Must be nonzero.
+    pub block_size: usize,
+    /// Number of worker threads compressing blocks. Defaults to the number of logical CPUs. The
+    /// output is identical for any thread count.
+    pub threads: usize,
+    /// Whether to move-to-front encode each block between the BWT and RLE. Recorded in the stream
+    /// header, so decompression needs no matching setting.
+    pub mtf: bool,
+    /// Whether to zero-run (RLE0) encode each block instead of writing `(byte, count)` runs. Pays
+    /// off after MTF, whose output is dominated by zeros. Recorded in the stream header.
+    pub rle0: bool,
+    /// Whether, without RLE0, to use the literal/run hybrid encoding instead of `(byte, count)`
+    /// runs, so bytes outside of runs aren't each paid for with a count. Recorded in the stream
+    /// header.
+    pub hybrid_rle: bool,
+    /// Entropy coder run over each block as a final pass. Without one, every `(byte, count)` run
+    /// or RLE0 symbol costs whole bytes. Recorded in the stream header.
+    pub entropy: Entropy,
+    /// Whether to store a CRC-32 of each block's original bytes, and an end-of-stream marker, so
+    /// decompression detects corrupted or truncated streams. Recorded in the stream header.
+    pub checksum: bool,
+    /// Whether to use the bijective BWT ([`bijective_forward`]) instead of the sentinel one, saving
+    /// each block its sentinel index. Recorded in the stream header.
+    pub bijective: bool,
+    /// How the sentinel BWT sorts each block's suffixes. Every choice gives the same output, so
+    /// it isn't recorded; it only changes how fast compression is.
+    pub suffix_sort: SuffixSort,
+}
+
+#[cfg(feature = "std")]
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            block_size: DEFAULT_BLOCK_SIZE,
+            threads: default_threads(),
+            mtf: true,
+            rle0: true,
+            hybrid_rle: true,
+            entropy: Entropy::Huffman,
+            checksum: true,
+            bijective: false,
+            suffix_sort: SuffixSort::Auto,
+        }
+    }
+}
+
+/// Final entropy-coding pass applied to each block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Entropy {
+    /// Leave blocks as they are
+    None,
+    /// Canonical Huffman coding, see [`huffman_encode`]
+    Huffman,
+    /// Adaptive order-0 range coding, see [`range_encode`]. Slower than Huffman, but doesn't round
+    /// every symbol up to a whole number of bits.
+    Range,
+}
+
+impl Entropy {
+    /// Every coder, in the order their names are listed in help text
+    pub const ALL: [Entropy; 3] = [Entropy::None, Entropy::Huffman, Entropy::Range];
+
+    /// Name used on the command line and in config files
+    pub fn name(self) -> &'static str {
+        match self {
+            Entropy::None => "none",
+            Entropy::Huffman => "huffman",
+            Entropy::Range => "range",
+        }
+    }
+}
+
+impl fmt::Display for Entropy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Entropy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Entropy::ALL
+            .into_iter()
+            .find(|entropy| entropy.name() == s)
+            .ok_or_else(|| format!("unknown entropy coder `{}`", s))
+    }
+}
+
+/// Suffix sorting algorithm behind [`BWTStr::forward_transform_with`]. They all give the same
+/// transform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuffixSort {
+    /// Prefix doubling for small inputs, SA-IS for the rest
+    Auto,
+    /// Prefix doubling (Manber and Myers), O(n log n). Simple, and quick on small inputs.
+    Doubling,
+    /// SA-IS (Nong, Zhang and Chan), O(n). The fastest on large inputs.
+    SaIs,
+}
+
+impl SuffixSort {
+    /// Every algorithm, in the order their names are listed in help text
+    pub const ALL: [SuffixSort; 3] = [SuffixSort::Auto, SuffixSort::Doubling, SuffixSort::SaIs];
+
+    /// Name used on the command line and in config files
+    pub fn name(self) -> &'static str {
+        match self {
+            SuffixSort::Auto => "auto",
+            SuffixSort::Doubling => "doubling",
+            SuffixSort::SaIs => "sais",
+        }
+    }
+}
+
+impl fmt::Display for SuffixSort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for SuffixSort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SuffixSort::ALL
+            .into_iter()
+            .find(|suffix_sort| suffix_sort.name() == s)
+            .ok_or_else(|| format!("unknown suffix sorting algorithm `{}`", s))
+    }
+}
+
+/// Compresses `input` with BWT + RLE using the default [`Options`].
+#[cfg(feature = "std")]
+pub fn compress_bytes(input: &[u8]) -> Vec<u8> {
+    compress_bytes_with(input, &Options::default())
+}
+
+/// Compresses `input` with the stages `options` selects, BWT + MTF + RLE0 + Huffman by default. The
+/// input is split into blocks of `options.block_size` bytes, each transformed independently.
+///
+/// The header stores the input's length. The CLI compresses a block at a time, like
+/// [`compress_stream`], so it only knows the length at the end and stores it in the block index
+/// instead: its output differs from this by those bytes, but decompresses the same.
+#[cfg(feature = "std")]
+pub fn compress_bytes_with(input: &[u8], options: &Options) -> Vec<u8> {
+    block::compress_blocks(input, options)
+}
+
+/// Like [`compress_bytes_with`], but writes each block to `writer` as soon as it's ready instead of
+/// collecting the whole output in memory.
+#[cfg(feature = "std")]
+pub fn compress_to<W: io::Write>(
+    input: &[u8],
+    options: &Options,
+    writer: &mut W,
+) -> io::Result<()> {
+    block::write_blocks(input, options, writer)
+}
+
+/// Lazily compresses the concatenation of `chunks`, which may be any size. The first item is the
+/// stream header; after that, one compressed block is yielded each time enough input has been
+/// pulled from `chunks` to fill one, plus a final short block if needed. Concatenated, the items
+/// are what [`compress_bytes_with`] returns for the concatenated input, except that the header
+/// can't store the original length, which isn't known until the end.
+///
+/// Blocks are compressed on the calling thread, one per call to `next`; `options.threads` is
+/// ignored.
+#[cfg(feature = "std")]
+pub fn compress_chunks<I>(chunks: I, options: &Options) -> impl Iterator<Item = Vec<u8>>
+where
+    I: IntoIterator<Item = Vec<u8>>,
+{
+    block::CompressChunks::new(chunks.into_iter(), options)
+}
+
+/// Compresses everything read from `src` with the default [`Options`], writing to `dst` as it goes.
+/// See [`compress_stream_with`].
+#[cfg(feature = "std")]
+pub fn compress_stream<R: io::Read + Send, W: io::Write>(src: R, dst: W) -> Result<(), BwtError> {
+    compress_stream_with(src, &Options::default(), dst)
+}
+
+/// Like [`compress_to`], but reads the input from `src` one block at a time instead of needing
+/// all of it in memory: at most about two blocks per thread are held at once, whatever the input
+/// size. The output is what [`compress_chunks`] yields for the same input, so like there, the
+/// header doesn't store the original length.
+#[cfg(feature = "std")]
+pub fn compress_stream_with<R: io::Read + Send, W: io::Write>(
+    src: R,
+    options: &Options,
+    dst: W,
+) -> Result<(), BwtError> {
+    compress_stream_timed(src, options, dst).map(|_| ())
+}
+
+/// Like [`compress_stream_with`], but also returns how long each stage took
+#[cfg(feature = "std")]
+pub fn compress_stream_timed<R: io::Read + Send, W: io::Write>(
+    src: R,
+    options: &Options,
+    dst: W,
+) -> Result<StageTimes, BwtError> {
+    compress_stream_progress(src, options, dst, |_| {})
+}
+
+/// Like [`compress_stream_timed`], but calls `progress` with how far it has got after each block
+/// is written
+#[cfg(feature = "std")]
+pub fn compress_stream_progress<R: io::Read + Send, W: io::Write>(
+    src: R,
+    options: &Options,
+    mut dst: W,
+    mut progress: impl FnMut(Progress),
+) -> Result<StageTimes, BwtError> {
+    block::write_stream(src, options, &mut dst, &[], &mut progress)
+}
+
+/// Carries on a stream that [`compress_stream_progress`] was writing to `dst` when it was stopped,
+/// so a long compression needn't start over after a crash. `written` holds the progress it
+/// reported after each block, up to any block known to be safely in `dst`; `dst` is checked to
+/// start with the header `options` gives, and the rest of the stream is written after that block,
+/// over anything that follows it. `src` must continue where that block's input ended, at its
+/// `original_len`. With no blocks written, the whole stream is written from the start of `dst`.
+///
+/// The resumed stream is exactly what an uninterrupted run would have written, as long as
+/// `options` are the same, thread count aside. If `dst` is a file, the caller should truncate it to
+/// the last block's `compressed_len` if it might be longer than the rest of the stream.
+#[cfg(feature = "std")]
+pub fn compress_stream_resume<R: io::Read + Send, W: io::Read + io::Write + io::Seek>(
+    src: R,
+    options: &Options,
+    mut dst: W,
+    written: &[Progress],
+    mut progress: impl FnMut(Progress),
+) -> Result<StageTimes, BwtError> {
+    block::resume_stream(src, options, &mut dst, written, &mut progress)
+}
+
+/// Settings for [`decompress_to`]
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecompressOptions {
+    /// Number of worker threads decompressing blocks. Defaults to the number of logical CPUs.
+    pub threads: usize,
+    /// Whether to use [`BWTStr::reverse_transform_checked`], failing on a corrupted block instead
+    /// of producing garbage
+    pub checked_reverse: bool,
+    /// Whether to check each block against its stored CRC-32, if the stream has them. Turning this
+    /// off saves a pass over the output but lets corruption through.
+    pub verify_checksum: bool,
+    /// Fail with [`BwtError::OutputTooLarge`] rather than decompress more than this many bytes.
+    /// Without it, only the original length stored in the stream's header, if any, bounds how
+    /// much a crafted stream can make decompression allocate.
+    pub max_output_size: Option<u64>,
+    /// Whether the input may be several streams one after another, as `cat` of compressed files
+    /// gives, decompressing to their outputs one after another. Only a stream with checksums ends
+    /// in a marker that can be followed; without one, a stream runs to the end of the input.
+    /// Without this, anything after the first stream is corrupt.
+    pub concatenated: bool,
+}
+
+#[cfg(feature = "std")]
+impl Default for DecompressOptions {
+    fn default() -> Self {
+        Self {
+            threads: default_threads(),
+            checked_reverse: false,
+            verify_checksum: true,
+            max_output_size: None,
+            concatenated: true,
+        }
+    }
+}
+
+/// Decompresses the output of [`compress_bytes`], returning the original bytes. Streams written
+/// by earlier versions, before the versioned header or before block mode existed (a single bare
+/// RLE stream), are still accepted. If the stream stores its original length, the output is
+/// allocated up front and checked against it at the end.
+#[cfg(feature = "std")]
+pub fn decompress_bytes(input: &[u8]) -> Result<Vec<u8>, BwtError> {
+    block::decompress_blocks(input, &DecompressOptions::default())
+}
+
+/// Like [`decompress_bytes`], but writes each block's output to `writer` in order as soon as it's
+/// ready instead of collecting the whole output in memory.
+#[cfg(feature = "std")]
+pub fn decompress_to<W: io::Write>(
+    input: &[u8],
+    options: &DecompressOptions,
+    writer: &mut W,
+) -> Result<(), BwtError> {
+    block::read_blocks(input, options, writer)
+}
+
+/// Decompresses a stream read from `src` with the default [`DecompressOptions`], writing to `dst`
+/// as it goes. See [`decompress_stream_with`].
+#[cfg(feature = "std")]
+pub fn decompress_stream<R: io::Read + Send, W: io::Write>(src: R, dst: W) -> Result<(), BwtError> {
+    decompress_stream_with(src, &DecompressOptions::default(), dst)
+}
+
+/// Like [`decompress_to`], but reads the stream from `src` one block at a time instead of needing
+/// all of it in memory: at most about two blocks per thread are held at once. Bare streams from
+/// before block mode are a single block, so they're still read whole.
+#[cfg(feature = "std")]
+pub fn decompress_stream_with<R: io::Read + Send, W: io::Write>(
+    src: R,
+    options: &DecompressOptions,
+    dst: W,
+) -> Result<(), BwtError> {
+    decompress_stream_timed(src, options, dst).map(|_| ())
+}
+
+/// Like [`decompress_stream_with`], but also returns how long each stage took
+#[cfg(feature = "std")]
+pub fn decompress_stream_timed<R: io::Read + Send, W: io::Write>(
+    src: R,
+    options: &DecompressOptions,
+    dst: W,
+) -> Result<StageTimes, BwtError> {
+    decompress_stream_progress(src, options, dst, |_| {})
+}
+
+/// Like [`decompress_stream_timed`], but calls `progress` with how far it has got after each
+/// block is written
+#[cfg(feature = "std")]
+pub fn decompress_stream_progress<R: io::Read + Send, W: io::Write>(
+    src: R,
+    options: &DecompressOptions,
+    mut dst: W,
+    mut progress: impl FnMut(Progress),
+) -> Result<StageTimes, BwtError> {
+    block::read_stream(src, options, &mut dst, &mut progress)
+}
+
+/// Decompresses just the bytes of the original data in `range`, writing them to `dst`. Only the
+/// blocks holding them are read and decoded, found from the block index at the end of `src`, which
+/// must be a single stream written with checksums by this version or later. Fails with
+/// [`BwtError::NoIndex`] if it has no index, in which case it can still be decompressed as a
+/// whole, and with [`BwtError::RangeOutOfBounds`] if `range` ends past the end of the data.
+#[cfg(feature = "std")]
+pub fn decompress_range<R: io::Read + io::Seek + Send, W: io::Write>(
+    src: R,
+    range: Range<u64>,
+    options: &DecompressOptions,
+    mut dst: W,
+) -> Result<(), BwtError> {
+    block::read_range(src, range, options, &mut dst)
+}
+
+/// How far [`compress_stream_progress`] or [`decompress_stream_progress`] has got
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// Bytes of original data compressed or decompressed so far
+    pub original_len: u64,
+    /// Bytes of the compressed stream written or read so far, header included
+    pub compressed_len: u64,
+    /// Blocks finished so far
+    pub blocks: u64,
+    /// Blocks decompressed so far whose output was checked against their CRC-32. Fewer than
+    /// `blocks` if a stream has no checksums or [`DecompressOptions::verify_checksum`] is off;
+    /// always 0 when compressing.
+    pub checked_blocks: u64,
+}
+
+/// Time spent in each stage of [`compress_stream_timed`] or [`decompress_stream_timed`]. Blocks
+/// are worked on in parallel, so the stage times are summed over every thread and can add up to
+/// more than the wall time. On `wasm32-unknown-unknown`, which has no clock, every time is zero.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StageTimes {
+    /// The BWT, or its reverse
+    pub transform: Duration,
+    /// Move-to-front coding
+    pub mtf: Duration,
+    /// Run-length coding
+    pub runs: Duration,
+    /// The entropy coder
+    pub entropy: Duration,
+    /// Computing and checking CRC-32s
+    pub checksum: Duration,
+    /// Reading the input and writing the output
+    pub io: Duration,
+}
+
+#[cfg(feature = "std")]
+impl AddAssign for StageTimes {
+    fn add_assign(&mut self, other: Self) {
+        self.transform += other.transform;
+        self.mtf += other.mtf;
+        self.runs += other.runs;
+        self.entropy += other.entropy;
+        self.checksum += other.checksum;
+        self.io += other.io;
+    }
+}
+
+/// What a compressed stream's header says about it, as returned by [`stream_header`]
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamHeader {
+    /// Format version from the header, or `None` for streams from before the versioned header
+    pub version: Option<u8>,
+    /// Whether blocks were move-to-front encoded
+    pub mtf: bool,
+    /// Whether blocks were zero-run (RLE0) encoded rather than `(byte, count)` runs
+    pub rle0: bool,
+    /// Whether blocks were literal/run hybrid encoded rather than `(byte, count)` runs
+    pub hybrid_rle: bool,
+    /// The final entropy-coding pass
+    pub entropy: Entropy,
+    /// Whether each block carries a CRC-32 of its original bytes
+    pub checksum: bool,
+    /// Total size of the original data, if the stream stores it. Streams written by
+    /// [`compress_chunks`], which can't know it up front, and by older versions don't.
+    pub original_len: Option<u64>,
+    /// Whether blocks were transformed with the bijective BWT rather than the sentinel one
+    pub bijective: bool,
+    /// Whether the stream ends with a block index, which [`decompress_range`] needs
+    pub index: bool,
+}
+
+/// What [`stream_info`] learned about a compressed stream
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamInfo {
+    pub header: StreamHeader,
+    /// Every block, in stream order
+    pub blocks: Vec<BlockInfo>,
+    /// Whether the stream ended early: partway through a block or before its end-of-stream
+    /// marker, or short of its stored original length. `blocks` holds the blocks before that.
+    pub truncated: bool,
+}
+
+#[cfg(feature = "std")]
+impl StreamInfo {
+    /// Total size of the blocks, not counting the stream header or block length prefixes
+    pub fn compressed_len(&self) -> usize {
+        self.blocks.iter().map(|block| block.compressed_len).sum()
+    }
+
+    /// Total size of the original data
+    pub fn original_len(&self) -> usize {
+        self.blocks.iter().map(|block| block.original_len).sum()
+    }
+}
+
+/// One block of a [`StreamInfo`]
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockInfo {
+    /// Size of the block in the stream, not counting its length prefix
+    pub compressed_len: usize,
+    /// Size of the block's original data
+    pub original_len: usize,
+    /// Position of the sentinel in the block's transformed data, or `None` for a bijective
+    /// transform, which has none
+    pub sentinel_index: Option<usize>,
+    /// Maps each run length in the block's transformed data to how many runs have it, as
+    /// [`BWTStr::run_length_histogram`] counts them
+    pub run_lengths: BTreeMap<usize, u64>,
+    /// The stored CRC-32 of the block's original data, if the stream has them
+    pub crc32: Option<u32>,
+}
+
+#[cfg(feature = "std")]
+impl BlockInfo {
+    /// Number of runs of equal bytes in the block's transformed data
+    pub fn runs(&self) -> u64 {
+        self.run_lengths.values().sum()
+    }
+}
+
+/// Reads a compressed stream's header, describing its format and stages, and its original length
+/// if stored, without decoding any blocks.
+#[cfg(feature = "std")]
+pub fn stream_header(input: &[u8]) -> Result<StreamHeader, BwtError> {
+    block::stream_header(input)
+}
+
+/// Describes a compressed stream: its format, stages, and the size, sentinel index and runs of
+/// every block. A truncated stream is described as far as it goes rather than failing. Much
+/// cheaper than decompressing it, since no BWT is reversed, but the blocks are still
+/// entropy and run-length decoded.
+#[cfg(feature = "std")]
+pub fn stream_info(input: &[u8]) -> Result<StreamInfo, BwtError> {
+    block::stream_info(input)
+}
+
+/// Counts how many times `pattern` occurs in the data compressed into `input`, overlapping
+/// occurrences included, by FM-index backward search over each block's transform. No transform is
+/// reversed, but the blocks are still entropy and run-length decoded. Occurrences spanning block
+/// boundaries are found from the bytes on either side of each one.
+#[cfg(feature = "std")]
+pub fn count_matches(input: &[u8], pattern: &[u8]) -> Result<u64, BwtError> {
+    block::count_matches(input, pattern)
+}
+
+/// Like [`count_matches`], but returns the 0-based position in the original data of every
+/// occurrence, in increasing order. Each block's index keeps the suffix array position of every
+/// `sample_rate`th byte (see [`FmIndex::with_sample_rate`]); lower rates are faster but use more
+/// memory. `sample_rate` must be nonzero.
+#[cfg(feature = "std")]
+pub fn locate_matches(
+    input: &[u8],
+    pattern: &[u8],
+    sample_rate: usize,
+) -> Result<Vec<u64>, BwtError> {
+    block::locate_matches(input, pattern, sample_rate)
+}
+
+#[cfg(feature = "std")]
+fn default_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    /// `count` inputs of random lengths over random alphabets, from a fixed seed, after the edge
+    /// cases: empty, a single byte, all one byte, and every byte value
+    fn generated_inputs(count: usize) -> Vec<Vec<u8>> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 32) as usize
+        };
+
+        let mut inputs = vec![Vec::new(), vec![0], vec![0xFF], vec![7; 1000]];
+        inputs.extend((0..count).map(|_| {
+            let len = match next() % 4 {
+                0 => next() % 8,
+                1 => next() % 100,
+                _ => next() % 3000,
+            };
+            let alphabet = [1, 2, 3, 4, 26, 256][next() % 6];
+            let base = next() % (257 - alphabet);
+            (0..len).map(|_| (base + next() % alphabet) as u8).collect()
+        }));
+        inputs
+    }
+
+    #[test]
+    fn generated_inputs_round_trip() {
+        // Small blocks, so that inputs span several
+        let options = Options {
+            block_size: 257,
+            ..Options::default()
+        };
+        for input in generated_inputs(200) {
+            assert_eq!(decompress_bytes(&compress_bytes(&input)).unwrap(), input);
+            assert_eq!(
+                decompress_bytes(&compress_bytes_with(&input, &options)).unwrap(),
+                input
+            );
+        }
+    }
+}
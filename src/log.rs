@@ -0,0 +1,71 @@
+//! Diagnostics from inside the pipeline. Nothing is logged, or even formatted, until a [`Log`] is
+//! installed with [`set_logger`]. Without the `std` feature there's nowhere to install one, so
+//! nothing is ever logged.
+
+use core::fmt;
+#[cfg(feature = "std")]
+use std::sync::OnceLock;
+
+/// How detailed a message is. Later levels are more detailed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    /// Once per stream: the settings used and how long each stage took
+    Debug,
+    /// Once per block or transform: sizes and positions
+    Trace,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::Debug => f.write_str("debug"),
+            Level::Trace => f.write_str("trace"),
+        }
+    }
+}
+
+/// Receives the library's diagnostics. Called from worker threads as well as the caller's.
+pub trait Log: Sync + Send {
+    /// Whether messages at `level` are wanted. Checked before a message is formatted.
+    fn enabled(&self, level: Level) -> bool;
+
+    fn log(&self, level: Level, message: fmt::Arguments<'_>);
+}
+
+#[cfg(feature = "std")]
+static LOGGER: OnceLock<&'static dyn Log> = OnceLock::new();
+
+/// Installs `logger` for the rest of the process. Only one logger can be installed; if there
+/// already is one, `logger` is handed back.
+#[cfg(feature = "std")]
+pub fn set_logger(logger: &'static dyn Log) -> Result<(), &'static dyn Log> {
+    LOGGER.set(logger)
+}
+
+/// Passes `message` to the installed logger, if it wants messages at `level`
+#[cfg(feature = "std")]
+pub(crate) fn log(level: Level, message: fmt::Arguments<'_>) {
+    if let Some(logger) = LOGGER.get().filter(|logger| logger.enabled(level)) {
+        logger.log(level, message);
+    }
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) fn log(_level: Level, _message: fmt::Arguments<'_>) {}
+
+#[cfg(feature = "std")]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Level::Debug, format_args!($($arg)*))
+    };
+}
+
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Level::Trace, format_args!($($arg)*))
+    };
+}
+
+#[cfg(feature = "std")]
+pub(crate) use debug;
+pub(crate) use trace;
@@ -1,14 +1,49 @@
 use clap::{
     builder::styling::{AnsiColor, Styles},
-    ArgGroup, Args, ColorChoice, CommandFactory, Parser, Subcommand,
+    builder::{PossibleValuesParser, TypedValueParser},
+    ArgAction, Args, CommandFactory, Parser, Subcommand,
 };
 use clap_complete::{generate, Shell};
+use rust_bwt::{Entropy, SuffixSort};
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom, Write};
-use std::path::PathBuf;
+use std::io::{IsTerminal, Read, Seek, SeekFrom, Write};
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Instant;
 
-mod bwtstring;
-use bwtstring::*;
+mod archive;
+mod bench;
+mod checkpoint;
+mod config;
+mod interrupt;
+mod json;
+mod progress;
+mod roundtrip;
+mod stats;
+mod volume;
+
+/// Suffix `compress` appends to its output file names by default, and `decompress` strips
+const DEFAULT_SUFFIX: &str = ".bwt";
+
+/// Inputs smaller than this aren't warned about growing when compressed: the stream header, block
+/// index and per-block fields alone can outweigh them
+const MIN_GROWTH_WARNING_LEN: u64 = 4096;
+
+/// How much of the input `compress` looks at to decide whether to tune its settings for DNA
+const TUNING_SAMPLE_LEN: u64 = 64 << 10; // 64 KiB
+
+/// Inputs with at most this many distinct bytes, like DNA's four bases, get DNA-tuned settings
+const DNA_MAX_ALPHABET: usize = 4;
+
+/// Inputs shorter than this are left untuned: a few bytes from a small alphabet aren't DNA, and
+/// wouldn't compress any differently
+const DNA_MIN_LEN: usize = 1024;
+
+/// Block size for DNA-tuned input. DNA repeats over long distances, which larger blocks catch.
+const DNA_BLOCK_SIZE: usize = 4 << 20; // 4 MiB
 
 #[derive(Parser)]
 #[command(
@@ -22,15 +57,47 @@ use bwtstring::*;
         .literal(AnsiColor::Green.on_default())
         .placeholder(AnsiColor::Green.on_default()))
 ]
+#[command(after_help = "\
+Exit status:
+  0  success
+  1  I/O error or other failure
+  2  invalid command-line arguments
+  3  the compressed input is truncated or corrupt
+  4  a block failed its CRC-32 check
+  5  the input isn't a rust-bwt stream, or was written by a newer version
+  6  the output would exceed --max-output-size
+  130  compress or decompress was interrupted with Ctrl-C (143 for SIGTERM)")]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Log the settings and time spent in each stage to stderr; repeat (-vv) to also log every
+    /// block
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     Compress(CompressArgs),
     Decompress(DecompressArgs),
+    /// Burrows-Wheeler transform data without compressing it, or undo such a transform
+    Transform(TransformArgs),
+    /// Undo a raw transform written by `transform`; the same as `transform --reverse`
+    InverseTransform(InverseTransformArgs),
+    /// Check that compressed files decompress cleanly, without writing any output. Every file is
+    /// checked even if an earlier one fails
+    #[command(alias = "test")]
+    Verify(VerifyArgs),
+    /// Show a compressed file's format, sizes and blocks
+    Info(InfoArgs),
+    /// Count the occurrences of a pattern in a compressed file without decompressing it
+    Count(CountArgs),
+    /// Print the 0-based position of every occurrence of a pattern in a compressed file, without
+    /// decompressing it
+    Locate(LocateArgs),
+    /// Time compression and decompression of a file or of generated text, reporting MB/s and the
+    /// compression ratio
+    Bench(BenchArgs),
 
     /// Generate shell completion scripts with clap_complete
     Completions {
@@ -40,12 +107,13 @@ enum Commands {
 }
 
 #[derive(Args)]
-#[command(group(
-    ArgGroup::new("input")
-        .required(true)
-        .args(&["input_file", "input_string"]),
-))]
 struct CompressArgs {
+    /// Input files, each compressed to its own output. A failure is reported and the rest are still
+    /// compressed
+    #[arg(value_name = "FILE", conflicts_with_all = ["input_file", "input_string"])]
+    files: Vec<PathBuf>,
+    /// Input file, or `-` for stdin. Stdin is also used when no input is given and it isn't a
+    /// terminal
     #[arg(short, long, value_name = "FILE", conflicts_with = "input_string")]
     input_file: Option<PathBuf>,
     #[arg(
@@ -55,32 +123,466 @@ struct CompressArgs {
         conflicts_with = "input_file"
     )]
     input_string: Option<String>,
+    /// Output file, or `-` for stdout. Defaults to the input file with the suffix appended, or to
+    /// stdout when reading a string or stdin. Only one input file may be given with it
     #[arg(short, long, value_name = "FILE")]
     output: Option<PathBuf>,
+    /// Write to stdout instead of a file named after the input
+    #[arg(short = 'c', long, conflicts_with_all = ["output", "split_size"])]
+    stdout: bool,
+    /// Suffix appended to input file names to name their outputs
+    #[arg(short = 'S', long, value_name = "SUFFIX", default_value = DEFAULT_SUFFIX, value_parser = parse_suffix)]
+    suffix: String,
+    /// Split the output into volumes of at most this size (e.g. 10MiB), named after --output with
+    /// .001, .002, ... appended. Decompress the set by passing the .001 volume
+    #[arg(long, value_name = "SIZE", requires = "output", value_parser = volume::parse_size)]
+    split_size: Option<NonZeroU64>,
+    /// Read compression settings from a config file. Flags given on the command line override it
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Number of input bytes transformed independently per block [default: 1048576]
+    #[arg(short, long, value_name = "BYTES")]
+    block_size: Option<NonZeroUsize>,
+    /// Number of blocks to compress in parallel; 0 uses every logical CPU [default: 0]
+    #[arg(short, long, value_name = "N")]
+    threads: Option<usize>,
+    /// Move-to-front encode each block between the BWT and RLE [default: true]
+    #[arg(long, value_name = "BOOL", action = ArgAction::Set)]
+    mtf: Option<bool>,
+    /// Zero-run (RLE0) encode each block instead of writing (byte, count) runs [default: true]
+    #[arg(long, value_name = "BOOL", action = ArgAction::Set)]
+    rle0: Option<bool>,
+    /// Without RLE0, write literal bytes and escaped runs instead of a (byte, count) pair for
+    /// every run [default: true]
+    #[arg(long, value_name = "BOOL", action = ArgAction::Set)]
+    hybrid_rle: Option<bool>,
+    /// Store a CRC-32 of every block so decompression catches corrupted or truncated data [default: true]
+    #[arg(long, value_name = "BOOL", action = ArgAction::Set)]
+    checksum: Option<bool>,
+    /// Entropy coder for the final pass over each block [default: huffman]
+    #[arg(
+        long,
+        visible_alias = "coder",
+        value_name = "CODER",
+        value_parser = PossibleValuesParser::new(Entropy::ALL.map(Entropy::name))
+            .map(|name| name.parse::<Entropy>().unwrap()),
+    )]
+    entropy: Option<Entropy>,
+    /// Shorthand for --entropy huffman
+    #[arg(long, conflicts_with_all = ["entropy", "no_entropy"])]
+    huffman: bool,
+    /// Shorthand for --entropy none
+    #[arg(long, conflicts_with = "entropy")]
+    no_entropy: bool,
+    /// Store mode: shorthand for --mtf false --rle0 false --hybrid-rle true --entropy none, which
+    /// adds the least overhead to random or already compressed input
+    #[arg(long, conflicts_with_all = ["mtf", "rle0", "hybrid_rle", "entropy", "huffman", "no_entropy"])]
+    store: bool,
+    /// Use the bijective BWT, which needs no sentinel, instead of the standard one
+    #[arg(long)]
+    bijective: bool,
+    /// Look at the start of the input, and if it has at most 4 distinct bytes, like DNA, use
+    /// 4 MiB blocks and skip MTF (and RLE0, which only pays off after it). Settings given as flags
+    /// are kept, and nothing is tuned with --config or for inputs under 1 KiB [default: true]
+    #[arg(long, value_name = "BOOL", action = ArgAction::Set)]
+    auto_tune: Option<bool>,
+    /// Suffix sorting algorithm for the standard BWT: prefix doubling, SA-IS, or (auto) doubling
+    /// for small blocks and SA-IS for the rest. The output is the same either way [default: auto]
+    #[arg(
+        long,
+        value_name = "ALGO",
+        value_parser = PossibleValuesParser::new(SuffixSort::ALL.map(SuffixSort::name))
+            .map(|name| name.parse::<SuffixSort>().unwrap()),
+    )]
+    sa_algo: Option<SuffixSort>,
+    /// Decompress the output while writing it and check that it matches the input. On a mismatch
+    /// the output file is deleted
+    #[arg(long, visible_alias = "verify-after")]
+    verify: bool,
+    /// Add the compressed stream to the end of the output file instead of overwriting it, so
+    /// decompressing the file gives every input appended to it in turn. Without --force, a file
+    /// that's already there must hold compressed streams with checksums
+    #[arg(long, conflicts_with_all = ["stdout", "split_size", "archive"])]
+    append: bool,
+    /// Record each block in FILE.checkpoint, FILE being the output, once it's synced to disk, so
+    /// an interrupted run can be carried on with --resume. If compression fails or is interrupted,
+    /// the output and its checkpoint are kept; the checkpoint is removed once the output is done
+    #[arg(
+        long,
+        conflicts_with_all = ["stdout", "split_size", "append", "archive", "verify"]
+    )]
+    checkpoint: bool,
+    /// Carry on a --checkpoint run that was interrupted, after the last block its checkpoint
+    /// records. The input and settings must be the same; the finished output is what an
+    /// uninterrupted run would have written
+    #[arg(
+        long,
+        conflicts_with_all = ["stdout", "split_size", "append", "archive", "verify", "force"]
+    )]
+    resume: bool,
+    /// Overwrite the output file if it already exists, and write the output to stdout even if it
+    /// is a terminal
+    #[arg(short, long)]
+    force: bool,
+    /// Keep the input file instead of deleting it once the output file has been written and
+    /// synced to disk (and verified, with --verify). It's never deleted when writing to stdout or
+    /// when compression fails
+    #[arg(short, long)]
+    keep: bool,
+    /// Delete the input file once the output file has been written and synced to disk (and
+    /// verified, with --verify). That's the default, so this only spells it out, for scripts
+    /// written for tools that keep the input unless asked
+    #[arg(long, conflicts_with = "keep")]
+    rm: bool,
+    /// Print sizes, the compression ratio, throughput and the time spent in each stage to stderr
+    #[arg(long)]
+    stats: bool,
+    /// Like --stats, but as a single JSON object
+    #[arg(long, conflicts_with = "stats")]
+    stats_json: bool,
+    /// Show a progress bar on stderr right away, advancing as each block is written. Without it,
+    /// the bar only appears once a run has taken over a second. Ignored when stderr isn't a
+    /// terminal
+    #[arg(long)]
+    progress: bool,
+    /// Don't show a progress bar, warnings, or a status line per file. Errors are still reported
+    #[arg(short, long, conflicts_with = "progress")]
+    quiet: bool,
+    /// Pack the input files into a single archive at FILE, each stored under its own name and
+    /// compressed separately. The inputs are kept
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "files",
+        conflicts_with_all = ["output", "stdout", "split_size", "verify", "stats", "stats_json", "progress"]
+    )]
+    archive: Option<PathBuf>,
+    /// With --archive, also store the contents of directories, and of directories inside them.
+    /// Symbolic links and other special files inside them are skipped
+    #[arg(short, long, requires = "archive")]
+    recursive: bool,
 }
 
 #[derive(Args)]
 struct DecompressArgs {
+    /// Input files, each decompressed to its own output. A failure is reported and the rest are
+    /// still decompressed
+    #[arg(value_name = "FILE", conflicts_with = "input_file")]
+    files: Vec<PathBuf>,
+    /// Input file, or `-` for stdin. Stdin is also used when no input file is given
     #[arg(short, long, value_name = "FILE")]
+    input_file: Option<PathBuf>,
+    /// Output file, or `-` for stdout. Defaults to the input file without its suffix, or to
+    /// stdout when reading stdin. Only one input file may be given with it. With --archive, the
+    /// directory to extract into, by default the current one
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+    /// Write to stdout instead of a file named after the input, even if it is a terminal and the
+    /// data looks binary
+    #[arg(short = 'c', long, conflicts_with = "output")]
+    stdout: bool,
+    /// Suffix stripped from the input file name to name the output
+    #[arg(short = 'S', long, value_name = "SUFFIX", default_value = DEFAULT_SUFFIX, value_parser = parse_suffix)]
+    suffix: String,
+    /// Overwrite the output file if it already exists, and write the output to stdout even if it
+    /// is a terminal and the data looks binary
+    #[arg(short, long)]
+    force: bool,
+    /// Number of blocks to decompress in parallel; 0 uses every logical CPU [default: 0]
+    #[arg(short, long, value_name = "N")]
+    threads: Option<usize>,
+    /// Check every step of the reverse transform, failing on corrupted data instead of
+    /// producing garbage
+    #[arg(long)]
+    checked_reverse: bool,
+    /// Skip checking each block against its stored CRC-32
+    #[arg(long)]
+    no_verify: bool,
+    /// Fully decompress and check the input, but discard the output. The exit status tells whether
+    /// the input is valid
+    #[arg(long, conflicts_with_all = ["output", "force"])]
+    verify_only: bool,
+    /// Fail instead of decompressing more than this much (e.g. 2GiB). Guards against crafted
+    /// input that claims an enormous size
+    #[arg(long, value_name = "SIZE", value_parser = volume::parse_size)]
+    max_output_size: Option<NonZeroU64>,
+    /// Treat anything after the first stream as corrupt, instead of decompressing any streams
+    /// that follow it (as `cat a.bwt b.bwt` gives) one after another
+    #[arg(long)]
+    single_stream: bool,
+    /// Only write bytes START up to END of the original data. Streams with a block index (any
+    /// written with checksums by this version) are seeked through, decoding only the blocks that
+    /// hold the range; others are decompressed from the start with a warning. The input is kept
+    #[arg(
+        long,
+        value_name = "START..END",
+        value_parser = parse_range,
+        conflicts_with_all = ["verify_only", "rm", "stats", "stats_json", "archive"]
+    )]
+    range: Option<Range<u64>>,
+    /// Like --range, starting at byte O of the original data. Needs --length
+    #[arg(
+        long,
+        value_name = "O",
+        requires = "length",
+        conflicts_with_all = ["range", "verify_only", "rm", "stats", "stats_json", "archive"]
+    )]
+    offset: Option<u64>,
+    /// Like --range, writing L bytes of the original data from --offset, or from the start
+    #[arg(
+        long,
+        value_name = "L",
+        conflicts_with_all = ["range", "verify_only", "rm", "stats", "stats_json", "archive"]
+    )]
+    length: Option<u64>,
+    /// Delete the input file (or every volume of a split stream) once the output file has been
+    /// written and synced to disk, even if the output couldn't be checked because the stream has no
+    /// checksums or --no-verify is given. By default the input is only deleted once every block
+    /// has passed its CRC-32 check. It's never deleted when writing to stdout or when
+    /// decompression fails
+    #[arg(long, conflicts_with = "verify_only")]
+    rm: bool,
+    /// Keep the input file instead of deleting it
+    #[arg(short, long, conflicts_with = "rm")]
+    keep: bool,
+    /// Print sizes, the compression ratio, throughput and the time spent in each stage to stderr
+    #[arg(long)]
+    stats: bool,
+    /// Like --stats, but as a single JSON object
+    #[arg(long, conflicts_with = "stats")]
+    stats_json: bool,
+    /// Show a progress bar on stderr right away, advancing as each block is written. Without it,
+    /// the bar only appears once a run has taken over a second. Ignored when stderr isn't a
+    /// terminal
+    #[arg(long)]
+    progress: bool,
+    /// Don't show a progress bar, warnings, or a status line per file. Errors are still reported
+    #[arg(short, long, conflicts_with = "progress")]
+    quiet: bool,
+    /// The input is an archive written by `compress --archive`: extract its members, keeping the
+    /// archive
+    #[arg(
+        long,
+        conflicts_with_all = ["stdout", "verify_only", "rm", "stats", "stats_json", "progress"]
+    )]
+    archive: bool,
+    /// With --archive, only extract the member at PATH, and everything in it if it's a directory
+    #[arg(long, value_name = "PATH", requires = "archive")]
+    member: Option<String>,
+    /// With --archive, list the members instead of extracting them
+    #[arg(long, requires = "archive", conflicts_with_all = ["member", "output", "force"])]
+    list: bool,
+    /// With --archive, extract every file straight into the output directory under its own name,
+    /// dropping the directories in its path. Two files with the same name are an error unless
+    /// --force is given, when the later one wins
+    #[arg(long, requires = "archive", conflicts_with = "list")]
+    to_dir_flat: bool,
+}
+
+#[derive(Args)]
+struct VerifyArgs {
+    /// Compressed files, or `-` for stdin
+    #[arg(value_name = "FILE", required = true)]
+    input_files: Vec<PathBuf>,
+    /// Number of blocks to decompress in parallel; 0 uses every logical CPU [default: 0]
+    #[arg(short, long, value_name = "N")]
+    threads: Option<usize>,
+}
+
+#[derive(Args)]
+struct InfoArgs {
+    /// Compressed file, or `-` for stdin
+    #[arg(value_name = "FILE")]
     input_file: PathBuf,
+    /// Only read the stream header, without decoding any blocks. The original size is then only
+    /// known if the stream stores it
+    #[arg(long)]
+    header_only: bool,
+    /// Print the summary as a single JSON object
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct InverseTransformArgs {
+    /// Transform to undo, or `-` for stdin. Stdin is also used when no input is given and it isn't
+    /// a terminal
+    #[arg(short, long, value_name = "FILE")]
+    input_file: Option<PathBuf>,
     #[arg(short, long, value_name = "FILE")]
     output: Option<PathBuf>,
+    /// Overwrite the output file if it already exists
+    #[arg(short, long)]
+    force: bool,
+    /// Read the transform as JSON, as written by `transform --json`
+    #[arg(long)]
+    json: bool,
+    /// Undo a transform written by `transform --bzip2`
+    #[arg(long, conflicts_with = "json")]
+    bzip2: bool,
+}
+
+#[derive(Args)]
+struct CountArgs {
+    /// Compressed file, or `-` for stdin
+    #[arg(value_name = "FILE")]
+    input_file: PathBuf,
+    /// Text to search for
+    #[arg(value_name = "PATTERN")]
+    pattern: String,
+}
+
+#[derive(Args)]
+struct LocateArgs {
+    /// Compressed file, or `-` for stdin
+    #[arg(value_name = "FILE")]
+    input_file: PathBuf,
+    /// Text to search for
+    #[arg(value_name = "PATTERN")]
+    pattern: String,
+    /// Keep the suffix array position of every Nth byte while searching. Lower values are faster
+    /// but use more memory
+    #[arg(long, value_name = "N", default_value_t = NonZeroUsize::new(rust_bwt::DEFAULT_SAMPLE_RATE).unwrap())]
+    sample_rate: NonZeroUsize,
+}
+
+#[derive(Args)]
+struct BenchArgs {
+    /// Benchmark this file instead of generated text
+    #[arg(short, long, value_name = "FILE", conflicts_with = "size")]
+    input_file: Option<PathBuf>,
+    /// Amount of text to generate (e.g. 10MiB)
+    #[arg(long, value_name = "SIZE", default_value = "1MiB", value_parser = volume::parse_size)]
+    size: NonZeroU64,
+    /// Number of input bytes transformed independently per block [default: 1048576]
+    #[arg(short, long, value_name = "BYTES")]
+    block_size: Option<NonZeroUsize>,
+    /// Number of blocks to work on in parallel; 0 uses every logical CPU [default: 0]
+    #[arg(short, long, value_name = "N")]
+    threads: Option<usize>,
+    /// Timed runs of each direction, after one untimed warmup run. The fastest is reported
+    #[arg(long, value_name = "N", default_value = "3")]
+    iterations: NonZeroUsize,
+    /// Suffix sorting algorithm for the BWT, as for compress [default: auto]
+    #[arg(
+        long,
+        value_name = "ALGO",
+        value_parser = PossibleValuesParser::new(SuffixSort::ALL.map(SuffixSort::name))
+            .map(|name| name.parse::<SuffixSort>().unwrap()),
+    )]
+    sa_algo: Option<SuffixSort>,
+}
+
+#[derive(Args)]
+struct TransformArgs {
+    /// Transform the input (the default). Without --json, the output is the sentinel index as a
+    /// little-endian u64 followed by the transformed bytes, without the sentinel
+    #[arg(long, conflicts_with = "reverse")]
+    forward: bool,
+    /// Undo a transform written by --forward
+    #[arg(long)]
+    reverse: bool,
+    /// Input file, or `-` for stdin. Stdin is also used when no input is given and it isn't a
+    /// terminal
+    #[arg(
+        short,
+        long,
+        visible_short_alias = 'f',
+        value_name = "FILE",
+        conflicts_with = "input_string"
+    )]
+    input_file: Option<PathBuf>,
+    #[arg(short = 's', long, value_name = "STRING")]
+    input_string: Option<String>,
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+    /// Overwrite the output file if it already exists. There's no short form, since `-f` is
+    /// --input-file here
+    #[arg(long)]
+    force: bool,
+    /// Write (--forward) or read (--reverse) the transform as JSON, like
+    /// {"bwt":"annb$aa","sentinel_index":4}, with bytes other than printable ASCII escaped
+    #[arg(long)]
+    json: bool,
+    /// Use bzip2's block transform, which sorts every rotation of the input with no sentinel. The
+    /// output is `RBWR`, bzip2's primary index (origPtr) as a little-endian u64, then the
+    /// transformed bytes. bzip2 shortens runs of 4 or more bytes before transforming a block, so
+    /// compare it on a block after that pass
+    #[arg(long, conflicts_with_all = ["json", "show_sentinel"])]
+    bzip2: bool,
+    /// Print the transform as text with `$` marking the sentinel, like `annb$aa`, for reading
+    /// rather than for --reverse. Bytes are printed as they are, so a `$` in the input is
+    /// ambiguous
+    #[arg(long, conflicts_with_all = ["reverse", "json"])]
+    show_sentinel: bool,
 }
 
 fn main() {
     let cli = Cli::parse();
 
+    let max_level = match cli.verbose {
+        0 => None,
+        1 => Some(rust_bwt::Level::Debug),
+        _ => Some(rust_bwt::Level::Trace),
+    };
+    if let Some(max_level) = max_level {
+        let logger = LOGGER.get_or_init(|| StderrLogger { max_level });
+        // Nothing else installs a logger
+        let _ = rust_bwt::set_logger(logger);
+    }
+
     match &cli.command {
         Commands::Compress(args) => {
-            if let Err(e) = compress(args) {
-                eprintln!("Error during compression: {}", e);
-                std::process::exit(1);
+            interrupt::install();
+            if let Some(code) = compress(args) {
+                std::process::exit(code);
             }
         }
         Commands::Decompress(args) => {
-            if let Err(e) = decompress(args) {
-                eprintln!("Error during decompression: {}", e);
-                std::process::exit(1);
+            interrupt::install();
+            if let Some(code) = decompress(args) {
+                std::process::exit(code);
+            }
+        }
+        Commands::Transform(args) => {
+            if let Err(e) = transform(args) {
+                eprintln!("Error during transform: {}", e);
+                std::process::exit(exit_code(&*e));
+            }
+        }
+        Commands::InverseTransform(args) => {
+            if let Err(e) = inverse_transform(args) {
+                eprintln!("Error during inverse transform: {}", e);
+                std::process::exit(exit_code(&*e));
+            }
+        }
+        Commands::Verify(args) => {
+            if let Some(code) = verify(args) {
+                std::process::exit(code);
+            }
+        }
+        Commands::Info(args) => {
+            if let Err(e) = info(args) {
+                eprintln!("Error reading stream info: {}", e);
+                std::process::exit(exit_code(&*e));
+            }
+        }
+        Commands::Count(args) => {
+            if let Err(e) = count(args) {
+                eprintln!("Error during search: {}", e);
+                std::process::exit(exit_code(&*e));
+            }
+        }
+        Commands::Locate(args) => {
+            if let Err(e) = locate(args) {
+                eprintln!("Error during search: {}", e);
+                std::process::exit(exit_code(&*e));
+            }
+        }
+        Commands::Bench(args) => {
+            if let Err(e) = bench(args) {
+                eprintln!("Error during benchmark: {}", e);
+                std::process::exit(exit_code(&*e));
             }
         }
         Commands::Completions { shell } => {
@@ -91,63 +593,1570 @@ fn main() {
     }
 }
 
-fn compress(args: &CompressArgs) -> Result<(), Box<dyn std::error::Error>> {
-    // Read input data
-    let input_data = if let Some(input_file) = &args.input_file {
-        let mut file = File::open(input_file)?;
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer)?;
-        buffer
-    } else if let Some(input_string) = &args.input_string {
-        input_string.clone().into_bytes()
-    } else {
-        unreachable!("Input is required");
+/// Exit status for a failed command, so scripts can tell failures apart without parsing messages.
+/// 2 is left to clap for usage errors.
+fn exit_code(e: &(dyn std::error::Error + 'static)) -> i32 {
+    use rust_bwt::BwtError::*;
+
+    // Whatever failed, it failed because of the signal
+    if let Some(signal) = interrupt::received() {
+        return interrupt::exit_code(signal);
+    }
+    match e.downcast_ref::<rust_bwt::BwtError>() {
+        Some(Io(_) | NoIndex | RangeOutOfBounds { .. }) | None => 1,
+        Some(
+            TruncatedStream
+            | UnexpectedEof { .. }
+            | InvalidSentinelIndex
+            | CorruptStream(_)
+            | LengthMismatch { .. }
+            | InconsistentTransform { .. },
+        ) => 3,
+        Some(ChecksumMismatch { .. }) => 4,
+        Some(BadMagic | UnsupportedVersion(_) | UnsupportedFlags(_)) => 5,
+        Some(OutputTooLarge { .. }) => 6,
+    }
+}
+
+fn compress(args: &CompressArgs) -> Option<i32> {
+    let failed = |e: Box<dyn std::error::Error>| {
+        eprintln!("Error during compression: {}", e);
+        Some(exit_code(&*e))
+    };
+
+    let options = match resolve_options(args) {
+        Ok(options) => options,
+        Err(e) => return failed(e),
+    };
+    if let Some(archive) = &args.archive {
+        let skipped =
+            match archive::create(archive, &args.files, args.recursive, &options, args.force) {
+                Ok(skipped) => skipped,
+                Err(e) => return failed(e),
+            };
+        if !args.quiet {
+            for skipped in skipped {
+                eprintln!(
+                    "Warning: skipped {}, which is neither a file nor a directory",
+                    skipped.display()
+                );
+            }
+        }
+        return None;
+    }
+    if args.files.len() <= 1 {
+        let input_file = args.files.first().or(args.input_file.as_ref());
+        return compress_file(args, input_file, &options)
+            .err()
+            .and_then(failed);
+    }
+    if args.output.is_some() {
+        return failed("--output can't be used with more than one input file".into());
+    }
+
+    let mut failure = None;
+    for input_file in &args.files {
+        match compress_file(args, Some(input_file), &options) {
+            Ok(_) if args.quiet => {}
+            Ok(output_file) => report_written(input_file, "compressed", output_file.as_deref()),
+            Err(e) => {
+                eprintln!("Error compressing {}: {}", input_file.display(), e);
+                failure = failure.or(Some(exit_code(&*e)));
+            }
+        }
+        if let Some(signal) = interrupt::received() {
+            return Some(interrupt::exit_code(signal));
+        }
+    }
+    failure
+}
+
+/// Compresses `input_file` (or `--input-string`, or stdin) to its output, returning the output
+/// file, or `None` for stdout
+fn compress_file(
+    args: &CompressArgs,
+    input_file: Option<&PathBuf>,
+    options: &rust_bwt::Options,
+) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+    let output_file = compress_output(args, input_file)?;
+    check_distinct(input_file.map(PathBuf::as_path), output_file.as_deref())?;
+    if output_file.is_none() && std::io::stdout().is_terminal() && !args.force {
+        return Err(
+            "refusing to write compressed data to a terminal; use --output or --force".into(),
+        );
+    }
+    let started = Instant::now();
+    let mut input = open_input(&input_file.cloned(), &args.input_string)?;
+    let mut options = options.clone();
+    let mut sample = Vec::new();
+    if auto_tunes(args) {
+        input
+            .by_ref()
+            .take(TUNING_SAMPLE_LEN)
+            .read_to_end(&mut sample)?;
+        tune_options(args, &mut options, &sample);
+    }
+    let options = &options;
+    let mut input = CountingReader::new(interrupt::Interruptible(
+        std::io::Cursor::new(sample).chain(input),
+    ));
+    let mut progress_bar = (!args.quiet)
+        .then(|| {
+            let total = match (input_file, &args.input_string) {
+                (_, Some(input_string)) => Some(input_string.len() as u64),
+                (Some(input_file), None) => file_size(input_file),
+                (None, None) => None,
+            };
+            progress::ProgressBar::new(total, progress::delay(args.progress))
+        })
+        .flatten();
+    let progress = |done: rust_bwt::Progress| {
+        if let Some(progress_bar) = &mut progress_bar {
+            progress_bar.update(done.original_len);
+        }
+    };
+
+    // Read the input a block at a time, writing compressed data as each block becomes ready
+    let (written, times) = match (&output_file, args.split_size) {
+        (Some(output_file), Some(split_size)) => {
+            let volumes = volume::VolumeWriter::new(output_file.clone(), split_size, args.force);
+            let (written, times, volumes) = write_compressed(
+                &mut input,
+                options,
+                args.verify,
+                volumes,
+                volume::VolumeWriter::remove,
+                progress,
+            )?;
+            if !args.keep {
+                volumes.sync_all()?;
+            }
+            (written, times)
+        }
+        (Some(output_file), None) if args.checkpoint || args.resume => {
+            let (written, times, file) =
+                write_checkpointed(args, &mut input, options, output_file, progress)?;
+            if !args.keep {
+                file.sync_all()?;
+            }
+            (written, times)
+        }
+        (Some(output_file), None) => {
+            let (file, appended_to) = if args.append {
+                open_appending(output_file, args.force)?
+            } else {
+                (create_output(output_file, args.force)?, None)
+            };
+            let (written, times, file) = write_compressed(
+                &mut input,
+                options,
+                args.verify,
+                file,
+                |file| match appended_to {
+                    Some(len) => file.set_len(len),
+                    None => {
+                        drop(file);
+                        std::fs::remove_file(output_file)
+                    }
+                },
+                progress,
+            )?;
+            if !args.keep {
+                file.sync_all()?;
+            }
+            (written, times)
+        }
+        (None, Some(_)) => return Err("--split-size needs an output file".into()),
+        (None, None) => {
+            let stdout = std::io::stdout().lock();
+            let (written, times, _) = write_compressed(
+                &mut input,
+                options,
+                args.verify,
+                stdout,
+                |_| Ok(()),
+                progress,
+            )?;
+            (written, times)
+        }
     };
+    // Dropped here, so the bar's line ends before anything else is printed
+    if let Some(mut progress_bar) = progress_bar {
+        progress_bar.finish(input.count);
+    }
+
+    if !args.keep && output_file.is_some() {
+        if let Some(input_file) = input_file.filter(|file| *file != "-") {
+            std::fs::remove_file(input_file)?;
+        }
+    }
+
+    if args.stats || args.stats_json {
+        stats::Stats {
+            input_len: input.count,
+            output_len: written,
+            compressing: true,
+            elapsed: started.elapsed(),
+            stages: times,
+        }
+        .report(args.stats_json);
+    }
 
-    // BWT+RLE compress
-    let bwt_str = BWTStr::new(input_data);
-    let transformed = bwt_str.forward_transform();
+    // Each append is usually a small addition to a log of them, which is nothing to warn about
+    // every time
+    let grew = input.count >= MIN_GROWTH_WARNING_LEN && written > input.count;
+    if !args.quiet && !args.append && grew {
+        eprintln!(
+            "Warning: compressed output ({} bytes) is larger than the input ({} bytes); the input \
+             is probably random or already compressed{}",
+            written,
+            input.count,
+            if args.store {
+                ""
+            } else {
+                ". Use --store (store mode) to keep the overhead to a minimum"
+            }
+        );
+    }
+
+    Ok(output_file)
+}
+
+/// The status line for one of several input files, on stderr so it never mixes with output
+fn report_written(input_file: &Path, done: &str, output_file: Option<&Path>) {
+    match output_file {
+        Some(output_file) => eprintln!(
+            "{}: {} to {}",
+            input_file.display(),
+            done,
+            output_file.display()
+        ),
+        None => eprintln!("{}: {} to stdout", input_file.display(), done),
+    }
+}
 
-    // Write compressed data
-    if let Some(output_file) = &args.output {
-        let mut file = File::create(output_file)?;
-        transformed.rle_write(&mut file)?;
+/// Compresses `input` into `output`, returning how many bytes were written, how long each stage
+/// took, and `output` itself. If compression fails, or with `verify` the output doesn't decompress
+/// back to the input, what was written is handed to `discard`. `progress` is called after each
+/// block is written.
+fn write_compressed<W: Write>(
+    input: impl Read + Send,
+    options: &rust_bwt::Options,
+    verify: bool,
+    output: W,
+    discard: impl FnOnce(W) -> std::io::Result<()>,
+    progress: impl FnMut(rust_bwt::Progress),
+) -> Result<(u64, rust_bwt::StageTimes, W), Box<dyn std::error::Error>> {
+    let mut output = CountingWriter::new(output);
+    let compressed = if verify {
+        roundtrip::compress_verified(input, options, &mut output, progress)
     } else {
-        // Default to writing to stdout
-        let stdout = std::io::stdout();
-        let mut handle = stdout.lock();
-        transformed.rle_write(&mut handle)?;
+        rust_bwt::compress_stream_progress(input, options, &mut output, progress)
+            .map_err(Into::into)
+    };
+    match compressed.and_then(|times| Ok(output.flush().map(|()| times)?)) {
+        Ok(times) => Ok((output.count, times, output.inner)),
+        Err(e) => {
+            discard(output.inner)?;
+            Err(e)
+        }
     }
+}
+
+/// `compress --checkpoint` or `--resume`: like [`write_compressed`] into a new `output_file`, or
+/// after the blocks an interrupted run recorded, recording each block in a checkpoint. If
+/// compression fails, the output and the checkpoint are kept for `--resume`.
+fn write_checkpointed(
+    args: &CompressArgs,
+    input: &mut (impl Read + Send),
+    options: &rust_bwt::Options,
+    output_file: &Path,
+    mut progress: impl FnMut(rust_bwt::Progress),
+) -> Result<(u64, rust_bwt::StageTimes, File), Box<dyn std::error::Error>> {
+    let (mut file, mut checkpoint, written) = if args.resume {
+        let file = File::options().read(true).write(true).open(output_file)?;
+        let (checkpoint, written) =
+            checkpoint::Checkpoint::resume(output_file, &file, options.block_size)?;
+        let start = written.last().copied().unwrap_or_default();
+        file.set_len(start.compressed_len)?;
+        // What was compressed already is read again, but not compressed
+        let skipped = std::io::copy(&mut input.take(start.original_len), &mut std::io::sink())?;
+        if skipped != start.original_len {
+            return Err(format!(
+                "the input is shorter than the {} bytes already compressed",
+                start.original_len
+            )
+            .into());
+        }
+        debug(format_args!(
+            "resuming after {} blocks, {} bytes in",
+            start.blocks, start.original_len
+        ));
+        (file, checkpoint, written)
+    } else {
+        let file = create_output(output_file, args.force)?;
+        let checkpoint = checkpoint::Checkpoint::create(output_file, &file, options.block_size)?;
+        (file, checkpoint, Vec::new())
+    };
+
+    let compressed = rust_bwt::compress_stream_resume(
+        input,
+        options,
+        &mut file,
+        &written,
+        |done: rust_bwt::Progress| {
+            checkpoint.record(done);
+            progress(done);
+        },
+    );
+    let times = match compressed {
+        Ok(times) => times,
+        Err(e) => {
+            if !args.quiet {
+                eprintln!(
+                    "Kept {} and {} to carry on from with --resume",
+                    output_file.display(),
+                    checkpoint::path(output_file).display()
+                );
+            }
+            return Err(e.into());
+        }
+    };
+    checkpoint.finish()?;
+    Ok((file.stream_position()?, times, file))
+}
+
+/// Creates `path` for writing. Unless `force`, it mustn't exist already.
+pub(crate) fn create_output(path: &Path, force: bool) -> std::io::Result<File> {
+    File::options()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .create_new(!force)
+        .open(path)
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::AlreadyExists => std::io::Error::new(
+                e.kind(),
+                format!(
+                    "{} already exists; use --force to overwrite it",
+                    path.display()
+                ),
+            ),
+            _ => e,
+        })
+}
+
+/// Opens `path` for `compress --append`, returning it and its length before anything is appended,
+/// or `None` if it didn't exist and was created. Unless `force`, a file that's already there must
+/// look like it ends in a stream another can follow: it starts with a versioned header and ends in
+/// the end-of-stream marker, or the block index after it, which only streams with checksums have.
+fn open_appending(
+    path: &Path,
+    force: bool,
+) -> Result<(File, Option<u64>), Box<dyn std::error::Error>> {
+    // The magic, version, flags and original length
+    const HEADER_MAX_LEN: u64 = 14;
+    const END_MARKER_LEN: u64 = 8;
+    // Ends the block index that follows the end marker in newer streams
+    const INDEX_MAGIC: &[u8] = b"RBWI";
+
+    let mut file = match File::options().read(true).append(true).open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok((create_output(path, false)?, None));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let len = file.metadata()?.len();
+    if force || len == 0 {
+        return Ok((file, Some(len)));
+    }
+
+    let mut header = Vec::new();
+    (&mut file).take(HEADER_MAX_LEN).read_to_end(&mut header)?;
+    let checksum = match rust_bwt::stream_header(&header) {
+        Ok(header) if header.version.is_some() => header.checksum,
+        _ => {
+            return Err(format!(
+                "{} isn't a rust-bwt stream; use --force to append to it anyway",
+                path.display()
+            )
+            .into())
+        }
+    };
+    let mut end = [0xff; END_MARKER_LEN as usize];
+    if len >= END_MARKER_LEN {
+        file.seek(SeekFrom::End(-(END_MARKER_LEN as i64)))?;
+        file.read_exact(&mut end)?;
+    }
+    if !checksum || !(end == [0; END_MARKER_LEN as usize] || end.ends_with(INDEX_MAGIC)) {
+        return Err(format!(
+            "{} doesn't end in a stream with checksums, so a stream appended to it couldn't be \
+             found; use --force to append to it anyway",
+            path.display()
+        )
+        .into());
+    }
+    Ok((file, Some(len)))
+}
 
+/// Where `compress` writes: `--output`, or else the input file with the suffix appended. `None`
+/// means stdout, for `--stdout` and by default for a string or stdin.
+fn compress_output(
+    args: &CompressArgs,
+    input_file: Option<&PathBuf>,
+) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+    if args.stdout {
+        return Ok(None);
+    }
+    match (&args.output, input_file) {
+        (Some(output_file), _) => Ok((output_file.as_os_str() != "-").then(|| output_file.clone())),
+        (None, Some(input_file)) if input_file.as_os_str() != "-" => {
+            if strip_suffix(input_file, &args.suffix).is_some() {
+                return Err(format!(
+                    "{} already ends in {}; pass --output to compress it anyway",
+                    input_file.display(),
+                    args.suffix
+                )
+                .into());
+            }
+            let mut output_file = input_file.as_os_str().to_owned();
+            output_file.push(&args.suffix);
+            Ok(Some(output_file.into()))
+        }
+        (None, _) => Ok(None),
+    }
+}
+
+/// Where `decompress` writes: `--output`, or else the input file without its suffix (for a split
+/// stream, the `.001` volume without its suffix and `.001`). `None` means stdout, for `--stdout` and
+/// by default for stdin.
+fn decompress_output(
+    args: &DecompressArgs,
+    input_file: Option<&PathBuf>,
+) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+    if args.stdout {
+        return Ok(None);
+    }
+    match (&args.output, input_file) {
+        (Some(output_file), _) => Ok((output_file.as_os_str() != "-").then(|| output_file.clone())),
+        (None, Some(input_file)) if input_file.as_os_str() != "-" => {
+            let compressed = volume::volume_set_base(input_file).unwrap_or(input_file.clone());
+            strip_suffix(&compressed, &args.suffix)
+                .map(Some)
+                .ok_or_else(|| {
+                    format!(
+                        "{} doesn't end in {}, so there's no output name to derive; pass --output",
+                        input_file.display(),
+                        args.suffix
+                    )
+                    .into()
+                })
+        }
+        (None, _) => Ok(None),
+    }
+}
+
+/// `path` with `suffix` removed from the end of its file name, or `None` if the name doesn't end
+/// in it (or is nothing but the suffix)
+fn strip_suffix(path: &Path, suffix: &str) -> Option<PathBuf> {
+    // A name that isn't UTF-8 can still be split at an extension
+    match suffix
+        .strip_prefix('.')
+        .filter(|extension| !extension.contains('.'))
+    {
+        Some(extension) => (path.extension()? == extension).then(|| path.with_extension("")),
+        None => {
+            let stem = path.file_name()?.to_str()?.strip_suffix(suffix)?;
+            (!stem.is_empty()).then(|| path.with_file_name(stem))
+        }
+    }
+}
+
+/// A non-empty suffix that doesn't contain a path separator
+fn parse_suffix(s: &str) -> Result<String, String> {
+    if s.is_empty() {
+        return Err("the suffix can't be empty".into());
+    }
+    if s.contains(std::path::is_separator) {
+        return Err("the suffix can't contain a path separator".into());
+    }
+    Ok(s.to_owned())
+}
+
+/// Parses a `START..END` byte range, which may be empty but not reversed
+fn parse_range(s: &str) -> Result<Range<u64>, String> {
+    let (start, end) = s
+        .split_once("..")
+        .ok_or("expected START..END, e.g. 1000..2000")?;
+    let offset = |s: &str| {
+        s.parse::<u64>()
+            .map_err(|e| format!("invalid offset {:?}: {}", s, e))
+    };
+    let range = offset(start)?..offset(end)?;
+    if range.start > range.end {
+        return Err("the range ends before it starts".into());
+    }
+    Ok(range)
+}
+
+/// Size of `path` if it's a regular file, and not `-` for stdin
+fn file_size(path: &Path) -> Option<u64> {
+    if path == "-" {
+        return None;
+    }
+    std::fs::metadata(path)
+        .ok()
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+}
+
+/// Refuses to read and write the same file: streaming would truncate the input before reading it
+fn check_distinct(
+    input_file: Option<&Path>,
+    output_file: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (Some(input_file), Some(output_file)) = (input_file, output_file) else {
+        return Ok(());
+    };
+    if let (Ok(input_file), Ok(output_file)) =
+        (input_file.canonicalize(), output_file.canonicalize())
+    {
+        if input_file == output_file {
+            return Err(
+                format!("{} is both the input and the output", input_file.display()).into(),
+            );
+        }
+    }
     Ok(())
 }
 
-fn decompress(args: &DecompressArgs) -> Result<(), Box<dyn std::error::Error>> {
-    // Read compressed data
-    let mut file = File::open(&args.input_file)?;
+/// The logger installed by `-v`, which also receives the CLI's own diagnostics
+static LOGGER: OnceLock<StderrLogger> = OnceLock::new();
+
+/// Logs one of the CLI's own diagnostics at debug level, like the library's
+fn debug(message: std::fmt::Arguments<'_>) {
+    use rust_bwt::Log;
+
+    if let Some(logger) = LOGGER
+        .get()
+        .filter(|logger| logger.enabled(rust_bwt::Level::Debug))
+    {
+        logger.log(rust_bwt::Level::Debug, message);
+    }
+}
+
+/// Prints the library's diagnostics up to `max_level` to stderr
+struct StderrLogger {
+    max_level: rust_bwt::Level,
+}
+
+impl rust_bwt::Log for StderrLogger {
+    fn enabled(&self, level: rust_bwt::Level) -> bool {
+        level <= self.max_level
+    }
 
-    // Decompress
-    let transformed = BWTStr::rle_read(&mut file)?;
-    let original = transformed.reverse_transform();
-    let output_data: Vec<u8> = original
-        .inner
-        .into_iter()
-        .filter_map(|bwt_byte| match bwt_byte {
-            bwtstring::BWTByte::Byte(b) => Some(b),
-            bwtstring::BWTByte::Sentinel => None,
+    fn log(&self, level: rust_bwt::Level, message: std::fmt::Arguments<'_>) {
+        eprintln!("[{}] {}", level, message);
+    }
+}
+
+/// Passes reads through to `inner`, counting the bytes read
+struct CountingReader<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R> CountingReader<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count += read as u64;
+        Ok(read)
+    }
+}
+
+/// Passes writes through to `inner`, counting the bytes written
+struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W> CountingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Builds the compression settings: defaults, then the config file if any, then command line flags
+fn resolve_options(args: &CompressArgs) -> Result<rust_bwt::Options, Box<dyn std::error::Error>> {
+    let mut options = match &args.config {
+        Some(path) => config::load(path)?,
+        None => rust_bwt::Options::default(),
+    };
+
+    if let Some(block_size) = args.block_size {
+        options.block_size = block_size.get();
+    }
+    match args.threads {
+        Some(0) => options.threads = rust_bwt::Options::default().threads,
+        Some(threads) => options.threads = threads,
+        None => {}
+    }
+    if let Some(mtf) = args.mtf {
+        options.mtf = mtf;
+    }
+    if let Some(rle0) = args.rle0 {
+        options.rle0 = rle0;
+    }
+    if let Some(hybrid_rle) = args.hybrid_rle {
+        options.hybrid_rle = hybrid_rle;
+    }
+    if let Some(checksum) = args.checksum {
+        options.checksum = checksum;
+    }
+    if let Some(entropy) = args.entropy {
+        options.entropy = entropy;
+    }
+    if args.huffman {
+        options.entropy = Entropy::Huffman;
+    }
+    if args.no_entropy {
+        options.entropy = Entropy::None;
+    }
+    if args.store {
+        options.mtf = false;
+        options.rle0 = false;
+        options.hybrid_rle = true;
+        options.entropy = Entropy::None;
+    }
+    if args.bijective {
+        options.bijective = true;
+    }
+    if let Some(sa_algo) = args.sa_algo {
+        options.suffix_sort = sa_algo;
+    }
+
+    Ok(options)
+}
+
+/// Whether `compress` should look at the input with [`tune_options`]
+fn auto_tunes(args: &CompressArgs) -> bool {
+    args.auto_tune.unwrap_or(true) && args.config.is_none()
+}
+
+/// Tunes the `options` from [`resolve_options`] for input starting with `sample`: if it's at least
+/// [`DNA_MIN_LEN`] long with at most [`DNA_MAX_ALPHABET`] distinct bytes, like DNA, blocks are made
+/// [`DNA_BLOCK_SIZE`] long and MTF and RLE0 are skipped. Settings given as flags are left alone.
+/// Any settings decompress the same way, so this only changes how well the input compresses.
+fn tune_options(args: &CompressArgs, options: &mut rust_bwt::Options, sample: &[u8]) {
+    let mut seen = [false; 256];
+    for b in sample {
+        seen[*b as usize] = true;
+    }
+    let alphabet = seen.iter().filter(|seen| **seen).count();
+    if sample.len() < DNA_MIN_LEN || alphabet > DNA_MAX_ALPHABET {
+        return;
+    }
+
+    if args.block_size.is_none() {
+        options.block_size = options.block_size.max(DNA_BLOCK_SIZE);
+    }
+    // --store sets both MTF and RLE0 already
+    if args.mtf.is_none() && !args.store {
+        options.mtf = false;
+        if args.rle0.is_none() {
+            options.rle0 = false;
+        }
+    }
+    debug(format_args!(
+        "input has an alphabet of {} bytes, like DNA; tuned to {} byte blocks, mtf {}, rle0 {}",
+        alphabet, options.block_size, options.mtf, options.rle0
+    ));
+}
+
+/// Reads the input named by `--input-file` or `--input-string`, falling back to stdin when neither
+/// is given and it isn't a terminal
+fn read_input(
+    input_file: &Option<PathBuf>,
+    input_string: &Option<String>,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut input_data = Vec::new();
+    open_input(input_file, input_string)?.read_to_end(&mut input_data)?;
+    Ok(input_data)
+}
+
+/// Opens the input given by `input_file` or `input_string`, or stdin if neither is given and it
+/// isn't a terminal
+fn open_input(
+    input_file: &Option<PathBuf>,
+    input_string: &Option<String>,
+) -> Result<Box<dyn Read + Send>, Box<dyn std::error::Error>> {
+    let input: Box<dyn Read + Send> = if let Some(input_file) = input_file {
+        if input_file.as_os_str() == "-" {
+            Box::new(std::io::stdin())
+        } else {
+            Box::new(File::open(input_file)?)
+        }
+    } else if let Some(input_string) = input_string {
+        Box::new(std::io::Cursor::new(input_string.clone().into_bytes()))
+    } else if !std::io::stdin().is_terminal() {
+        Box::new(std::io::stdin())
+    } else {
+        return Err(
+            "no input given; pass --input-file, --input-string, or pipe data on stdin".into(),
+        );
+    };
+
+    Ok(input)
+}
+
+fn decompress(args: &DecompressArgs) -> Option<i32> {
+    let failed = |e: Box<dyn std::error::Error>| {
+        eprintln!("Error during decompression: {}", e);
+        Some(exit_code(&*e))
+    };
+
+    let mut options = rust_bwt::DecompressOptions {
+        checked_reverse: args.checked_reverse,
+        verify_checksum: !args.no_verify,
+        max_output_size: args.max_output_size.map(NonZeroU64::get),
+        concatenated: !args.single_stream,
+        ..Default::default()
+    };
+    if let Some(threads) = args.threads.filter(|threads| *threads > 0) {
+        options.threads = threads;
+    }
+
+    if args.files.len() <= 1 {
+        let input_file = args.files.first().or(args.input_file.as_ref());
+        return decompress_file(args, input_file, &options)
+            .err()
+            .and_then(failed);
+    }
+    if args.output.is_some() {
+        return failed("--output can't be used with more than one input file".into());
+    }
+
+    let mut failure = None;
+    for input_file in &args.files {
+        match decompress_file(args, Some(input_file), &options) {
+            Ok(_) if args.quiet || args.list => {}
+            Ok(_) if args.verify_only => eprintln!("{}: OK", input_file.display()),
+            Ok(output_file) => report_written(input_file, "decompressed", output_file.as_deref()),
+            Err(e) => {
+                eprintln!("Error decompressing {}: {}", input_file.display(), e);
+                failure = failure.or(Some(exit_code(&*e)));
+            }
+        }
+        if let Some(signal) = interrupt::received() {
+            return Some(interrupt::exit_code(signal));
+        }
+    }
+    failure
+}
+
+/// Decompresses `input_file` (or stdin) to its output, returning the output file, or `None` for
+/// stdout or `--verify-only`
+fn decompress_file(
+    args: &DecompressArgs,
+    input_file: Option<&PathBuf>,
+    options: &rust_bwt::DecompressOptions,
+) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
+    if args.archive {
+        // Members are found by seeking, which stdin can't do
+        let archive = input_file
+            .filter(|file| *file != "-")
+            .ok_or("--archive needs an input file")?;
+        if args.list {
+            print_members(&archive::list(std::io::BufReader::new(File::open(
+                archive,
+            )?))?);
+            return Ok(None);
+        }
+        let destination = args.output.clone().unwrap_or_else(|| PathBuf::from("."));
+        archive::extract(
+            archive,
+            &destination,
+            args.member.as_deref(),
+            options,
+            args.to_dir_flat,
+            args.force,
+        )?;
+        return Ok(Some(destination));
+    }
+
+    let range = decompress_range_arg(args)?;
+    let output_file = if args.verify_only {
+        None
+    } else {
+        decompress_output(args, input_file)?
+    };
+    check_distinct(input_file.map(PathBuf::as_path), output_file.as_deref())?;
+    let started = Instant::now();
+    let mut compressed = CountingReader::new(interrupt::Interruptible(open_compressed(
+        &input_file.cloned(),
+    )?));
+    let mut progress_bar = (!args.quiet && range.is_none())
+        .then(|| {
+            // Streams written a block at a time don't store their original size, so progress is
+            // measured through the compressed input
+            let total = input_file
+                .map(PathBuf::as_path)
+                .filter(|file| volume::volume_set_base(file).is_none())
+                .and_then(file_size);
+            progress::ProgressBar::new(total, progress::delay(args.progress))
         })
-        .collect();
+        .flatten();
+    let mut last_progress = rust_bwt::Progress::default();
+    let mut progress = |done: rust_bwt::Progress| {
+        last_progress = done;
+        if let Some(progress_bar) = &mut progress_bar {
+            progress_bar.update(done.compressed_len);
+        }
+    };
 
-    // Write decompressed data
-    if let Some(output_file) = &args.output {
-        let mut output = File::create(output_file)?;
-        output.write_all(&output_data)?;
+    // Read the stream a block at a time, writing each block as it becomes ready
+    let mut decode = |output: &mut dyn Write| match &range {
+        Some(range) => decompress_range(
+            input_file.filter(|file| volume::volume_set_base(file).is_none()),
+            &mut compressed,
+            range.clone(),
+            options,
+            output,
+            args.quiet,
+        ),
+        None => decompress_counted(&mut compressed, options, output, &mut progress),
+    };
+    let (written, times) = if args.verify_only {
+        decode(&mut std::io::sink())?
+    } else if let Some(output_file) = &output_file {
+        let mut output = create_output(output_file, args.force)?;
+        let written = decode(&mut output).map_err(Into::into).and_then(|written| {
+            if !args.keep {
+                output.sync_all()?;
+            }
+            Ok::<_, Box<dyn std::error::Error>>(written)
+        });
+        match written {
+            Ok(written) => written,
+            Err(e) => {
+                // Whatever was written is truncated or garbage
+                drop(output);
+                std::fs::remove_file(output_file)?;
+                return Err(e);
+            }
+        }
     } else {
         // Default stdout
         let stdout = std::io::stdout();
-        let mut handle = stdout.lock();
-        handle.write_all(&output_data)?;
+        if stdout.is_terminal() && !args.force && !args.stdout {
+            // The whole output is needed to decide whether it's safe to show
+            let mut output_data = Vec::new();
+            let written = decode(&mut output_data)?;
+            if refuse_binary_to_terminal(&output_data, true, args.force, args.stdout) {
+                return Err(
+                    "refusing to write binary data to a terminal; use --output, --force or -c"
+                        .into(),
+                );
+            }
+            stdout.lock().write_all(&output_data)?;
+            written
+        } else {
+            decode(&mut stdout.lock())?
+        }
+    };
+    // Dropped here, so the bar's line ends before anything else is printed
+    if let Some(mut progress_bar) = progress_bar {
+        progress_bar.finish(compressed.count);
+    }
+
+    if args.stats || args.stats_json {
+        stats::Stats {
+            input_len: compressed.count,
+            output_len: written,
+            compressing: false,
+            elapsed: started.elapsed(),
+            stages: times,
+        }
+        .report(args.stats_json);
+    }
+
+    // The output can only stand in for the input once every block has been checked against its
+    // CRC-32, unless --rm says to delete it anyway
+    let checked = last_progress.checked_blocks == last_progress.blocks;
+    if !args.keep && range.is_none() && output_file.is_some() {
+        drop(compressed);
+        if let Some(input_file) = input_file.filter(|file| *file != "-") {
+            if !(checked || args.rm) {
+                if !args.quiet {
+                    eprintln!(
+                        "Warning: keeping {}, since the output couldn't be checked against its \
+                         checksums; use --rm to delete it anyway",
+                        input_file.display()
+                    );
+                }
+                return Ok(output_file);
+            }
+            match volume::volume_set_base(input_file) {
+                Some(base) => volume::remove_volume_set(&base)?,
+                None => std::fs::remove_file(input_file)?,
+            }
+        }
+    }
+
+    Ok(output_file)
+}
+
+/// Decompresses `input` into `output`, returning how many bytes were written and how long each
+/// stage took. `progress` is called after each block is written.
+fn decompress_counted(
+    input: impl Read + Send,
+    options: &rust_bwt::DecompressOptions,
+    output: impl Write,
+    progress: impl FnMut(rust_bwt::Progress),
+) -> Result<(u64, rust_bwt::StageTimes), rust_bwt::BwtError> {
+    let mut output = CountingWriter::new(output);
+    let times = rust_bwt::decompress_stream_progress(input, options, &mut output, progress)?;
+    Ok((output.count, times))
+}
+
+/// The part of the original data to decompress: --range, or --offset and --length, or `None` for
+/// all of it
+fn decompress_range_arg(args: &DecompressArgs) -> Result<Option<Range<u64>>, String> {
+    let Some(length) = args.length else {
+        return Ok(args.range.clone());
+    };
+    let offset = args.offset.unwrap_or(0);
+    let end = offset
+        .checked_add(length)
+        .ok_or("--offset plus --length is too large")?;
+    Ok(Some(offset..end))
+}
+
+/// Decompresses the bytes in `range` of the stream in `input` into `output`, returning how many
+/// bytes were written and how long each stage took. If `input_file` names a file, it's seeked
+/// through by its block index; if it has none, or the stream comes from stdin, `input` is
+/// decompressed from the start and everything outside the range is thrown away.
+fn decompress_range(
+    input_file: Option<&PathBuf>,
+    input: impl Read + Send,
+    range: Range<u64>,
+    options: &rust_bwt::DecompressOptions,
+    output: impl Write,
+    quiet: bool,
+) -> Result<(u64, rust_bwt::StageTimes), rust_bwt::BwtError> {
+    let mut output = CountingWriter::new(output);
+    let input_file = input_file.filter(|file| *file != "-");
+    if let Some(input_file) = input_file {
+        let file = interrupt::Interruptible(File::open(input_file)?);
+        match rust_bwt::decompress_range(file, range.clone(), options, &mut output) {
+            Err(rust_bwt::BwtError::NoIndex) => {}
+            result => return result.map(|()| (output.count, rust_bwt::StageTimes::default())),
+        }
+    }
+
+    if !quiet {
+        match input_file {
+            Some(input_file) => eprintln!(
+                "Warning: {} has no block index; decompressing it from the start",
+                input_file.display()
+            ),
+            None => eprintln!("Warning: can't seek in the input; decompressing it from the start"),
+        }
+    }
+    let mut output = RangeWriter {
+        inner: output,
+        range: range.clone(),
+        position: 0,
+    };
+    let times = rust_bwt::decompress_stream_timed(input, options, &mut output)?;
+    if output.position < range.end {
+        return Err(rust_bwt::BwtError::RangeOutOfBounds {
+            end: range.end,
+            len: output.position,
+        });
+    }
+    Ok((output.inner.count, times))
+}
+
+/// Passes on only the bytes written to it that fall in `range`, counting from the first write
+struct RangeWriter<W> {
+    inner: W,
+    range: Range<u64>,
+    /// How many bytes have been written to it so far
+    position: u64,
+}
+
+impl<W: Write> Write for RangeWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let start = self.position;
+        let end = start + buf.len() as u64;
+        let from = self.range.start.clamp(start, end) - start;
+        let to = self.range.end.clamp(start, end) - start;
+        self.inner.write_all(&buf[from as usize..to as usize])?;
+        self.position = end;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn bench(args: &BenchArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let input = match &args.input_file {
+        Some(input_file) => read_input(&Some(input_file.clone()), &None)?,
+        None => bench::synthetic_text(usize::try_from(args.size.get())?),
+    };
+
+    let mut options = rust_bwt::Options::default();
+    if let Some(block_size) = args.block_size {
+        options.block_size = block_size.get();
+    }
+    if let Some(threads) = args.threads.filter(|threads| *threads > 0) {
+        options.threads = threads;
+    }
+    if let Some(sa_algo) = args.sa_algo {
+        options.suffix_sort = sa_algo;
+    }
+    let decompress_options = rust_bwt::DecompressOptions {
+        threads: options.threads,
+        ..Default::default()
+    };
+
+    let iterations = args.iterations.get();
+    let (compress_time, compressed) = bench::fastest(iterations, || {
+        Ok::<_, rust_bwt::BwtError>(rust_bwt::compress_bytes_with(&input, &options))
+    })?;
+    let (decompress_time, decompressed) = bench::fastest(iterations, || {
+        let mut output = Vec::with_capacity(input.len());
+        rust_bwt::decompress_to(&compressed, &decompress_options, &mut output).map(|()| output)
+    })?;
+    if decompressed != input {
+        return Err("the input didn't survive a round trip".into());
+    }
+
+    match &args.input_file {
+        Some(input_file) => println!(
+            "Input:       {}, {} bytes",
+            input_file.display(),
+            input.len()
+        ),
+        None => println!("Input:       {} bytes of generated text", input.len()),
+    }
+    if !input.is_empty() {
+        println!(
+            "Compressed:  {} bytes, ratio {:.3}",
+            compressed.len(),
+            compressed.len() as f64 / input.len() as f64
+        );
+    }
+    println!(
+        "Compress:    {:.2} MB/s ({:.3} s, fastest of {})",
+        bench::megabytes_per_second(input.len(), compress_time),
+        compress_time.as_secs_f64(),
+        iterations
+    );
+    println!(
+        "Decompress:  {:.2} MB/s ({:.3} s, fastest of {})",
+        bench::megabytes_per_second(input.len(), decompress_time),
+        decompress_time.as_secs_f64(),
+        iterations
+    );
+
+    Ok(())
+}
+
+/// Reads a compressed stream from `input_file`, or from stdin if it's `-` or not given. If
+/// `input_file` is the `.001` volume of a split stream, the whole volume set is read.
+fn read_compressed(input_file: &Option<PathBuf>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut compressed = Vec::new();
+    open_compressed(input_file)?.read_to_end(&mut compressed)?;
+    Ok(compressed)
+}
+
+/// Opens a compressed stream like [`read_compressed`], without reading it yet. A split stream's
+/// volumes are still read up front.
+fn open_compressed(
+    input_file: &Option<PathBuf>,
+) -> Result<Box<dyn Read + Send>, Box<dyn std::error::Error>> {
+    let compressed: Box<dyn Read + Send> = match input_file {
+        Some(input_file) if input_file.as_os_str() != "-" => {
+            match volume::volume_set_base(input_file) {
+                Some(base) => Box::new(std::io::Cursor::new(volume::read_volumes(&base)?)),
+                None => Box::new(File::open(input_file)?),
+            }
+        }
+        None if std::io::stdin().is_terminal() => {
+            return Err("no input given; pass --input-file or pipe data on stdin".into());
+        }
+        _ => Box::new(std::io::stdin()),
+    };
+
+    Ok(compressed)
+}
+
+/// Verifies every file, reporting each one's status. Returns the exit status of the first failure,
+/// if any.
+fn verify(args: &VerifyArgs) -> Option<i32> {
+    let mut options = rust_bwt::DecompressOptions {
+        checked_reverse: true,
+        ..Default::default()
+    };
+    if let Some(threads) = args.threads.filter(|threads| *threads > 0) {
+        options.threads = threads;
+    }
+
+    let mut failure = None;
+    for input_file in &args.input_files {
+        match verify_file(input_file, &options) {
+            Ok((len, crc32)) => println!(
+                "{}: OK, {} bytes, CRC-32 {:08x}",
+                input_file.display(),
+                len,
+                crc32
+            ),
+            Err(e) => {
+                eprintln!("{}: FAILED, {}", input_file.display(), e);
+                failure = failure.or(Some(exit_code(&*e)));
+            }
+        }
+    }
+    failure
+}
+
+/// Decompresses `input_file` into a checksum, discarding the output. Returns its length and CRC-32.
+fn verify_file(
+    input_file: &Path,
+    options: &rust_bwt::DecompressOptions,
+) -> Result<(u64, u32), Box<dyn std::error::Error>> {
+    let compressed = open_compressed(&Some(input_file.to_path_buf()))?;
+
+    let mut crc = CountingWriter::new(rust_bwt::Crc32::new());
+    rust_bwt::decompress_stream_with(compressed, options, &mut crc)?;
+
+    Ok((crc.count, crc.inner.finish()))
+}
+
+fn info(args: &InfoArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let compressed = read_compressed(&Some(args.input_file.clone()))?;
+    if compressed.starts_with(archive::MAGIC) {
+        return archive_info(args, &compressed);
+    }
+    let (header, info) = if args.header_only {
+        (rust_bwt::stream_header(&compressed)?, None)
+    } else {
+        let info = rust_bwt::stream_info(&compressed)?;
+        (info.header.clone(), Some(info))
+    };
+
+    let mut stages = vec![if header.bijective {
+        "bijective-bwt"
+    } else {
+        "bwt"
+    }];
+    if header.mtf {
+        stages.push("mtf");
+    }
+    stages.push(if header.rle0 {
+        "rle0"
+    } else if header.hybrid_rle {
+        "hybrid-rle"
+    } else {
+        "rle"
+    });
+    if header.entropy != Entropy::None {
+        stages.push(header.entropy.name());
+    }
+    if header.checksum {
+        stages.push("crc32");
+    }
+
+    // Decoded blocks give the real size; the header's is only a claim. A truncated stream's blocks
+    // are only part of it, though.
+    let original_len = match &info {
+        Some(info) if !info.truncated => Some(info.original_len() as u64),
+        _ => header.original_len,
+    };
+
+    let mut run_lengths = BTreeMap::new();
+    for block in info.iter().flat_map(|info| &info.blocks) {
+        for (run_len, count) in &block.run_lengths {
+            *run_lengths.entry(*run_len).or_insert(0) += count;
+        }
+    }
+    let runs = run_lengths.values().sum::<u64>();
+
+    if args.json {
+        let mut fields = vec![
+            format!(
+                "\"file\":{}",
+                json::string(&args.input_file.display().to_string())
+            ),
+            format!(
+                "\"format_version\":{}",
+                header
+                    .version
+                    .map_or("null".to_string(), |version| version.to_string())
+            ),
+            format!(
+                "\"stages\":[{}]",
+                stages
+                    .iter()
+                    .map(|stage| json::string(stage))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+            format!("\"block_index\":{}", header.index),
+            format!("\"compressed_size\":{}", compressed.len()),
+            format!(
+                "\"original_size\":{}",
+                original_len.map_or("null".to_string(), |len| len.to_string())
+            ),
+        ];
+        if let Some(info) = &info {
+            let blocks = info
+                .blocks
+                .iter()
+                .map(|block| {
+                    format!(
+                        "{{\"original_size\":{},\"compressed_size\":{},\"sentinel_index\":{},\"runs\":{},\"run_lengths\":{},\"crc32\":{}}}",
+                        block.original_len,
+                        block.compressed_len,
+                        block
+                            .sentinel_index
+                            .map_or("null".to_string(), |index| index.to_string()),
+                        block.runs(),
+                        run_lengths_json(&block.run_lengths),
+                        block
+                            .crc32
+                            .map_or("null".to_string(), |crc32| format!("\"{:08x}\"", crc32))
+                    )
+                })
+                .collect::<Vec<_>>();
+            fields.push(format!("\"blocks\":[{}]", blocks.join(",")));
+            fields.push(format!("\"runs\":{}", runs));
+            fields.push(format!(
+                "\"run_lengths\":{}",
+                run_lengths_json(&run_lengths)
+            ));
+            fields.push(format!("\"truncated\":{}", info.truncated));
+        }
+        println!("{{{}}}", fields.join(","));
+    } else {
+        println!("File:            {}", args.input_file.display());
+        match header.version {
+            Some(version) => println!("Format version:  {}", version),
+            None => println!("Format version:  unversioned"),
+        }
+        println!("Stages:          {}", stages.join(", "));
+        println!(
+            "Block index:     {}",
+            if header.index { "yes" } else { "no" }
+        );
+        println!("Compressed size: {} bytes", compressed.len());
+        match original_len {
+            Some(original_len) => println!("Original size:   {} bytes", original_len),
+            None => println!("Original size:   unknown"),
+        }
+        if let Some(original_len @ 1..) = original_len {
+            println!(
+                "Ratio:           {:.3} ({:.2} bits per byte)",
+                compressed.len() as f64 / original_len as f64,
+                compressed.len() as f64 * 8.0 / original_len as f64
+            );
+        }
+
+        if let Some(info) = &info {
+            println!("Blocks:          {}", info.blocks.len());
+            for (i, block) in info.blocks.iter().enumerate() {
+                print!(
+                    "  block {}: {} -> {} bytes, {} runs",
+                    i,
+                    block.original_len,
+                    block.compressed_len,
+                    block.runs()
+                );
+                if let Some(sentinel_index) = block.sentinel_index {
+                    print!(", sentinel index {}", sentinel_index);
+                }
+                match block.crc32 {
+                    Some(crc32) => println!(", CRC-32 {:08x}", crc32),
+                    None => println!(),
+                }
+            }
+            println!("Runs:            {}", runs);
+            for (run_len, count) in &run_lengths {
+                println!("  length {}: {} runs", run_len, count);
+            }
+            if info.truncated {
+                println!("Truncated:       yes, only the blocks above could be read");
+            }
+        }
+    }
+
+    if info.is_some_and(|info| info.truncated) {
+        return Err(rust_bwt::BwtError::TruncatedStream.into());
+    }
+    Ok(())
+}
+
+/// `info` for an archive: its members, rather than a stream's blocks
+fn archive_info(args: &InfoArgs, archive: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    let members = archive::list(std::io::Cursor::new(archive))?;
+
+    if args.json {
+        let members = members
+            .iter()
+            .map(|member| {
+                format!(
+                    "{{\"path\":{},\"directory\":{},\"original_size\":{},\"compressed_size\":{}}}",
+                    json::string(&member.path),
+                    member.is_directory,
+                    member.original_len,
+                    member.compressed_len
+                )
+            })
+            .collect::<Vec<_>>();
+        println!(
+            "{{\"file\":{},\"archive\":true,\"compressed_size\":{},\"members\":[{}]}}",
+            json::string(&args.input_file.display().to_string()),
+            archive.len(),
+            members.join(",")
+        );
+    } else {
+        println!("File:            {}", args.input_file.display());
+        println!("Archive:         {} members", members.len());
+        println!("Compressed size: {} bytes", archive.len());
+        println!(
+            "Original size:   {} bytes",
+            members
+                .iter()
+                .map(|member| member.original_len)
+                .sum::<u64>()
+        );
+        print_members(&members);
+    }
+    Ok(())
+}
+
+/// One line per archive member: its original and compressed sizes, then its path, with a `/` after
+/// directories
+fn print_members(members: &[archive::Member]) {
+    for member in members {
+        if member.is_directory {
+            println!("{:>12} {:>12}  {}/", "-", "-", member.path);
+        } else {
+            println!(
+                "{:>12} {:>12}  {}",
+                member.original_len, member.compressed_len, member.path
+            );
+        }
+    }
+}
+
+/// A run-length histogram as a JSON object from run length to count
+fn run_lengths_json(run_lengths: &BTreeMap<usize, u64>) -> String {
+    let entries = run_lengths
+        .iter()
+        .map(|(run_len, count)| format!("\"{}\":{}", run_len, count))
+        .collect::<Vec<_>>();
+    format!("{{{}}}", entries.join(","))
+}
+
+fn count(args: &CountArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let compressed = read_compressed(&Some(args.input_file.clone()))?;
+    println!(
+        "{}",
+        rust_bwt::count_matches(&compressed, args.pattern.as_bytes())?
+    );
+    Ok(())
+}
+
+fn locate(args: &LocateArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let compressed = read_compressed(&Some(args.input_file.clone()))?;
+    let positions =
+        rust_bwt::locate_matches(&compressed, args.pattern.as_bytes(), args.sample_rate.get())?;
+
+    let mut stdout = std::io::stdout().lock();
+    for position in positions {
+        writeln!(stdout, "{}", position)?;
+    }
+    Ok(())
+}
+
+fn transform(args: &TransformArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let input_data = read_input(&args.input_file, &args.input_string)?;
+
+    let output_data = if args.reverse && args.bzip2 {
+        reverse_rotation_transform(&input_data)?
+    } else if args.reverse {
+        reverse_raw_transform(&input_data, args.json)?
+    } else if args.bzip2 {
+        let (bytes, orig_ptr) = rust_bwt::rotation_forward(&input_data);
+        let mut output = ROTATION_MAGIC.to_vec();
+        output.extend((orig_ptr as u64).to_le_bytes());
+        output.extend(bytes);
+        output
+    } else {
+        let (bytes, sentinel_index) = rust_bwt::BWTStr::new(input_data)
+            .forward_transform()
+            .into_transformed_parts();
+        if args.json {
+            let mut json = json::to_json(&bytes, sentinel_index);
+            json.push('\n');
+            json.into_bytes()
+        } else if args.show_sentinel {
+            let mut output = bytes;
+            output.insert(sentinel_index, b'$');
+            output.push(b'\n');
+            output
+        } else {
+            let mut output = (sentinel_index as u64).to_le_bytes().to_vec();
+            output.extend(bytes);
+            output
+        }
+    };
+
+    write_transform_output(&args.output, args.force, &output_data)
+}
+
+fn inverse_transform(args: &InverseTransformArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let input_data = read_input(&args.input_file, &None)?;
+    let output_data = if args.bzip2 {
+        reverse_rotation_transform(&input_data)?
+    } else {
+        reverse_raw_transform(&input_data, args.json)?
+    };
+    write_transform_output(&args.output, args.force, &output_data)
+}
+
+/// Starts the output of `transform --bzip2`, ahead of the primary index and the transformed bytes
+const ROTATION_MAGIC: [u8; 4] = *b"RBWR";
+
+/// Undoes a transform written by `transform --bzip2`
+fn reverse_rotation_transform(input_data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let (orig_ptr_bytes, bytes) = input_data
+        .strip_prefix(ROTATION_MAGIC.as_slice())
+        .ok_or("not a bzip2-style transform (bad magic bytes)")?
+        .split_at_checked(8)
+        .ok_or("input is too short to hold a primary index")?;
+    let orig_ptr = u64::from_le_bytes(orig_ptr_bytes.try_into().unwrap());
+    match usize::try_from(orig_ptr) {
+        Ok(orig_ptr) if orig_ptr < bytes.len().max(1) => {
+            Ok(rust_bwt::rotation_reverse(bytes, orig_ptr)?)
+        }
+        _ => Err(format!(
+            "primary index {} is out of range for {} transformed bytes",
+            orig_ptr,
+            bytes.len()
+        )
+        .into()),
+    }
+}
+
+/// Undoes a transform in the raw layout written by `transform` (or its JSON form), checking that
+/// it's consistent instead of producing garbage
+fn reverse_raw_transform(
+    input_data: &[u8],
+    json: bool,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let (bytes, sentinel_index) = if json {
+        json::from_json(std::str::from_utf8(input_data)?)?
+    } else {
+        let (sentinel_index_bytes, bytes) = input_data
+            .split_at_checked(8)
+            .ok_or("input is too short to hold a sentinel index")?;
+        let sentinel_index = u64::from_le_bytes(sentinel_index_bytes.try_into().unwrap());
+        (bytes.to_vec(), usize::try_from(sentinel_index)?)
+    };
+    if sentinel_index > bytes.len() {
+        return Err(format!(
+            "sentinel index {} is out of range for {} transformed bytes",
+            sentinel_index,
+            bytes.len()
+        )
+        .into());
+    }
+
+    let transformed = rust_bwt::BWTStr::new_with_sentinel(bytes, sentinel_index);
+    Ok(transformed.reverse_transform_checked()?.bytes().collect())
+}
+
+fn write_transform_output(
+    output: &Option<PathBuf>,
+    force: bool,
+    output_data: &[u8],
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(output_file) = output {
+        create_output(output_file, force)?.write_all(output_data)?;
+    } else {
+        std::io::stdout().lock().write_all(output_data)?;
     }
 
     Ok(())
 }
+
+/// Decides whether writing `data` to stdout should be refused, so binary output doesn't garble an
+/// interactive terminal. Data is considered binary if it has NUL bytes or isn't valid UTF-8.
+/// Asking for stdout with `-c` counts as meaning it, like `--force`.
+fn refuse_binary_to_terminal(
+    data: &[u8],
+    stdout_is_terminal: bool,
+    force: bool,
+    to_stdout: bool,
+) -> bool {
+    stdout_is_terminal
+        && !force
+        && !to_stdout
+        && (data.contains(&0) || std::str::from_utf8(data).is_err())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_only_binary_data_to_a_terminal() {
+        // (data, stdout is a terminal, --force, -c, refused)
+        for (data, terminal, force, to_stdout, refused) in [
+            (&b"hello\n"[..], true, false, false, false),
+            ("héllo\n".as_bytes(), true, false, false, false),
+            (b"", true, false, false, false),
+            (b"a\x00b", true, false, false, true),
+            (b"\xff\xfe", true, false, false, true),
+            (b"\xff\xfe", false, false, false, false),
+            (b"\xff\xfe", true, true, false, false),
+            (b"\xff\xfe", true, false, true, false),
+            (b"a\x00b", false, true, true, false),
+        ] {
+            assert_eq!(
+                refuse_binary_to_terminal(data, terminal, force, to_stdout),
+                refused,
+                "{:?} terminal {} force {} -c {}",
+                data,
+                terminal,
+                force,
+                to_stdout
+            );
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use alloc::vec::Vec;
+
+/// Move-to-front encodes `input`: each byte is replaced by its position in a table of all 256 byte
+/// values, and then moved to the front of that table. Runs of equal bytes, like the ones the BWT
+/// produces, become runs of zeros.
+pub fn mtf_encode(input: &[u8]) -> Vec<u8> {
+    let mut table = initial_table();
+
+    input
+        .iter()
+        .map(|b| {
+            let index = table.iter().position(|entry| entry == b).unwrap();
+            table.copy_within(0..index, 1);
+            table[0] = *b;
+            index as u8
+        })
+        .collect()
+}
+
+/// Reverses [`mtf_encode`]
+pub fn mtf_decode(input: &[u8]) -> Vec<u8> {
+    let mut table = initial_table();
+
+    input
+        .iter()
+        .map(|index| {
+            let index = *index as usize;
+            let b = table[index];
+            table.copy_within(0..index, 1);
+            table[0] = b;
+            b
+        })
+        .collect()
+}
+
+fn initial_table() -> [u8; 256] {
+    core::array::from_fn(|i| i as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    #[test]
+    fn encodes_positions_in_the_table() {
+        assert_eq!(
+            mtf_encode(b"bananaaa"),
+            vec![b'b', b'b', b'n', 1, 1, 1, 0, 0]
+        );
+        assert_eq!(mtf_decode(&[b'b', b'b', b'n', 1, 1, 1, 0, 0]), b"bananaaa");
+    }
+
+    #[test]
+    fn round_trips() {
+        let every_byte = (0..=255).rev().collect::<Vec<u8>>();
+        for input in [
+            &b""[..],
+            b"\x00",
+            b"\xff\xff\x00\xff",
+            b"mississippi",
+            &every_byte,
+        ] {
+            assert_eq!(mtf_decode(&mtf_encode(input)), input);
+        }
+    }
+}
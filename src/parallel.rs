@@ -0,0 +1,82 @@
+use std::collections::BTreeMap;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+/// Runs `work` over every job on up to `threads` worker threads, passing the results to `sink` in
+/// the original job order. Stops at, and returns, the first error from `sink`.
+///
+/// Memory stays bounded: at most `2 * threads` jobs are queued, being worked on, or waiting in the
+/// reorder buffer for an earlier job to finish at any one time.
+pub(crate) fn for_each_ordered<J, T, E>(
+    jobs: impl Iterator<Item = J> + Send,
+    threads: usize,
+    work: impl Fn(J) -> T + Sync,
+    mut sink: impl FnMut(T) -> Result<(), E>,
+) -> Result<(), E>
+where
+    J: Send,
+    T: Send,
+{
+    if threads <= 1 {
+        for job in jobs {
+            sink(work(job))?;
+        }
+        return Ok(());
+    }
+
+    let max_in_flight = 2 * threads;
+
+    let (job_tx, job_rx) = mpsc::sync_channel::<(usize, J)>(max_in_flight);
+    let (done_tx, done_rx) = mpsc::sync_channel::<(usize, T)>(max_in_flight);
+    let (credit_tx, credit_rx) = mpsc::channel::<()>();
+    let job_rx = Mutex::new(job_rx);
+    let work = &work;
+
+    for _ in 0..max_in_flight {
+        credit_tx.send(()).unwrap();
+    }
+
+    thread::scope(|scope| {
+        // Feeder: hands out jobs, but only as fast as the sink frees up room for them
+        scope.spawn(move || {
+            for job in jobs.enumerate() {
+                if credit_rx.recv().is_err() || job_tx.send(job).is_err() {
+                    break;
+                }
+            }
+        });
+
+        for _ in 0..threads {
+            let done_tx = done_tx.clone();
+            let job_rx = &job_rx;
+            scope.spawn(move || loop {
+                let job = job_rx.lock().unwrap().recv();
+                let Ok((i, job)) = job else {
+                    break;
+                };
+                if done_tx.send((i, work(job))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(done_tx);
+
+        // Owned by this closure so that returning early on a sink error hangs up on the feeder and
+        // the workers instead of leaving them blocked
+        let (done_rx, credit_tx) = (done_rx, credit_tx);
+
+        // Jobs can finish out of order, so hold on to results until it's their turn
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        for (i, result) in done_rx {
+            pending.insert(i, result);
+            while let Some(result) = pending.remove(&next) {
+                sink(result)?;
+                next += 1;
+                let _ = credit_tx.send(());
+            }
+        }
+
+        Ok(())
+    })
+}
@@ -0,0 +1,119 @@
+//! A progress bar on stderr, redrawn as blocks finish. It appears by default once a run has taken
+//! [`AUTO_DELAY`], right away with `--progress`, and never with `--quiet`. Never drawn when stderr
+//! isn't a terminal, so logs don't fill up with carriage returns.
+
+use std::io::{IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+/// Width of the bar itself, in characters
+const BAR_WIDTH: usize = 30;
+
+/// Redrawing more often than this only costs time
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long a run goes without a bar unless one is asked for, so small files finish silently
+pub(crate) const AUTO_DELAY: Duration = Duration::from_secs(1);
+
+/// How long to wait before drawing: not at all if the bar was `requested` with `--progress`
+pub(crate) fn delay(requested: bool) -> Duration {
+    if requested {
+        Duration::ZERO
+    } else {
+        AUTO_DELAY
+    }
+}
+
+pub(crate) struct ProgressBar {
+    /// What the count fills up to, if known
+    total: Option<u64>,
+    /// Nothing is drawn until this long after starting
+    delay: Duration,
+    started: Instant,
+    last_drawn: Option<Instant>,
+}
+
+impl ProgressBar {
+    /// A bar counting up to `total`, first drawn `delay` after starting, or `None` if stderr isn't
+    /// a terminal
+    pub(crate) fn new(total: Option<u64>, delay: Duration) -> Option<Self> {
+        std::io::stderr().is_terminal().then(|| Self {
+            total,
+            delay,
+            started: Instant::now(),
+            last_drawn: None,
+        })
+    }
+
+    /// Shows that `done` bytes have been processed
+    pub(crate) fn update(&mut self, done: u64) {
+        let now = Instant::now();
+        if now - self.started < self.delay
+            || self
+                .last_drawn
+                .is_some_and(|last_drawn| now - last_drawn < REDRAW_INTERVAL)
+        {
+            return;
+        }
+        self.last_drawn = Some(now);
+        self.draw(done, now - self.started);
+    }
+
+    /// Shows the final count, whether or not it's due for a redraw. A run that finished before the
+    /// bar's delay leaves nothing behind.
+    pub(crate) fn finish(&mut self, done: u64) {
+        if self.last_drawn.is_none() && self.started.elapsed() < self.delay {
+            return;
+        }
+        self.last_drawn = Some(Instant::now());
+        self.draw(done, self.started.elapsed());
+    }
+
+    fn draw(&self, done: u64, elapsed: Duration) {
+        let rate = done as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+        let line = match self.total.filter(|total| *total > 0) {
+            Some(total) => {
+                let fraction = (done as f64 / total as f64).min(1.0);
+                let filled = (fraction * BAR_WIDTH as f64) as usize;
+                let eta = match total.saturating_sub(done) {
+                    0 => "done".to_string(),
+                    _ if done == 0 => "ETA --:--".to_string(),
+                    left => format!("ETA {}", clock(left as f64 / rate)),
+                };
+                format!(
+                    "[{}{}] {:>3}%  {} / {}  {}",
+                    "#".repeat(filled),
+                    " ".repeat(BAR_WIDTH - filled),
+                    (fraction * 100.0) as u32,
+                    megabytes(done),
+                    megabytes(total),
+                    eta
+                )
+            }
+            None => format!("{}  {:.2} MB/s", megabytes(done), rate / 1e6),
+        };
+
+        // Back to the start of the line, then clear whatever a longer earlier line left behind
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(stderr, "\r{}\x1b[K", line);
+        let _ = stderr.flush();
+    }
+}
+
+impl Drop for ProgressBar {
+    /// Moves off the bar's line, so it stays visible above whatever comes next
+    fn drop(&mut self) {
+        if self.last_drawn.is_some() {
+            eprintln!();
+        }
+    }
+}
+
+fn megabytes(len: u64) -> String {
+    format!("{:.1} MB", len as f64 / 1e6)
+}
+
+/// `seconds` as minutes and seconds, like `2:05`
+fn clock(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
@@ -0,0 +1,275 @@
+use alloc::vec::Vec;
+
+use crate::BwtError;
+
+const SYMBOL_CNT: usize = 256;
+
+/// Size of the little-endian decoded length at the start of the output
+const LEN_BYTE_CNT: usize = (u64::BITS / 8) as usize;
+
+/// The coder renormalizes whenever its range drops below this, so there are always at least 24
+/// bits of range to divide among the model's frequencies
+const RANGE_BOTTOM: u32 = 1 << 24;
+
+/// Added to a byte's frequency every time it's coded
+const FREQ_STEP: u32 = 32;
+
+/// Once the frequencies add up to more than this, they're halved. Keeps `range / total` from
+/// losing too much precision and lets the model follow changing statistics.
+const MAX_TOTAL: u32 = 1 << 16;
+
+/// Size of the encoder's final flush, and of what the decoder reads up front
+const FLUSH_BYTE_CNT: usize = 5;
+
+/// Adaptive order-0 range codes `input`.
+///
+/// The output is the decoded length as a little-endian `u64` followed by the coded bytes. No model
+/// is stored: the encoder and decoder both start from a flat model and update it identically after
+/// every byte, so each byte costs close to its entropy under the statistics seen so far.
+pub fn range_encode(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(LEN_BYTE_CNT + input.len() / 2 + FLUSH_BYTE_CNT);
+    output.extend_from_slice((input.len() as u64).to_le_bytes().as_slice());
+
+    let mut model = Model::new();
+    let mut encoder = Encoder::new(output);
+    for b in input {
+        let (start, size) = model.interval(*b);
+        encoder.encode(start, size, model.total);
+        model.update(*b);
+    }
+
+    encoder.finish()
+}
+
+/// Reverses [`range_encode`]
+pub fn range_decode(input: &[u8]) -> Result<Vec<u8>, BwtError> {
+    let (len_bytes, rest) = input
+        .split_at_checked(LEN_BYTE_CNT)
+        .ok_or(BwtError::TruncatedStream)?;
+    let len = u64::from_le_bytes(len_bytes.try_into().unwrap());
+
+    // The other 255 bytes always keep some of the total, so each byte costs over 1/2048 of an
+    // output byte. That bounds a believable length.
+    if len > (rest.len() as u64).saturating_mul(8 * 256) {
+        return Err(BwtError::TruncatedStream);
+    }
+
+    let mut model = Model::new();
+    let mut decoder = Decoder::new(rest)?;
+    // Only reserve what an ordinary compression ratio would need; the output grows from there
+    let mut output = Vec::with_capacity(len.min(rest.len() as u64 * 8) as usize);
+    while (output.len() as u64) < len {
+        let target = decoder.target(model.total)?;
+        let b = model.symbol_at(target);
+        let (start, size) = model.interval(b);
+        decoder.consume(start, size)?;
+        model.update(b);
+        output.push(b);
+    }
+
+    Ok(output)
+}
+
+/// Byte frequencies, shared in lockstep by the encoder and the decoder
+struct Model {
+    freqs: [u32; SYMBOL_CNT],
+    total: u32,
+}
+
+impl Model {
+    fn new() -> Self {
+        Self {
+            freqs: [1; SYMBOL_CNT],
+            total: SYMBOL_CNT as u32,
+        }
+    }
+
+    /// The `(start, size)` of `b`'s slice of `0..total`
+    fn interval(&self, b: u8) -> (u32, u32) {
+        let start = self.freqs[..b as usize].iter().sum();
+        (start, self.freqs[b as usize])
+    }
+
+    /// The byte whose slice of `0..total` contains `target`
+    fn symbol_at(&self, target: u32) -> u8 {
+        let mut start = 0;
+        for (b, freq) in self.freqs.iter().enumerate() {
+            start += freq;
+            if target < start {
+                return b as u8;
+            }
+        }
+        unreachable!("target is always below the total")
+    }
+
+    fn update(&mut self, b: u8) {
+        self.freqs[b as usize] += FREQ_STEP;
+        self.total += FREQ_STEP;
+
+        if self.total > MAX_TOTAL {
+            // Rounding up keeps every byte codable
+            for freq in self.freqs.iter_mut() {
+                *freq = freq.div_ceil(2);
+            }
+            self.total = self.freqs.iter().sum();
+        }
+    }
+}
+
+/// Range encoder with carry propagation: a pending byte and a count of `0xFF` bytes after it are
+/// held back until it's known whether a carry will ripple into them
+struct Encoder {
+    output: Vec<u8>,
+    low: u64,
+    range: u32,
+    cache: u8,
+    cache_len: u64,
+}
+
+impl Encoder {
+    fn new(output: Vec<u8>) -> Self {
+        Self {
+            output,
+            low: 0,
+            range: u32::MAX,
+            cache: 0,
+            cache_len: 1,
+        }
+    }
+
+    fn encode(&mut self, start: u32, size: u32, total: u32) {
+        self.range /= total;
+        self.low += start as u64 * self.range as u64;
+        self.range *= size;
+        while self.range < RANGE_BOTTOM {
+            self.range <<= 8;
+            self.shift_low();
+        }
+    }
+
+    fn shift_low(&mut self) {
+        let carry = self.low >> 32 != 0;
+        if (self.low as u32) < 0xFF00_0000 || carry {
+            let mut pending = self.cache;
+            for _ in 0..self.cache_len {
+                self.output.push(pending.wrapping_add(carry as u8));
+                pending = 0xFF;
+            }
+            self.cache_len = 0;
+            self.cache = (self.low >> 24) as u8;
+        }
+        self.cache_len += 1;
+        self.low = (self.low & 0x00FF_FFFF) << 8;
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        for _ in 0..FLUSH_BYTE_CNT {
+            self.shift_low();
+        }
+        self.output
+    }
+}
+
+struct Decoder<'a> {
+    input: &'a [u8],
+    code: u32,
+    range: u32,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(input: &'a [u8]) -> Result<Self, BwtError> {
+        let mut decoder = Self {
+            input,
+            code: 0,
+            range: u32::MAX,
+        };
+        // The encoder's first byte is always its initial, empty cache
+        for _ in 0..FLUSH_BYTE_CNT {
+            decoder.code = (decoder.code << 8) | decoder.next_byte()? as u32;
+        }
+        Ok(decoder)
+    }
+
+    fn next_byte(&mut self) -> Result<u8, BwtError> {
+        let (b, rest) = self.input.split_first().ok_or(BwtError::TruncatedStream)?;
+        self.input = rest;
+        Ok(*b)
+    }
+
+    /// Where the next symbol falls in `0..total`. Must be followed by [`Decoder::consume`].
+    fn target(&mut self, total: u32) -> Result<u32, BwtError> {
+        self.range /= total;
+        let target = self.code / self.range;
+        if target >= total {
+            return Err(BwtError::CorruptStream("invalid range coder state"));
+        }
+        Ok(target)
+    }
+
+    fn consume(&mut self, start: u32, size: u32) -> Result<(), BwtError> {
+        self.code -= start * self.range;
+        self.range *= size;
+        while self.range < RANGE_BOTTOM {
+            self.range <<= 8;
+            self.code = (self.code << 8) | self.next_byte()? as u32;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let every_byte = (0..=255).collect::<Vec<u8>>();
+        // Long enough for the model to be halved many times over
+        let runs = [vec![0; 50_000], vec![255; 50_000], every_byte.repeat(100)].concat();
+        let mut state = 7_u32;
+        let noise = (0..20_000)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 24) as u8
+            })
+            .collect::<Vec<_>>();
+        for input in [
+            &b""[..],
+            b"\x00",
+            b"\xff",
+            b"abracadabra",
+            &every_byte,
+            &runs,
+            &noise,
+        ] {
+            assert_eq!(range_decode(&range_encode(input)).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn compresses_skewed_input() {
+        let input = [vec![b'a'; 10_000], vec![b'b'; 100]].concat();
+        assert!(range_encode(&input).len() < input.len() / 20);
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let encoded = range_encode(b"abracadabra");
+        for len in [0, LEN_BYTE_CNT - 1, LEN_BYTE_CNT, LEN_BYTE_CNT + 2] {
+            assert!(matches!(
+                range_decode(&encoded[..len]),
+                Err(BwtError::TruncatedStream)
+            ));
+        }
+
+        // A length far beyond what the coded bytes could hold
+        let mut too_long = encoded.clone();
+        too_long[..LEN_BYTE_CNT].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(
+            range_decode(&too_long),
+            Err(BwtError::TruncatedStream)
+        ));
+    }
+}
@@ -0,0 +1,180 @@
+use alloc::vec::Vec;
+
+use crate::BwtError;
+
+/// Run-length digit worth 1 × its place value
+const RUNA: u8 = 0;
+/// Run-length digit worth 2 × its place value
+const RUNB: u8 = 1;
+/// Introduces one of the two non-zero values (254 and 255) that don't fit once shifted by one
+const ESCAPE: u8 = 255;
+
+/// bzip2-style zero-run encoding, meant for move-to-front output where zeros dominate.
+///
+/// Each run of zeros becomes its length in bijective base 2, least significant digit first, using
+/// the `RUNA`/`RUNB` symbols `0` and `1`, so a run of 100 zeros costs 6 bytes. Non-zero bytes are
+/// shifted up by one to make room; the two largest values, 254 and 255, don't fit once shifted and
+/// are written as `255` followed by `0` or `1` respectively.
+pub fn rle0_encode(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len());
+    let mut run_len = 0_usize;
+
+    for b in input {
+        if *b == 0 {
+            run_len += 1;
+            continue;
+        }
+
+        push_run(&mut output, run_len);
+        run_len = 0;
+
+        match b {
+            1..=253 => output.push(b + 1),
+            _ => output.extend_from_slice(&[ESCAPE, b - 254]),
+        }
+    }
+    push_run(&mut output, run_len);
+
+    output
+}
+
+/// Reverses [`rle0_encode`]
+pub fn rle0_decode(input: &[u8]) -> Result<Vec<u8>, BwtError> {
+    rle0_decode_limited(input, usize::MAX)
+}
+
+/// Like [`rle0_decode`], but fails with [`BwtError::OutputTooLarge`] instead of producing more than
+/// `limit` bytes, before allocating room for them
+pub(crate) fn rle0_decode_limited(input: &[u8], limit: usize) -> Result<Vec<u8>, BwtError> {
+    let mut output = Vec::with_capacity(input.len());
+    let mut run_len = 0_usize;
+    let mut place_value = 1_usize;
+
+    let mut iter = input.iter();
+    while let Some(symbol) = iter.next() {
+        let digit = match *symbol {
+            RUNA => 1,
+            RUNB => 2,
+            _ => 0,
+        };
+        if digit != 0 {
+            run_len = place_value
+                .checked_mul(digit)
+                .and_then(|value| run_len.checked_add(value))
+                .ok_or(BwtError::CorruptStream("zero run is too long"))?;
+            place_value = place_value.saturating_mul(2);
+            continue;
+        }
+
+        push_zeros(&mut output, run_len, limit)?;
+        run_len = 0;
+        place_value = 1;
+
+        match *symbol {
+            ESCAPE => match iter.next() {
+                Some(offset @ 0..=1) => output.push(254 + offset),
+                Some(_) => return Err(BwtError::CorruptStream("invalid escaped value")),
+                None => return Err(BwtError::TruncatedStream),
+            },
+            shifted => output.push(shifted - 1),
+        }
+        if output.len() > limit {
+            return Err(BwtError::OutputTooLarge {
+                limit: limit as u64,
+            });
+        }
+    }
+    push_zeros(&mut output, run_len, limit)?;
+
+    Ok(output)
+}
+
+fn push_zeros(output: &mut Vec<u8>, run_len: usize, limit: usize) -> Result<(), BwtError> {
+    if run_len > limit - output.len() {
+        return Err(BwtError::OutputTooLarge {
+            limit: limit as u64,
+        });
+    }
+    output.extend(core::iter::repeat_n(0, run_len));
+    Ok(())
+}
+
+/// Writes `run_len` in bijective base 2, least significant digit first
+fn push_run(output: &mut Vec<u8>, mut run_len: usize) {
+    while run_len > 0 {
+        if run_len % 2 == 1 {
+            output.push(RUNA);
+            run_len = (run_len - 1) / 2;
+        } else {
+            output.push(RUNB);
+            run_len = (run_len - 2) / 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    #[test]
+    fn encodes_zero_runs_in_bijective_base_2() {
+        assert_eq!(
+            rle0_encode(&[0; 100]),
+            vec![RUNB, RUNA, RUNB, RUNA, RUNA, RUNB]
+        );
+        assert_eq!(rle0_encode(&[0, 1, 0, 0]), vec![RUNA, 2, RUNB]);
+        assert_eq!(
+            rle0_encode(&[253, 254, 255]),
+            vec![254, ESCAPE, 0, ESCAPE, 1]
+        );
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut inputs = vec![Vec::new(), vec![0], vec![255], vec![254, 0, 255]];
+        inputs.push((0..=255).collect());
+        for run_len in [1, 2, 3, 4, 7, 8, 255, 256, 1000] {
+            let mut input = vec![0; run_len];
+            input.extend([5, 0, 254]);
+            input.extend(vec![0; run_len]);
+            inputs.push(input);
+        }
+
+        for input in &inputs {
+            assert_eq!(rle0_decode(&rle0_encode(input)).unwrap(), *input);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert!(matches!(
+            rle0_decode(&[2, ESCAPE]),
+            Err(BwtError::TruncatedStream)
+        ));
+        assert!(matches!(
+            rle0_decode(&[ESCAPE, 2]),
+            Err(BwtError::CorruptStream(_))
+        ));
+        // A run with more digits than a usize holds
+        assert!(matches!(
+            rle0_decode(&[RUNB; usize::BITS as usize + 1]),
+            Err(BwtError::CorruptStream(_))
+        ));
+    }
+
+    #[test]
+    fn stops_at_the_limit() {
+        let encoded = rle0_encode(&[0; 100]);
+        assert_eq!(rle0_decode_limited(&encoded, 100).unwrap().len(), 100);
+        assert!(matches!(
+            rle0_decode_limited(&encoded, 99),
+            Err(BwtError::OutputTooLarge { limit: 99 })
+        ));
+        assert!(matches!(
+            rle0_decode_limited(&[2, 3, 4], 2),
+            Err(BwtError::OutputTooLarge { limit: 2 })
+        ));
+    }
+}
@@ -0,0 +1,121 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::suffix::sorted_suffixes;
+use crate::{BwtError, SuffixSort};
+
+/// The Burrows-Wheeler transform as bzip2 computes it on a block: every rotation of `input` is
+/// sorted, with no sentinel, and the output is the last byte of each along with the row holding
+/// `input` itself (bzip2's `origPtr`). Equal rotations of a periodic input are ordered like bzip2
+/// orders them, so the primary index matches too.
+///
+/// The rotations are sorted as the suffixes of `input` twice over: the first `input.len()` bytes
+/// of the suffix starting at `i` are the rotation starting at `i`, and among equal rotations, the
+/// later start has the shorter suffix and sorts first.
+///
+/// ```
+/// let (bwt, orig_ptr) = rust_bwt::rotation_forward(b"banana");
+/// assert_eq!((bwt.as_slice(), orig_ptr), (&b"nnbaaa"[..], 3));
+/// assert_eq!(rust_bwt::rotation_reverse(&bwt, orig_ptr)?, b"banana");
+/// # Ok::<(), rust_bwt::BwtError>(())
+/// ```
+pub fn rotation_forward(input: &[u8]) -> (Vec<u8>, usize) {
+    let doubled = [input, input].concat();
+
+    let mut bwt = Vec::with_capacity(input.len());
+    let mut orig_ptr = 0;
+    for start in sorted_suffixes(&doubled, SuffixSort::Auto)
+        .into_iter()
+        .filter(|start| *start < input.len())
+    {
+        if start == 0 {
+            orig_ptr = bwt.len();
+        }
+        bwt.push(input[(start + input.len() - 1) % input.len()]);
+    }
+
+    (bwt, orig_ptr)
+}
+
+/// Reverses [`rotation_forward`]. Fails with [`BwtError::InvalidSentinelIndex`] if `orig_ptr`,
+/// which plays the sentinel index's part, isn't a row of `bwt`.
+pub fn rotation_reverse(bwt: &[u8], orig_ptr: usize) -> Result<Vec<u8>, BwtError> {
+    if orig_ptr >= bwt.len().max(1) {
+        return Err(BwtError::InvalidSentinelIndex);
+    }
+
+    // Stably sorting the last column gives the first column, as in bzip2's decoder: `next[r]` is
+    // the row whose last byte is row r's first, which is row r's rotation shifted left by one
+    let mut starts = [0_usize; 256];
+    for b in bwt {
+        starts[*b as usize] += 1;
+    }
+    let mut total = 0;
+    for start in starts.iter_mut() {
+        (*start, total) = (total, total + *start);
+    }
+    let mut next = vec![0; bwt.len()];
+    for (row, b) in bwt.iter().enumerate() {
+        next[starts[*b as usize]] = row;
+        starts[*b as usize] += 1;
+    }
+
+    // A periodic input's rows form a cycle per period, which the walk just goes round again
+    let mut row = orig_ptr;
+    Ok((0..bwt.len())
+        .map(|_| {
+            row = next[row];
+            bwt[row]
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_bzip2() {
+        // bzip2 -9 stores these primary indexes for the inputs, none of which has the runs of four
+        // or more that its first RLE pass would shorten
+        for (input, bwt, orig_ptr) in [
+            (&b"banana"[..], &b"nnbaaa"[..], 3),
+            (b"abracadabra", b"rdarcaaaabb", 2),
+            (b"mississippi", b"pssmipissii", 4),
+            (b"abab", b"bbaa", 1),
+            (b"xyxyxyx", b"yyyxxxx", 3),
+            (b"Hello, world!\n", b"!,do\nlHrellwo ", 4),
+            (
+                b"The quick brown fox jumps over the lazy dog",
+                b"kynxesergl i hhv otTu c uwd rfm ebp qjoooza",
+                8,
+            ),
+        ] {
+            assert_eq!(rotation_forward(input), (bwt.to_vec(), orig_ptr));
+            assert_eq!(rotation_reverse(bwt, orig_ptr).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let long = (0..5000_u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 28) as u8)
+            .collect::<Vec<_>>();
+        for input in [&b""[..], b"a", b"aaaa", &b"abc".repeat(300), &long] {
+            let (bwt, orig_ptr) = rotation_forward(input);
+            assert_eq!(rotation_reverse(&bwt, orig_ptr).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn rejects_a_primary_index_past_the_end() {
+        assert!(matches!(
+            rotation_reverse(b"nnbaaa", 6),
+            Err(BwtError::InvalidSentinelIndex)
+        ));
+        assert!(matches!(
+            rotation_reverse(b"", 1),
+            Err(BwtError::InvalidSentinelIndex)
+        ));
+    }
+}
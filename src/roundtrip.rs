@@ -0,0 +1,141 @@
+//! `compress --verify`: the compressed output is decompressed on a second thread as it's written,
+//! and must come back as the input. Only the length and CRC-32 of each side are kept, so checking
+//! a large file needs no more memory than compressing it.
+
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::sync::mpsc;
+use std::thread;
+
+use rust_bwt::{Crc32, DecompressOptions, Options, Progress, StageTimes};
+
+use crate::CountingWriter;
+
+/// How many writes of compressed output may be waiting for the decoder at once
+const MAX_QUEUED_WRITES: usize = 64;
+
+/// Compresses `input` into `output` like [`rust_bwt::compress_stream_progress`], and fails unless
+/// the output decompresses to exactly the bytes read from `input`. The stage times don't include
+/// the decoder's.
+pub(crate) fn compress_verified<R: Read + Send, W: Write>(
+    input: R,
+    options: &Options,
+    output: &mut W,
+    progress: impl FnMut(Progress),
+) -> Result<StageTimes, Box<dyn Error>> {
+    let mut input = CrcReader {
+        inner: input,
+        len: 0,
+        crc: Crc32::new(),
+    };
+    let decode_options = DecompressOptions {
+        threads: options.threads,
+        checked_reverse: true,
+        ..Default::default()
+    };
+    let (copy_tx, copy_rx) = mpsc::sync_channel(MAX_QUEUED_WRITES);
+
+    thread::scope(|scope| {
+        let decoder = scope.spawn(move || {
+            let mut decoded = CountingWriter::new(Crc32::new());
+            rust_bwt::decompress_stream_with(
+                ChannelReader::new(copy_rx),
+                &decode_options,
+                &mut decoded,
+            )
+            .map(|()| (decoded.count, decoded.inner.finish()))
+        });
+
+        let mut tee = TeeWriter {
+            inner: output,
+            copy: copy_tx,
+        };
+        let compressed =
+            rust_bwt::compress_stream_progress(&mut input, options, &mut tee, progress);
+        // Hanging up tells the decoder the stream is over
+        drop(tee);
+        let decoded = decoder.join().expect("the verifying decoder panicked");
+
+        let times = compressed?;
+        let (decoded_len, decoded_crc) =
+            decoded.map_err(|e| format!("the compressed output doesn't decompress: {}", e))?;
+        let (input_len, input_crc) = (input.len, input.crc.finish());
+        if (decoded_len, decoded_crc) != (input_len, input_crc) {
+            return Err(format!(
+                "the compressed output decompresses to {} bytes with CRC-32 {:08x}, but the input \
+                 was {} bytes with CRC-32 {:08x}",
+                decoded_len, decoded_crc, input_len, input_crc
+            )
+            .into());
+        }
+        Ok(times)
+    })
+}
+
+/// Passes reads through to `inner`, keeping the length and CRC-32 of everything read
+struct CrcReader<R> {
+    inner: R,
+    len: u64,
+    crc: Crc32,
+}
+
+impl<R: Read> Read for CrcReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.len += read as u64;
+        self.crc.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// Passes writes through to `inner`, sending a copy of each to the decoder
+struct TeeWriter<'a, W> {
+    inner: &'a mut W,
+    copy: mpsc::SyncSender<Vec<u8>>,
+}
+
+impl<W: Write> Write for TeeWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        // A decoder that hung up has already failed, and will report why
+        let _ = self.copy.send(buf[..written].to_vec());
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads the copies sent by a [`TeeWriter`], in order, until it hangs up
+struct ChannelReader {
+    chunks: mpsc::Receiver<Vec<u8>>,
+    current: Vec<u8>,
+    pos: usize,
+}
+
+impl ChannelReader {
+    fn new(chunks: mpsc::Receiver<Vec<u8>>) -> Self {
+        Self {
+            chunks,
+            current: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.current.len() {
+            match self.chunks.recv() {
+                Ok(chunk) => (self.current, self.pos) = (chunk, 0),
+                Err(mpsc::RecvError) => return Ok(0),
+            }
+        }
+
+        let read = buf.len().min(self.current.len() - self.pos);
+        buf[..read].copy_from_slice(&self.current[self.pos..self.pos + read]);
+        self.pos += read;
+        Ok(read)
+    }
+}
@@ -0,0 +1,76 @@
+//! `--stats` and `--stats-json`: sizes, ratio, and where the time went, printed to stderr so they
+//! never mix with data written to stdout.
+
+use std::time::Duration;
+
+use rust_bwt::StageTimes;
+
+/// What one run of `compress` or `decompress` did
+pub(crate) struct Stats {
+    /// Bytes read, counted as they're read, so stdin works too
+    pub(crate) input_len: u64,
+    /// Bytes written, counted the same way
+    pub(crate) output_len: u64,
+    /// Whether the input is the original data, rather than the output. Throughput is measured in
+    /// original bytes.
+    pub(crate) compressing: bool,
+    /// Wall time from opening the input to the output being written
+    pub(crate) elapsed: Duration,
+    pub(crate) stages: StageTimes,
+}
+
+impl Stats {
+    /// Prints the stats to stderr, as a single JSON object with `json`
+    pub(crate) fn report(&self, json: bool) {
+        let (original_len, compressed_len) = if self.compressing {
+            (self.input_len, self.output_len)
+        } else {
+            (self.output_len, self.input_len)
+        };
+        let ratio = (original_len > 0).then(|| compressed_len as f64 / original_len as f64);
+        let throughput = original_len as f64 / 1e6 / self.elapsed.as_secs_f64();
+        let stages = [
+            ("transform", self.stages.transform),
+            ("mtf", self.stages.mtf),
+            ("runs", self.stages.runs),
+            ("entropy", self.stages.entropy),
+            ("checksum", self.stages.checksum),
+            ("io", self.stages.io),
+        ];
+
+        if json {
+            let stages = stages
+                .iter()
+                .map(|(stage, time)| format!("\"{}\":{:.6}", stage, time.as_secs_f64()))
+                .collect::<Vec<_>>();
+            eprintln!(
+                "{{\"input_size\":{},\"output_size\":{},\"ratio\":{},\"seconds\":{:.6},\"mb_per_second\":{:.3},\"stage_seconds\":{{{}}}}}",
+                self.input_len,
+                self.output_len,
+                ratio.map_or("null".to_string(), |ratio| format!("{:.6}", ratio)),
+                self.elapsed.as_secs_f64(),
+                throughput,
+                stages.join(",")
+            );
+        } else {
+            eprintln!("Input size:  {} bytes", self.input_len);
+            eprintln!("Output size: {} bytes", self.output_len);
+            if let Some(ratio) = ratio {
+                eprintln!(
+                    "Ratio:       {:.3} ({:.2} bits per byte)",
+                    ratio,
+                    ratio * 8.0
+                );
+            }
+            eprintln!(
+                "Time:        {:.3} s ({:.2} MB/s)",
+                self.elapsed.as_secs_f64(),
+                throughput
+            );
+            eprintln!("Stage times, summed over threads:");
+            for (stage, time) in stages {
+                eprintln!("  {:<10} {:.3} s", stage, time.as_secs_f64());
+            }
+        }
+    }
+}
@@ -0,0 +1,320 @@
+//! Suffix sorting for the forward BWT. Both algorithms sort the suffixes of the input followed by a
+//! sentinel that is unique and sorts first, and give the same result; they differ only in speed.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::SuffixSort;
+
+/// Inputs up to this long are sorted by prefix doubling under [`SuffixSort::Auto`]. Below it,
+/// SA-IS's extra passes and recursion cost more than doubling's log factor.
+const DOUBLING_MAX_LEN: usize = 1 << 9;
+
+/// The start of every suffix of `bytes` followed by a sentinel, in sorted order. The suffix made
+/// of the sentinel alone starts at `bytes.len()`, and always comes first.
+pub(crate) fn sorted_suffixes(bytes: &[u8], suffix_sort: SuffixSort) -> Vec<usize> {
+    // Bytes shifted up by one, leaving 0 for the sentinel
+    let text = bytes
+        .iter()
+        .map(|b| *b as usize + 1)
+        .chain([0])
+        .collect::<Vec<_>>();
+
+    match suffix_sort {
+        SuffixSort::Auto if bytes.len() <= DOUBLING_MAX_LEN => doubling(&text, BYTE_ALPHABET),
+        SuffixSort::Doubling => doubling(&text, BYTE_ALPHABET),
+        SuffixSort::Auto | SuffixSort::SaIs => sa_is(&text, BYTE_ALPHABET),
+    }
+}
+
+/// Every byte value plus the sentinel
+const BYTE_ALPHABET: usize = 256 + 1;
+
+/// Prefix doubling (Manber and Myers): sorts the suffixes by their first 1, 2, 4, ... symbols,
+/// each round a counting sort on the ranks from the one before, until every rank is distinct.
+/// O(n log n).
+///
+/// `text` must end in a sentinel smaller than every other symbol, which lets the suffixes be
+/// sorted as the text's rotations: none compares past it. Symbols must be below `alphabet`.
+fn doubling(text: &[usize], alphabet: usize) -> Vec<usize> {
+    let n = text.len();
+    let mut suffixes = vec![0; n];
+    let mut ranks = text.to_vec();
+    let mut counts = vec![0; alphabet.max(n)];
+
+    // Sorted by their first symbol
+    for symbol in text {
+        counts[*symbol] += 1;
+    }
+    prefix_sums(&mut counts[..alphabet]);
+    for (i, symbol) in text.iter().enumerate() {
+        suffixes[counts[*symbol]] = i;
+        counts[*symbol] += 1;
+    }
+    let mut class_cnt = rerank(&mut ranks, &suffixes, |i| text[i]);
+
+    let mut shifted = vec![0; n];
+    let mut len = 1;
+    while class_cnt < n {
+        // Ordered by the rank of their second half, the rotations starting `len` earlier are
+        // already sorted by it; a stable sort by their first half's rank finishes the job
+        for (shifted, suffix) in shifted.iter_mut().zip(&suffixes) {
+            *shifted = (suffix + n - len) % n;
+        }
+        counts[..class_cnt].fill(0);
+        for suffix in &shifted {
+            counts[ranks[*suffix]] += 1;
+        }
+        prefix_sums(&mut counts[..class_cnt]);
+        for suffix in &shifted {
+            suffixes[counts[ranks[*suffix]]] = *suffix;
+            counts[ranks[*suffix]] += 1;
+        }
+
+        let previous = ranks.clone();
+        class_cnt = rerank(&mut ranks, &suffixes, |i| {
+            (previous[i], previous[(i + len) % n])
+        });
+        len *= 2;
+    }
+
+    suffixes
+}
+
+/// Turns `counts` into the start of each symbol's range in a sorted array
+fn prefix_sums(counts: &mut [usize]) {
+    let mut total = 0;
+    for count in counts {
+        (*count, total) = (total, total + *count);
+    }
+}
+
+/// Gives each suffix the number of distinct `key`s before its own in `suffixes`, which must be
+/// sorted by `key`. Returns how many distinct keys there are.
+fn rerank<K: PartialEq>(
+    ranks: &mut [usize],
+    suffixes: &[usize],
+    key: impl Fn(usize) -> K,
+) -> usize {
+    let mut class = 0;
+    let mut previous = None;
+    for suffix in suffixes {
+        let key = key(*suffix);
+        if previous.as_ref().is_some_and(|previous| *previous != key) {
+            class += 1;
+        }
+        ranks[*suffix] = class;
+        previous = Some(key);
+    }
+    class + 1
+}
+
+/// SA-IS (Nong, Zhang and Chan): sorts the LMS substrings by induced sorting, names them, sorts
+/// the string of names recursively if they aren't all distinct, and induces the order of every
+/// suffix from the sorted LMS suffixes. O(n).
+///
+/// `text` must end in a sentinel smaller than every other symbol, and symbols must be below
+/// `alphabet`.
+fn sa_is(text: &[usize], alphabet: usize) -> Vec<usize> {
+    let n = text.len();
+    if n == 1 {
+        return vec![0];
+    }
+
+    // A suffix is S-type if it sorts before the one after it, L-type if after. The sentinel's is
+    // S-type, so the suffix before it, which is larger, is L-type.
+    let mut s_type = vec![false; n];
+    s_type[n - 1] = true;
+    for i in (0..n - 1).rev() {
+        s_type[i] = text[i] < text[i + 1] || (text[i] == text[i + 1] && s_type[i + 1]);
+    }
+    // Leftmost S-type: an S-type suffix right after an L-type one
+    let is_lms = |i: usize| i > 0 && s_type[i] && !s_type[i - 1];
+
+    let mut bucket_sizes = vec![0; alphabet];
+    for symbol in text {
+        bucket_sizes[*symbol] += 1;
+    }
+
+    // Sorting the LMS suffixes by their first LMS substring alone puts the substrings in order
+    let lms = (0..n).filter(|i| is_lms(*i)).collect::<Vec<_>>();
+    let suffixes = induce(text, &s_type, &bucket_sizes, &lms);
+
+    // Name each LMS substring by its rank among the distinct ones
+    let mut names = vec![NONE; n];
+    let mut name = 0;
+    let mut previous = None;
+    for suffix in suffixes.into_iter().filter(|i| is_lms(*i)) {
+        if previous.is_some_and(|previous| !lms_substrings_eq(text, &s_type, previous, suffix)) {
+            name += 1;
+        }
+        names[suffix] = name;
+        previous = Some(suffix);
+    }
+    let name_cnt = name + 1;
+
+    // The names, in text order, are a shorter text whose suffix order is the LMS suffixes'. It ends
+    // in the name of the sentinel's substring, which is unique and sorts first. `names` is as long
+    // as the text, so it's freed before recursing.
+    let reduced = lms.iter().map(|i| names[*i]).collect::<Vec<_>>();
+    drop(names);
+    let reduced_suffixes = if name_cnt == reduced.len() {
+        let mut reduced_suffixes = vec![0; reduced.len()];
+        for (i, name) in reduced.iter().enumerate() {
+            reduced_suffixes[*name] = i;
+        }
+        reduced_suffixes
+    } else {
+        sa_is(&reduced, name_cnt)
+    };
+
+    let sorted_lms = reduced_suffixes
+        .into_iter()
+        .map(|i| lms[i])
+        .collect::<Vec<_>>();
+    induce(text, &s_type, &bucket_sizes, &sorted_lms)
+}
+
+/// An empty slot of a suffix array being induced
+const NONE: usize = usize::MAX;
+
+/// Induced sorting: places the LMS suffixes `lms` at the ends of their buckets, keeping their
+/// order, then induces the L-type suffixes from left to right and the S-type ones from right to
+/// left. If `lms` is sorted, so is the result.
+fn induce(text: &[usize], s_type: &[bool], bucket_sizes: &[usize], lms: &[usize]) -> Vec<usize> {
+    let n = text.len();
+    let mut suffixes = vec![NONE; n];
+
+    let mut tails = bucket_tails(bucket_sizes);
+    for i in lms.iter().rev() {
+        tails[text[*i]] -= 1;
+        suffixes[tails[text[*i]]] = *i;
+    }
+
+    let mut heads = bucket_tails(bucket_sizes);
+    for (head, size) in heads.iter_mut().zip(bucket_sizes) {
+        *head -= size;
+    }
+    for k in 0..n {
+        let i = suffixes[k];
+        if i != NONE && i > 0 && !s_type[i - 1] {
+            suffixes[heads[text[i - 1]]] = i - 1;
+            heads[text[i - 1]] += 1;
+        }
+    }
+
+    let mut tails = bucket_tails(bucket_sizes);
+    for k in (0..n).rev() {
+        let i = suffixes[k];
+        if i != NONE && i > 0 && s_type[i - 1] {
+            tails[text[i - 1]] -= 1;
+            suffixes[tails[text[i - 1]]] = i - 1;
+        }
+    }
+
+    suffixes
+}
+
+/// Where each symbol's bucket ends in the suffix array
+fn bucket_tails(bucket_sizes: &[usize]) -> Vec<usize> {
+    bucket_sizes
+        .iter()
+        .scan(0, |end, size| {
+            *end += size;
+            Some(*end)
+        })
+        .collect()
+}
+
+/// Whether the LMS substrings starting at `a` and `b` (each running to the next LMS position,
+/// inclusive) are equal in both symbols and types
+fn lms_substrings_eq(text: &[usize], s_type: &[bool], a: usize, b: usize) -> bool {
+    let is_lms = |i: usize| i > 0 && s_type[i] && !s_type[i - 1];
+    // The sentinel's substring is the only one that ends the text, and it's unique
+    if a == text.len() - 1 || b == text.len() - 1 {
+        return false;
+    }
+
+    // Neither runs past the sentinel, which is an LMS position
+    let mut offset = 0;
+    loop {
+        let (i, j) = (a + offset, b + offset);
+        if text[i] != text[j] || s_type[i] != s_type[j] {
+            return false;
+        }
+        if offset > 0 && (is_lms(i) || is_lms(j)) {
+            return is_lms(i) && is_lms(j);
+        }
+        offset += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sorts the suffixes by comparing them outright, as the reference the others must match
+    fn naive(bytes: &[u8]) -> Vec<usize> {
+        let mut suffixes = (0..=bytes.len()).collect::<Vec<_>>();
+        // A suffix that's a prefix of another sorts first, just as if it ended in the sentinel
+        suffixes.sort_by(|a, b| bytes[*a..].cmp(&bytes[*b..]));
+        suffixes
+    }
+
+    /// `len` bytes drawn from the first `alphabet` values
+    fn text(len: usize, alphabet: u32, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                ((state >> 16) % alphabet) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_algorithm_sorts_like_a_naive_sort() {
+        let mut inputs = vec![
+            Vec::new(),
+            b"a".to_vec(),
+            b"banana".to_vec(),
+            b"mississippi".to_vec(),
+            vec![0; 700],
+            vec![255; 3],
+            b"ab".repeat(400),
+            b"abaabaaab".repeat(90),
+        ];
+        for (alphabet, seed) in [(2, 1), (4, 2), (26, 3), (256, 4)] {
+            inputs.push(text(DOUBLING_MAX_LEN + 300, alphabet, seed));
+        }
+
+        for bytes in &inputs {
+            let expected = naive(bytes);
+            for suffix_sort in SuffixSort::ALL {
+                assert_eq!(
+                    sorted_suffixes(bytes, suffix_sort),
+                    expected,
+                    "{:?} on {} bytes",
+                    suffix_sort,
+                    bytes.len()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sa_is_matches_doubling() {
+        // Short texts over a tiny alphabet repeat their LMS substrings, so SA-IS recurses
+        for len in 0..200 {
+            for alphabet in [1, 2, 3] {
+                let bytes = text(len, alphabet, len as u32);
+                assert_eq!(
+                    sorted_suffixes(&bytes, SuffixSort::SaIs),
+                    sorted_suffixes(&bytes, SuffixSort::Doubling),
+                    "{:?}",
+                    bytes
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,179 @@
+//! Splitting compressed output into numbered, size-limited volumes (`out.bwt.001`,
+//! `out.bwt.002`, ...) and joining them back together. Volumes are plain slices of the stream, so
+//! reassembly is concatenation in order.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::num::NonZeroU64;
+use std::path::{Path, PathBuf};
+
+/// Extension of the first volume, by which `decompress` recognizes a volume set
+const FIRST_VOLUME_EXTENSION: &str = "001";
+
+/// Parses a size like `4096`, `64K`, `10MiB` or `1G`. Suffixes are binary multiples, with or without
+/// the `iB`/`B`.
+pub(crate) fn parse_size(s: &str) -> Result<NonZeroU64, String> {
+    let digits_len = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (digits, suffix) = s.split_at(digits_len);
+
+    let shift = match suffix.to_ascii_lowercase().as_str() {
+        "" | "b" => 0,
+        "k" | "kb" | "kib" => 10,
+        "m" | "mb" | "mib" => 20,
+        "g" | "gb" | "gib" => 30,
+        _ => return Err(format!("unknown size suffix `{}`", suffix)),
+    };
+
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .and_then(NonZeroU64::new)
+        .ok_or_else(|| format!("invalid size `{}`", s))
+}
+
+/// Path of volume `index` (counting from 1) of `base`
+fn volume_path(base: &Path, index: u32) -> PathBuf {
+    let mut path = base.as_os_str().to_owned();
+    path.push(format!(".{:03}", index));
+    path.into()
+}
+
+/// Writes to `base.001`, `base.002`, ..., starting a new volume whenever the current one reaches
+/// the volume size. A volume is only created once there's data for it. Unless `overwrite`, volumes
+/// that already exist are an error.
+pub(crate) struct VolumeWriter {
+    base: PathBuf,
+    volume_size: u64,
+    overwrite: bool,
+    current: Option<File>,
+    current_len: u64,
+    volume_cnt: u32,
+}
+
+impl VolumeWriter {
+    pub(crate) fn new(base: PathBuf, volume_size: NonZeroU64, overwrite: bool) -> Self {
+        Self {
+            base,
+            volume_size: volume_size.get(),
+            overwrite,
+            current: None,
+            current_len: 0,
+            volume_cnt: 0,
+        }
+    }
+
+    /// Deletes every volume written so far
+    pub(crate) fn remove(self) -> io::Result<()> {
+        for index in 1..=self.volume_cnt {
+            std::fs::remove_file(volume_path(&self.base, index))?;
+        }
+        Ok(())
+    }
+
+    /// Flushes every volume written so far to disk
+    pub(crate) fn sync_all(&self) -> io::Result<()> {
+        for index in 1..=self.volume_cnt {
+            File::open(volume_path(&self.base, index))?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for VolumeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.current.is_none() || self.current_len == self.volume_size {
+            if let Some(mut full) = self.current.take() {
+                full.flush()?;
+            }
+            let path = volume_path(&self.base, self.volume_cnt + 1);
+            self.current = Some(crate::create_output(&path, self.overwrite)?);
+            self.volume_cnt += 1;
+            self.current_len = 0;
+        }
+
+        let room = (self.volume_size - self.current_len).min(buf.len() as u64) as usize;
+        let written = self.current.as_mut().unwrap().write(&buf[..room])?;
+        self.current_len += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// If `path` is the first volume of a set, the base path the set was written to
+pub(crate) fn volume_set_base(path: &Path) -> Option<PathBuf> {
+    if path.extension()? == FIRST_VOLUME_EXTENSION {
+        Some(path.with_extension(""))
+    } else {
+        None
+    }
+}
+
+/// Deletes `base.001`, `base.002`, ... up to the first one that doesn't exist: the volumes
+/// [`read_volumes`] reads
+pub(crate) fn remove_volume_set(base: &Path) -> io::Result<()> {
+    for index in 1.. {
+        match std::fs::remove_file(volume_path(base, index)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound && index > 1 => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Reads and concatenates `base.001`, `base.002`, ... up to the first one that doesn't exist
+pub(crate) fn read_volumes(base: &Path) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+
+    for index in 1.. {
+        let mut volume = match File::open(volume_path(base, index)) {
+            Ok(volume) => volume,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && index > 1 => break,
+            Err(e) => return Err(e),
+        };
+        volume.read_to_end(&mut data)?;
+    }
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        for (s, size) in [
+            ("4096", 4096),
+            ("1b", 1),
+            ("64K", 64 << 10),
+            ("10MiB", 10 << 20),
+            ("1G", 1 << 30),
+        ] {
+            assert_eq!(parse_size(s).unwrap().get(), size, "{}", s);
+        }
+        for s in ["", "0", "K", "12X", "-1", "99999999999G"] {
+            assert!(parse_size(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn names_volumes_after_the_base() {
+        let first = volume_path(Path::new("out.bwt"), 1);
+        assert_eq!(first, Path::new("out.bwt.001"));
+        assert_eq!(volume_set_base(&first), Some(PathBuf::from("out.bwt")));
+        assert_eq!(volume_set_base(Path::new("out.bwt.002")), None);
+        assert_eq!(volume_set_base(Path::new("out.bwt")), None);
+    }
+}
@@ -0,0 +1,923 @@
+//! Runs the `rust-bwt` binary on files in a scratch directory
+
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A directory of its own for one test, removed afterwards
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new(test: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("rust-bwt-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    fn path(&self, file: &str) -> PathBuf {
+        self.0.join(file)
+    }
+
+    /// Writes `contents` to `file`, returning its path
+    fn write(&self, file: &str, contents: &[u8]) -> PathBuf {
+        let path = self.path(file);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn rust_bwt<I: IntoIterator<Item = S>, S: AsRef<OsStr>>(args: I) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rust-bwt"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+/// `len` bytes that don't compress, from a fixed seed
+fn noise(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    (0..len)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 56) as u8
+        })
+        .collect()
+}
+
+fn arg(path: &Path) -> &OsStr {
+    path.as_os_str()
+}
+
+#[test]
+fn info_reports_the_size_of_a_compressed_file() {
+    let scratch = Scratch::new("info-size");
+    for (name, contents) in [("text", &b"hello world"[..]), ("empty", b"")] {
+        let input = scratch.write(name, contents);
+        assert!(rust_bwt([OsStr::new("compress"), arg(&input)])
+            .status
+            .success());
+
+        let compressed = scratch.path(&format!("{}.bwt", name));
+        for header_only in [true, false] {
+            let mut args = vec![OsStr::new("info"), arg(&compressed)];
+            if header_only {
+                args.push(OsStr::new("--header-only"));
+            }
+            let output = rust_bwt(args);
+            assert!(output.status.success());
+            assert!(
+                stdout(&output).contains(&format!("Original size:   {} bytes", contents.len())),
+                "{}",
+                stdout(&output)
+            );
+        }
+    }
+}
+
+#[test]
+fn decompress_deletes_the_input_only_once_checked() {
+    let scratch = Scratch::new("delete-input");
+    let contents = b"banana bandana ".repeat(100);
+    let input = scratch.write("data", &contents);
+    let compressed = scratch.path("data.bwt");
+
+    // (compress flags, decompress flags, whether the compressed file is deleted)
+    for (compress_args, decompress_args, deleted) in [
+        (&[][..], &[][..], true),
+        (&[], &["--keep"], false),
+        (&[], &["--no-verify"], false),
+        (&[], &["--no-verify", "--rm"], true),
+        (&["--checksum", "false"], &[], false),
+        (&["--checksum", "false"], &["--rm"], true),
+    ] {
+        let output = rust_bwt(
+            [
+                OsStr::new("compress"),
+                OsStr::new("-k"),
+                OsStr::new("-f"),
+                arg(&input),
+            ]
+            .into_iter()
+            .chain(compress_args.iter().map(OsStr::new)),
+        );
+        assert!(output.status.success());
+        let output = rust_bwt(
+            [OsStr::new("decompress"), OsStr::new("-f"), arg(&compressed)]
+                .into_iter()
+                .chain(decompress_args.iter().map(OsStr::new)),
+        );
+        assert!(output.status.success());
+        assert_eq!(fs::read(&input).unwrap(), contents);
+        assert_eq!(
+            !compressed.exists(),
+            deleted,
+            "{:?} {:?}",
+            compress_args,
+            decompress_args
+        );
+    }
+
+    // A corrupted stream fails, and is kept
+    assert!(
+        rust_bwt([OsStr::new("compress"), OsStr::new("-k"), arg(&input)])
+            .status
+            .success()
+    );
+    let mut corrupted = fs::read(&compressed).unwrap();
+    let middle = corrupted.len() / 2;
+    corrupted[middle] ^= 0x55;
+    fs::write(&compressed, &corrupted).unwrap();
+    let output = rust_bwt([OsStr::new("decompress"), OsStr::new("-f"), arg(&compressed)]);
+    assert!(!output.status.success());
+    assert_eq!(fs::read(&compressed).unwrap(), corrupted);
+}
+
+#[test]
+fn compress_deletes_the_input_only_on_success() {
+    let scratch = Scratch::new("compress-delete-input");
+    let contents = b"banana bandana ".repeat(100);
+    let input = scratch.path("data");
+    let compressed = scratch.path("data.bwt");
+
+    // (compress flags, whether the input is deleted)
+    for (args, deleted) in [
+        (&[][..], true),
+        (&["--rm"], true),
+        (&["--verify"], true),
+        (&["--keep"], false),
+    ] {
+        fs::write(&input, &contents).unwrap();
+        let output = rust_bwt(
+            [OsStr::new("compress"), OsStr::new("-f"), arg(&input)]
+                .into_iter()
+                .chain(args.iter().map(OsStr::new)),
+        );
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert_eq!(!input.exists(), deleted, "{:?}", args);
+
+        let output = rust_bwt([OsStr::new("decompress"), OsStr::new("-c"), arg(&compressed)]);
+        assert_eq!(output.stdout, contents);
+    }
+
+    // Writing to stdout, or failing to create an output that's already there, keeps the input
+    fs::write(&input, &contents).unwrap();
+    let output = rust_bwt([OsStr::new("compress"), OsStr::new("-c"), arg(&input)]);
+    assert!(output.status.success());
+    assert_eq!(fs::read(&input).unwrap(), contents);
+
+    fs::write(&compressed, b"already here").unwrap();
+    let output = rust_bwt([OsStr::new("compress"), arg(&input)]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("already exists"),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(fs::read(&input).unwrap(), contents);
+    assert_eq!(fs::read(&compressed).unwrap(), b"already here");
+}
+
+/// Runs `rust-bwt` with files limited to 10 blocks (of 512 or 1024 bytes, depending on the shell)
+/// and `SIGXFSZ` ignored, so writing past that fails with `EFBIG` as if the disk were full
+#[cfg(unix)]
+fn rust_bwt_with_small_disk(args: &[&OsStr]) -> Output {
+    Command::new("sh")
+        .arg("-c")
+        .arg("trap '' XFSZ; ulimit -f 10; exec \"$0\" \"$@\"")
+        .arg(env!("CARGO_BIN_EXE_rust-bwt"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[cfg(unix)]
+#[test]
+fn failing_to_write_keeps_the_input() {
+    let scratch = Scratch::new("write-fails");
+    let contents = noise(100_000);
+    let input = scratch.write("data", &contents);
+    let compressed = scratch.path("data.bwt");
+
+    let output = rust_bwt_with_small_disk(&[OsStr::new("compress"), arg(&input)]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("too large"), "{}", stderr(&output));
+    assert_eq!(fs::read(&input).unwrap(), contents);
+    assert!(!compressed.exists());
+
+    assert!(
+        rust_bwt([OsStr::new("compress"), OsStr::new("-k"), arg(&input)])
+            .status
+            .success()
+    );
+    let whole = fs::read(&compressed).unwrap();
+    fs::remove_file(&input).unwrap();
+    let output = rust_bwt_with_small_disk(&[OsStr::new("decompress"), arg(&compressed)]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("too large"), "{}", stderr(&output));
+    assert_eq!(fs::read(&compressed).unwrap(), whole);
+    assert!(!input.exists());
+}
+
+/// Every file and directory under `dir`, relative to it, with the contents of the files
+fn read_tree(dir: &Path) -> BTreeMap<PathBuf, Option<Vec<u8>>> {
+    let mut tree = BTreeMap::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let name = PathBuf::from(path.file_name().unwrap());
+        if path.is_dir() {
+            tree.insert(name.clone(), None);
+            tree.extend(
+                read_tree(&path)
+                    .into_iter()
+                    .map(|(inner, contents)| (name.join(inner), contents)),
+            );
+        } else {
+            tree.insert(name, Some(fs::read(&path).unwrap()));
+        }
+    }
+    tree
+}
+
+#[test]
+fn archives_round_trip_a_directory_tree() {
+    let scratch = Scratch::new("archive");
+    let tree = scratch.path("tree");
+    fs::create_dir_all(tree.join("sub/deeper")).unwrap();
+    fs::create_dir_all(tree.join("empty-dir")).unwrap();
+    fs::write(tree.join("a.txt"), b"hello\n").unwrap();
+    fs::write(tree.join("empty"), b"").unwrap();
+    fs::write(tree.join("sub/b.bin"), noise(5000)).unwrap();
+    fs::write(tree.join("sub/deeper/c.txt"), b"banana ".repeat(100)).unwrap();
+    #[cfg(unix)]
+    fs::write(
+        tree.join(<OsStr as std::os::unix::ffi::OsStrExt>::from_bytes(
+            b"caf\xe9 \\",
+        )),
+        b"not UTF-8",
+    )
+    .unwrap();
+    let top = scratch.write("top.txt", b"top level");
+    let archive = scratch.path("backup.bwt");
+
+    let output = rust_bwt([
+        OsStr::new("compress"),
+        OsStr::new("--archive"),
+        arg(&archive),
+        OsStr::new("--recursive"),
+        arg(&tree),
+        arg(&top),
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(tree.exists() && top.exists());
+
+    let output = rust_bwt([OsStr::new("info"), arg(&archive)]);
+    assert!(output.status.success());
+    let info = stdout(&output);
+    let members = if cfg!(unix) { 10 } else { 9 };
+    assert!(info.contains(&format!("Archive:         {} members", members)));
+    for path in [
+        "tree/",
+        "tree/a.txt",
+        "tree/empty",
+        "tree/empty-dir/",
+        "tree/sub/deeper/c.txt",
+        "top.txt",
+    ] {
+        assert!(
+            info.lines()
+                .any(|line| line.ends_with(&format!("  {}", path))),
+            "{:?} in {}",
+            path,
+            info
+        );
+    }
+    assert!(
+        info.lines()
+            .any(|line| line.starts_with("           0 ") && line.ends_with("  tree/empty")),
+        "{}",
+        info
+    );
+    #[cfg(unix)]
+    assert!(info.contains("tree/caf\\xe9 \\\\\n"), "{}", info);
+
+    // Everything comes back, empty file and directory included
+    let extracted = scratch.path("extracted");
+    let output = rust_bwt([
+        OsStr::new("decompress"),
+        OsStr::new("--archive"),
+        arg(&archive),
+        OsStr::new("-o"),
+        arg(&extracted),
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(archive.exists());
+    assert_eq!(read_tree(&extracted.join("tree")), read_tree(&tree));
+    assert_eq!(fs::read(extracted.join("top.txt")).unwrap(), b"top level");
+
+    // Just one member, and for a directory, what's in it
+    for (member, expected) in [
+        ("tree/a.txt", vec!["tree", "tree/a.txt"]),
+        (
+            "tree/sub",
+            vec![
+                "tree",
+                "tree/sub",
+                "tree/sub/b.bin",
+                "tree/sub/deeper",
+                "tree/sub/deeper/c.txt",
+            ],
+        ),
+    ] {
+        let only = scratch.path(&format!("only-{}", member.replace('/', "-")));
+        let output = rust_bwt([
+            OsStr::new("decompress"),
+            OsStr::new("--archive"),
+            arg(&archive),
+            OsStr::new("--member"),
+            OsStr::new(member),
+            OsStr::new("-o"),
+            arg(&only),
+        ]);
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert_eq!(
+            read_tree(&only).into_keys().collect::<Vec<_>>(),
+            expected.into_iter().map(PathBuf::from).collect::<Vec<_>>()
+        );
+    }
+    let output = rust_bwt([
+        OsStr::new("decompress"),
+        OsStr::new("--archive"),
+        arg(&archive),
+        OsStr::new("--member"),
+        OsStr::new("tree/missing"),
+        OsStr::new("-o"),
+        arg(&scratch.path("missing")),
+    ]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("has no member tree/missing"));
+}
+
+/// An archive holding one file member, `contents`, stored under `path` as it is
+fn archive_with_member(path: &str, contents: &[u8], scratch: &Scratch) -> Vec<u8> {
+    let input = scratch.write("member", contents);
+    let output = rust_bwt([OsStr::new("compress"), OsStr::new("-c"), arg(&input)]);
+    assert!(output.status.success());
+    fs::remove_file(&input).unwrap();
+
+    let mut archive = b"RBWA\x01\x01".to_vec();
+    archive.extend((path.len() as u32).to_le_bytes());
+    archive.extend(path.as_bytes());
+    archive.extend((contents.len() as u64).to_le_bytes());
+    archive.extend((output.stdout.len() as u64).to_le_bytes());
+    archive.extend(output.stdout);
+    archive.push(0);
+    archive
+}
+
+#[test]
+fn extraction_stays_inside_the_destination() {
+    let scratch = Scratch::new("zip-slip");
+    let destination = scratch.path("out/inner");
+    fs::create_dir_all(&destination).unwrap();
+
+    for path in ["../evil", "inner/../../evil", "/tmp/evil"] {
+        let archive = scratch.write("evil.bwt", &archive_with_member(path, b"gotcha", &scratch));
+        let output = rust_bwt([
+            OsStr::new("decompress"),
+            OsStr::new("--archive"),
+            arg(&archive),
+            OsStr::new("-o"),
+            arg(&destination),
+        ]);
+        assert!(!output.status.success(), "{}", path);
+        assert!(
+            stderr(&output).contains("refusing to extract"),
+            "{}",
+            stderr(&output)
+        );
+        assert!(!scratch.path("out/evil").exists());
+        assert!(!scratch.path("evil").exists());
+        assert_eq!(fs::read_dir(&destination).unwrap().count(), 0, "{}", path);
+
+        // --to-dir-flat keeps just the name, inside the destination
+        let output = rust_bwt([
+            OsStr::new("decompress"),
+            OsStr::new("--archive"),
+            OsStr::new("--to-dir-flat"),
+            arg(&archive),
+            OsStr::new("-o"),
+            arg(&destination),
+        ]);
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert_eq!(fs::read(destination.join("evil")).unwrap(), b"gotcha");
+        assert_eq!(fs::read_dir(&destination).unwrap().count(), 1, "{}", path);
+        fs::remove_file(destination.join("evil")).unwrap();
+    }
+}
+
+#[test]
+fn resumed_compression_matches_an_uninterrupted_one() {
+    let scratch = Scratch::new("resume");
+    let contents = noise(4 << 20)
+        .iter()
+        .map(|b| b"etaoin shrdlu"[*b as usize % 13])
+        .collect::<Vec<_>>();
+    let input = scratch.write("data", &contents);
+    let compressed = scratch.path("data.bwt");
+    let checkpoint = scratch.path("data.bwt.checkpoint");
+    let settings = ["-k", "-b", "32768", "-t", "1"];
+
+    let output = rust_bwt(
+        [OsStr::new("compress"), OsStr::new("-c"), arg(&input)]
+            .into_iter()
+            .chain(settings.map(OsStr::new)),
+    );
+    assert!(output.status.success());
+    let uninterrupted = output.stdout;
+
+    // Killed outright once a few blocks are recorded, so nothing is cleaned up
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rust-bwt"))
+        .args([
+            OsStr::new("compress"),
+            OsStr::new("--checkpoint"),
+            arg(&input),
+        ])
+        .args(settings)
+        .spawn()
+        .unwrap();
+    let started = std::time::Instant::now();
+    while fs::metadata(&checkpoint).map_or(0, |m| m.len()) < 12 + 3 * 24 {
+        assert!(
+            child.try_wait().unwrap().is_none(),
+            "finished before it was killed"
+        );
+        assert!(started.elapsed().as_secs() < 60);
+        std::thread::sleep(std::time::Duration::from_millis(5));
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert!(fs::read(&compressed).unwrap().len() < uninterrupted.len());
+
+    let output = rust_bwt(
+        [
+            OsStr::new("-v"),
+            OsStr::new("compress"),
+            OsStr::new("--resume"),
+            arg(&input),
+        ]
+        .into_iter()
+        .chain(settings.map(OsStr::new)),
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        !stderr(&output).contains("resuming after 0 blocks")
+            && stderr(&output).contains("resuming after"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(fs::read(&compressed).unwrap() == uninterrupted);
+    assert!(!checkpoint.exists());
+
+    // Other settings don't carry on the same stream
+    fs::remove_file(&compressed).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rust-bwt"))
+        .args([
+            OsStr::new("compress"),
+            OsStr::new("--checkpoint"),
+            arg(&input),
+        ])
+        .args(settings)
+        .spawn()
+        .unwrap();
+    while fs::metadata(&checkpoint).map_or(0, |m| m.len()) < 12 + 24 {
+        assert!(
+            child.try_wait().unwrap().is_none(),
+            "finished before it was killed"
+        );
+        std::thread::sleep(std::time::Duration::from_millis(5));
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+    let output = rust_bwt([
+        OsStr::new("compress"),
+        OsStr::new("--resume"),
+        OsStr::new("-k"),
+        OsStr::new("-b"),
+        OsStr::new("65536"),
+        arg(&input),
+    ]);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("same settings"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn failed_decompression_leaves_no_output() {
+    let scratch = Scratch::new("failed-output");
+    let contents = b"mississippi ".repeat(1000);
+    let input = scratch.write("data", &contents);
+    assert!(rust_bwt([
+        OsStr::new("compress"),
+        OsStr::new("-b"),
+        OsStr::new("1000"),
+        arg(&input)
+    ])
+    .status
+    .success());
+    let compressed = fs::read(scratch.path("data.bwt")).unwrap();
+    let mut corrupted = compressed.clone();
+    let middle = corrupted.len() / 2;
+    corrupted[middle] ^= 0x55;
+    let corrupted = scratch.write("corrupted.bwt", &corrupted);
+    let whole = scratch.write("whole.bwt", &compressed);
+
+    let output = scratch.path("out");
+    for args in [
+        vec![OsStr::new("decompress"), arg(&corrupted)],
+        vec![
+            OsStr::new("decompress"),
+            OsStr::new("--max-output-size"),
+            OsStr::new("5000"),
+            arg(&whole),
+        ],
+    ] {
+        let result = rust_bwt(args.into_iter().chain([OsStr::new("-o"), arg(&output)]));
+        assert!(!result.status.success());
+        assert!(!output.exists());
+    }
+}
+
+#[test]
+fn failed_extraction_leaves_no_member() {
+    let scratch = Scratch::new("failed-extraction");
+    let contents = b"she sells sea shells ".repeat(500);
+    let member = scratch.write("member", &contents);
+    let archive = scratch.path("archive.bwt");
+    assert!(rust_bwt([
+        OsStr::new("compress"),
+        OsStr::new("--archive"),
+        arg(&archive),
+        arg(&member)
+    ])
+    .status
+    .success());
+
+    // Damage the member's only block, past the archive and member headers
+    let mut corrupted = fs::read(&archive).unwrap();
+    let middle = corrupted.len() / 2;
+    corrupted[middle] ^= 0x55;
+    fs::write(&archive, &corrupted).unwrap();
+
+    let destination = scratch.path("extracted");
+    let result = rust_bwt([
+        OsStr::new("decompress"),
+        OsStr::new("--archive"),
+        arg(&archive),
+        OsStr::new("-o"),
+        arg(&destination),
+    ]);
+    assert!(!result.status.success());
+    assert!(!destination.join("member").exists());
+}
+
+#[test]
+fn compress_warns_when_the_output_grows() {
+    let scratch = Scratch::new("growth-warning");
+    let random = scratch.write("random", &noise(64 * 1024));
+    let output = rust_bwt([OsStr::new("compress"), OsStr::new("-k"), arg(&random)]);
+    assert!(output.status.success());
+    assert!(stderr(&output).contains("is larger than the input"));
+    assert!(stderr(&output).contains("--store"), "{}", stderr(&output));
+
+    // Store mode has nothing better to suggest, but still decompresses to the input
+    let stored = scratch.path("stored");
+    let output = rust_bwt([
+        OsStr::new("compress"),
+        OsStr::new("-k"),
+        OsStr::new("--store"),
+        arg(&random),
+        OsStr::new("-o"),
+        arg(&stored),
+    ]);
+    assert!(output.status.success());
+    assert!(stderr(&output).contains("is larger than the input"));
+    assert!(!stderr(&output).contains("--store"), "{}", stderr(&output));
+    let output = rust_bwt([OsStr::new("decompress"), OsStr::new("-c"), arg(&stored)]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, fs::read(&random).unwrap());
+
+    // Nor is a small input, or a stream appended to a file
+    let small = scratch.write("small", &noise(100));
+    let output = rust_bwt([OsStr::new("compress"), arg(&small)]);
+    assert!(output.status.success());
+    assert_eq!(stderr(&output), "");
+    let output = rust_bwt([
+        OsStr::new("compress"),
+        OsStr::new("-k"),
+        OsStr::new("--append"),
+        arg(&random),
+        OsStr::new("-o"),
+        arg(&stored),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn split_volumes_round_trip() {
+    let scratch = Scratch::new("split-volumes");
+    let input = scratch.write("input", &noise(10_000));
+    let output = scratch.path("input.bwt");
+    assert!(rust_bwt([
+        OsStr::new("compress"),
+        OsStr::new("-k"),
+        OsStr::new("--split-size"),
+        OsStr::new("4KiB"),
+        arg(&input),
+        OsStr::new("-o"),
+        arg(&output),
+    ])
+    .status
+    .success());
+
+    let volumes = (1..=3)
+        .map(|i| scratch.path(&format!("input.bwt.{:03}", i)))
+        .collect::<Vec<_>>();
+    for volume in &volumes {
+        assert!(fs::metadata(volume).unwrap().len() <= 4096);
+    }
+    assert!(!scratch.path("input.bwt.004").exists());
+
+    let restored = scratch.path("restored");
+    assert!(rust_bwt([
+        OsStr::new("decompress"),
+        arg(&volumes[0]),
+        OsStr::new("-o"),
+        arg(&restored),
+    ])
+    .status
+    .success());
+    assert_eq!(fs::read(&restored).unwrap(), fs::read(&input).unwrap());
+}
+
+#[test]
+fn appended_streams_decompress_in_turn() {
+    let scratch = Scratch::new("append");
+    let log = scratch.path("log.bwt");
+    let parts = [&b"first part\n"[..], b"", b"third part\n"];
+    for (i, part) in parts.iter().enumerate() {
+        let input = scratch.write(&format!("part{}", i), part);
+        assert!(rust_bwt([
+            OsStr::new("compress"),
+            OsStr::new("--append"),
+            arg(&input),
+            OsStr::new("-o"),
+            arg(&log),
+        ])
+        .status
+        .success());
+    }
+
+    let output = rust_bwt([OsStr::new("decompress"), OsStr::new("-c"), arg(&log)]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, parts.concat());
+
+    // Appending to something that isn't a stream is refused
+    let text = scratch.write("text", b"not compressed");
+    let input = scratch.write("more", b"more");
+    let output = rust_bwt([
+        OsStr::new("compress"),
+        OsStr::new("--append"),
+        arg(&input),
+        OsStr::new("-o"),
+        arg(&text),
+    ]);
+    assert!(!output.status.success());
+    assert_eq!(fs::read(&text).unwrap(), b"not compressed");
+}
+
+#[test]
+fn transforms_like_bzip2() {
+    let scratch = Scratch::new("bzip2-transform");
+    let block = scratch.write("block.bin", b"abracadabra");
+    let transformed = scratch.path("block.bwt");
+    assert!(rust_bwt([
+        OsStr::new("transform"),
+        OsStr::new("--forward"),
+        OsStr::new("--bzip2"),
+        OsStr::new("-f"),
+        arg(&block),
+        OsStr::new("-o"),
+        arg(&transformed),
+    ])
+    .status
+    .success());
+
+    // bzip2 -9 puts "abracadabra" at row 2 of its sorted rotations
+    let mut expected = b"RBWR".to_vec();
+    expected.extend(2_u64.to_le_bytes());
+    expected.extend(b"rdarcaaaabb");
+    assert_eq!(fs::read(&transformed).unwrap(), expected);
+
+    let output = rust_bwt([
+        OsStr::new("transform"),
+        OsStr::new("--reverse"),
+        OsStr::new("--bzip2"),
+        OsStr::new("-f"),
+        arg(&transformed),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"abracadabra");
+}
+
+#[test]
+fn inverse_transform_round_trips_binary_data() {
+    let scratch = Scratch::new("inverse-transform");
+    let mut contents = b"\x00\x00banana\x00\xff\x80\xfe\xff\xff".to_vec();
+    contents.extend(noise(3000));
+    let input = scratch.write("input", &contents);
+    let transformed = scratch.path("input.raw");
+    let restored = scratch.path("restored");
+
+    assert!(rust_bwt([
+        OsStr::new("transform"),
+        OsStr::new("-i"),
+        arg(&input),
+        OsStr::new("-o"),
+        arg(&transformed),
+    ])
+    .status
+    .success());
+    let output = rust_bwt([
+        OsStr::new("inverse-transform"),
+        OsStr::new("-i"),
+        arg(&transformed),
+        OsStr::new("-o"),
+        arg(&restored),
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(fs::read(&restored).unwrap(), contents);
+}
+
+#[test]
+fn inverse_transform_rejects_inconsistent_input() {
+    let scratch = Scratch::new("inverse-transform-errors");
+    let raw = |sentinel_index: u64, bytes: &[u8]| {
+        let mut raw = sentinel_index.to_le_bytes().to_vec();
+        raw.extend(bytes);
+        raw
+    };
+
+    for (name, contents, message) in [
+        (
+            "out-of-range",
+            raw(7, b"annbaa"),
+            "sentinel index 7 is out of range for 6 transformed bytes",
+        ),
+        (
+            "short",
+            b"\x04\x00\x00".to_vec(),
+            "input is too short to hold a sentinel index",
+        ),
+        // Swapping two bytes of banana's transform closes the LF-mapping cycle after 5 steps
+        (
+            "short-cycle",
+            raw(4, b"nanbaa"),
+            "transformed data is inconsistent: LF-mapping walk failed at step 5",
+        ),
+    ] {
+        let input = scratch.write(name, &contents);
+        let restored = scratch.path(&format!("{}.out", name));
+        let output = rust_bwt([
+            OsStr::new("inverse-transform"),
+            OsStr::new("-i"),
+            arg(&input),
+            OsStr::new("-o"),
+            arg(&restored),
+        ]);
+        assert!(!output.status.success(), "{}", name);
+        assert!(stderr(&output).contains(message), "{}", stderr(&output));
+        assert!(!restored.exists(), "{}", name);
+    }
+}
+
+#[test]
+fn transform_shows_the_sentinel_and_overwrites_only_with_force() {
+    let output = rust_bwt(["transform", "-s", "banana", "--show-sentinel"]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "annb$aa\n");
+
+    let scratch = Scratch::new("transform-force");
+    let existing = scratch.write("existing", b"keep me");
+    let transform = |force: bool| {
+        let mut args = vec![
+            OsStr::new("transform"),
+            OsStr::new("-s"),
+            OsStr::new("banana"),
+            OsStr::new("-o"),
+            arg(&existing),
+        ];
+        if force {
+            args.push(OsStr::new("--force"));
+        }
+        rust_bwt(args)
+    };
+
+    let output = transform(false);
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("already exists"),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(fs::read(&existing).unwrap(), b"keep me");
+
+    assert!(transform(true).status.success());
+    let output = rust_bwt([
+        OsStr::new("inverse-transform"),
+        OsStr::new("-i"),
+        arg(&existing),
+        OsStr::new("-o"),
+        arg(&existing),
+        OsStr::new("-f"),
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(fs::read(&existing).unwrap(), b"banana");
+}
+
+#[test]
+fn compress_tunes_settings_for_dna() {
+    let scratch = Scratch::new("dna");
+    let dna = noise(20_000)
+        .iter()
+        .map(|b| b"ACGT"[*b as usize % 4])
+        .collect::<Vec<_>>()
+        .repeat(3);
+    let input = scratch.write("genome", &dna);
+    let text = scratch.write("text", &b"not a genome, just text\n".repeat(1000));
+
+    for (input, args, tuned) in [
+        (&input, &[][..], true),
+        (&input, &["--auto-tune", "false"], false),
+        (&text, &[], false),
+    ] {
+        let compressed = scratch.path("compressed");
+        let mut compress = vec![OsStr::new("compress"), OsStr::new("-v"), OsStr::new("-k")];
+        compress.extend(args.iter().map(OsStr::new));
+        compress.extend([
+            arg(input),
+            OsStr::new("-o"),
+            arg(&compressed),
+            OsStr::new("-f"),
+        ]);
+        let output = rust_bwt(compress);
+        assert!(output.status.success());
+        assert_eq!(
+            stderr(&output).contains(
+                "input has an alphabet of 4 bytes, like DNA; tuned to 4194304 byte blocks, mtf \
+                 false, rle0 false"
+            ),
+            tuned,
+            "{}",
+            stderr(&output)
+        );
+
+        let info = rust_bwt([
+            OsStr::new("info"),
+            OsStr::new("--header-only"),
+            arg(&compressed),
+        ]);
+        let stages = if tuned {
+            "bwt, hybrid-rle, huffman, crc32"
+        } else {
+            "bwt, mtf, rle0, huffman, crc32"
+        };
+        assert!(stdout(&info).contains(stages), "{}", stdout(&info));
+
+        let output = rust_bwt([OsStr::new("decompress"), OsStr::new("-c"), arg(&compressed)]);
+        assert!(output.status.success());
+        assert_eq!(output.stdout, fs::read(input).unwrap());
+    }
+}
@@ -0,0 +1,158 @@
+//! Streams more data through compression and decompression than their memory should ever hold,
+//! counting every allocation
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rust_bwt::{DecompressOptions, Options};
+
+/// The system allocator, keeping track of how many bytes are live and the most there have been
+struct Counting;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // SAFETY: passed on from the caller
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            let live = LIVE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(live, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        // SAFETY: passed on from the caller
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+const BLOCK_SIZE: usize = 1 << 20;
+
+/// More than either direction may hold at once
+const INPUT_LEN: u64 = 65 << 20;
+
+/// The most compression may allocate on top of what was live before it started, with one thread.
+/// Sorting a block's suffixes takes most of it: SA-IS works on a word per byte, and keeps a few
+/// arrays that long.
+const MAX_COMPRESS_LIVE: usize = 40 * BLOCK_SIZE;
+
+/// The same for decompression, which only keeps a block's LF-mapping and its buffers
+const MAX_DECOMPRESS_LIVE: usize = 16 * BLOCK_SIZE;
+
+/// `len` bytes of text-like data from a fixed seed, starting over every 3 MiB, generated as they're
+/// read so the input itself takes no memory
+struct Repeating {
+    state: u64,
+    position: u64,
+    len: u64,
+}
+
+impl Repeating {
+    const PERIOD: u64 = 3 << 20;
+    const SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+    fn new(len: u64) -> Self {
+        Self {
+            state: Self::SEED,
+            position: 0,
+            len,
+        }
+    }
+}
+
+impl Read for Repeating {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = buf.len().min((self.len - self.position) as usize);
+        for b in &mut buf[..n] {
+            if self.position.is_multiple_of(Self::PERIOD) {
+                self.state = Self::SEED;
+            }
+            self.state = self
+                .state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            *b = b"etaoin shrdlu"[(self.state >> 60) as usize % 13];
+            self.position += 1;
+        }
+        Ok(n)
+    }
+}
+
+/// Checks what's written against what [`Repeating`] reads, holding none of it
+struct Compare(Repeating);
+
+impl Write for Compare {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut expected = [0; 4096];
+        for chunk in buf.chunks(expected.len()) {
+            let expected = &mut expected[..chunk.len()];
+            self.0.read_exact(expected)?;
+            assert!(
+                chunk == expected,
+                "output differs near byte {}",
+                self.0.position
+            );
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn streaming_memory_is_bounded_by_the_block_size() {
+    let compressed = std::env::temp_dir().join(format!("rust-bwt-memory-{}", std::process::id()));
+    let options = Options {
+        block_size: BLOCK_SIZE,
+        threads: 1,
+        ..Options::default()
+    };
+
+    let before = LIVE.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+    rust_bwt::compress_stream_with(
+        Repeating::new(INPUT_LEN),
+        &options,
+        BufWriter::new(File::create(&compressed).unwrap()),
+    )
+    .unwrap();
+    let compress_peak = PEAK.load(Ordering::Relaxed) - before;
+
+    let before = LIVE.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+    let mut output = Compare(Repeating::new(INPUT_LEN));
+    rust_bwt::decompress_stream_with(
+        BufReader::new(File::open(&compressed).unwrap()),
+        &DecompressOptions {
+            threads: 1,
+            ..DecompressOptions::default()
+        },
+        &mut output,
+    )
+    .unwrap();
+    let decompress_peak = PEAK.load(Ordering::Relaxed) - before;
+    fs::remove_file(&compressed).unwrap();
+
+    assert_eq!(output.0.position, INPUT_LEN);
+    assert!(
+        compress_peak <= MAX_COMPRESS_LIVE,
+        "compression peaked at {} bytes",
+        compress_peak
+    );
+    assert!(
+        decompress_peak <= MAX_DECOMPRESS_LIVE,
+        "decompression peaked at {} bytes",
+        decompress_peak
+    );
+}